@@ -0,0 +1,393 @@
+//! A local tile server for development and tests, serving a directory of glb and png tiles
+//! over HTTP. Latency, errors and missing tiles can be added to test the loading, retries
+//! and the cache without the live servers.
+//!
+//! ```text
+//! cargo run --example mock_tiles -- dir=tiles port=8080 latency=200 errors=0.1
+//! cargo run -- tiles=http://localhost:8080/ raster=http://localhost:8080/raster/ cache=none
+//! ```
+//!
+//! The URL path is the file path in the directory: 3D tiles are `lod1/{z}/{x}/{y}.glb`,
+//! raster tiles with `raster=http://localhost:8080/raster/` are `raster/{z}/{x}/{y}.png`.
+//!
+//! Its tests run with `cargo test --example mock_tiles`.
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    ops::RangeInclusive,
+    path::{Component, Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+const HELP: &str = "\
+Usage: mock_tiles [key=value]...
+
+  dir=<dir>             directory of the tiles (default tiles)
+  port=<port>           (default 8080)
+  latency=<ms>          delay of every response (default 0)
+  jitter=<ms>           random additional delay up to this (default 0)
+  errors=<0..1>         share of requests answered with HTTP 503 (default 0)
+  throttle=<0..1>       share of requests answered with HTTP 429 and Retry-After (default 0)
+  missing=<regions>     tiles answered with HTTP 404, `z/x/y` with ranges and `*`, separated
+                        by commas: `missing=16/34800-34900/*,17/*/*`
+";
+
+/// Tile coordinates, `*` is any
+#[derive(Clone)]
+struct Region([Option<RangeInclusive<u32>>; 3]);
+
+impl Region {
+    fn parse(s: &str) -> Option<Self> {
+        let mut ranges = s.split('/').map(|part| match part {
+            "*" => Some(None),
+            _ => {
+                let (start, end) = part.split_once('-').unwrap_or((part, part));
+                Some(Some(start.parse().ok()?..=end.parse().ok()?))
+            }
+        });
+        let region = Self([ranges.next()??, ranges.next()??, ranges.next()??]);
+        ranges.next().is_none().then_some(region)
+    }
+
+    fn contains(&self, tile: [u32; 3]) -> bool {
+        self.0
+            .iter()
+            .zip(tile)
+            .all(|(range, n)| range.as_ref().is_none_or(|range| range.contains(&n)))
+    }
+}
+
+struct Config {
+    dir: PathBuf,
+    port: u16,
+    latency: u64,
+    jitter: u64,
+    errors: f64,
+    throttle: f64,
+    missing: Vec<Region>,
+}
+
+impl Config {
+    /// The configuration of `key=value` arguments, or the help text
+    fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut config = Self {
+            dir: "tiles".into(),
+            port: 8080,
+            latency: 0,
+            jitter: 0,
+            errors: 0.0,
+            throttle: 0.0,
+            missing: vec![],
+        };
+        for arg in args {
+            let (k, v) = arg.split_once('=').ok_or(HELP)?;
+            let invalid = || format!("invalid value `{v}` for `{k}`");
+            match k {
+                "dir" => config.dir = v.into(),
+                "port" => config.port = v.parse().map_err(|_| invalid())?,
+                "latency" => config.latency = v.parse().map_err(|_| invalid())?,
+                "jitter" => config.jitter = v.parse().map_err(|_| invalid())?,
+                "errors" => config.errors = v.parse().map_err(|_| invalid())?,
+                "throttle" => config.throttle = v.parse().map_err(|_| invalid())?,
+                "missing" => {
+                    for region in v.split(',') {
+                        config
+                            .missing
+                            .push(Region::parse(region).ok_or_else(invalid)?);
+                    }
+                }
+                _ => return Err(HELP.into()),
+            }
+        }
+        Ok(config)
+    }
+}
+
+/// A small xorshift generator, good enough for the errors
+struct Random(AtomicU64);
+
+impl Random {
+    /// A number in `0..1`
+    fn next(&self) -> f64 {
+        let mut x = self.0.load(Ordering::Relaxed);
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.0.store(x, Ordering::Relaxed);
+        (x >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+fn main() {
+    let config = match Config::parse(std::env::args().skip(1)) {
+        Ok(config) => Arc::new(config),
+        Err(err) => {
+            eprintln!("{err}");
+            std::process::exit(1);
+        }
+    };
+    let seed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos() as u64;
+    let random = Arc::new(Random(AtomicU64::new(seed | 1)));
+    let listener = TcpListener::bind(("127.0.0.1", config.port)).expect("port in use");
+    println!(
+        "serving {} on http://localhost:{}/",
+        config.dir.display(),
+        config.port
+    );
+    for stream in listener.incoming().flatten() {
+        let config = config.clone();
+        let random = random.clone();
+        std::thread::spawn(move || {
+            if let Err(err) = serve(&config, &random, stream) {
+                eprintln!("{err}");
+            }
+        });
+    }
+}
+
+/// The tile coordinates: the last three path segments, without the extension
+fn tile_of(path: &str) -> Option<[u32; 3]> {
+    let mut parts = path.trim_end_matches('/').rsplit('/');
+    let y = parts.next()?.split('.').next()?.parse().ok()?;
+    let x = parts.next()?.parse().ok()?;
+    let z = parts.next()?.parse().ok()?;
+    Some([z, x, y])
+}
+
+/// The file of a URL path, `None` if it leaves the directory
+fn file_of(dir: &Path, path: &str) -> Option<PathBuf> {
+    let path = Path::new(path.trim_start_matches('/'));
+    path.components()
+        .all(|component| matches!(component, Component::Normal(_)))
+        .then(|| dir.join(path))
+}
+
+fn serve(config: &Config, random: &Random, stream: TcpStream) -> std::io::Result<()> {
+    let mut reader = BufReader::new(&stream);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    let mut if_none_match = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("if-none-match") {
+                if_none_match = Some(value.trim().to_owned());
+            }
+        }
+    }
+    let path = request.split_whitespace().nth(1).unwrap_or("/");
+    let path = path.split('?').next().unwrap_or(path);
+
+    let jitter = (random.next() * config.jitter as f64) as u64;
+    std::thread::sleep(Duration::from_millis(config.latency + jitter));
+
+    let (status, headers, body) = respond(config, random, path, if_none_match.as_deref());
+    println!("{status} {path}");
+    let mut stream = stream;
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Length: {}\r\nConnection: close\r\n",
+        body.len()
+    )?;
+    for (name, value) in headers {
+        write!(stream, "{name}: {value}\r\n")?;
+    }
+    stream.write_all(b"\r\n")?;
+    stream.write_all(&body)
+}
+
+fn respond(
+    config: &Config,
+    random: &Random,
+    path: &str,
+    if_none_match: Option<&str>,
+) -> (&'static str, Vec<(&'static str, String)>, Vec<u8>) {
+    if random.next() < config.errors {
+        return ("503 Service Unavailable", vec![], vec![]);
+    }
+    if random.next() < config.throttle {
+        let retry_after = vec![("Retry-After", "2".into())];
+        return ("429 Too Many Requests", retry_after, vec![]);
+    }
+    let missing =
+        tile_of(path).is_some_and(|tile| config.missing.iter().any(|region| region.contains(tile)));
+    let file = file_of(&config.dir, path).filter(|_| !missing);
+    let Some((body, modified)) = file.and_then(|file| {
+        let modified = file.metadata().ok()?.modified().ok()?;
+        Some((std::fs::read(file).ok()?, modified))
+    }) else {
+        return ("404 Not Found", vec![], vec![]);
+    };
+
+    let modified = modified.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let etag = format!("\"{:x}-{:x}\"", modified.as_secs(), body.len());
+    let mut headers = vec![("ETag", etag.clone())];
+    if if_none_match == Some(etag.as_str()) {
+        return ("304 Not Modified", headers, vec![]);
+    }
+    let content_type = match path.rsplit('.').next() {
+        Some("glb") => "model/gltf-binary",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        _ => "application/octet-stream",
+    };
+    headers.push(("Content-Type", content_type.into()));
+    ("200 OK", headers, body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    fn config(args: &[&str]) -> Result<Config, String> {
+        Config::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    /// A directory with the tile `lod1/15/17388/11332.glb`
+    fn tiles(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("mock_tiles_{name}_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("lod1/15/17388")).unwrap();
+        std::fs::write(dir.join("lod1/15/17388/11332.glb"), b"glTF").unwrap();
+        dir
+    }
+
+    #[test]
+    fn region() {
+        let region = Region::parse("16/34800-34900/*").unwrap();
+        assert!(region.contains([16, 34800, 0]));
+        assert!(region.contains([16, 34900, 99999]));
+        assert!(!region.contains([16, 34901, 0]));
+        assert!(!region.contains([17, 34850, 0]));
+        assert!(Region::parse("*/*/*").unwrap().contains([0, 0, 0]));
+
+        for invalid in ["16/1", "16/1/2/3", "16/a/*", "16/1-/*", "", "16/2-1-0/*"] {
+            assert!(Region::parse(invalid).is_none(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn parse_config() {
+        let config = config(&[
+            "dir=/srv/tiles",
+            "port=9000",
+            "latency=200",
+            "errors=0.1",
+            "missing=16/34800-34900/*,17/*/*",
+        ])
+        .unwrap();
+        assert_eq!(config.dir, PathBuf::from("/srv/tiles"));
+        assert_eq!((config.port, config.latency, config.jitter), (9000, 200, 0));
+        assert_eq!((config.errors, config.throttle), (0.1, 0.0));
+        assert_eq!(config.missing.len(), 2);
+        assert!(config.missing[1].contains([17, 1, 2]));
+
+        assert_eq!(self::config(&["port"]).err().as_deref(), Some(HELP));
+        assert_eq!(self::config(&["size=1"]).err().as_deref(), Some(HELP));
+        assert_eq!(
+            self::config(&["port=http"]).err().as_deref(),
+            Some("invalid value `http` for `port`")
+        );
+        assert_eq!(
+            self::config(&["missing=16/1/2,17"]).err().as_deref(),
+            Some("invalid value `16/1/2,17` for `missing`")
+        );
+    }
+
+    #[test]
+    fn paths() {
+        assert_eq!(
+            tile_of("/lod1/15/17388/11332.glb"),
+            Some([15, 17388, 11332])
+        );
+        assert_eq!(tile_of("/raster/3/4/5.png"), Some([3, 4, 5]));
+        assert_eq!(tile_of("/lod1/15/17388/"), None);
+        assert_eq!(tile_of("/style.json"), None);
+
+        let dir = Path::new("tiles");
+        assert_eq!(
+            file_of(dir, "/lod1/15/1/2.glb"),
+            Some(dir.join("lod1/15/1/2.glb"))
+        );
+        assert_eq!(file_of(dir, "/../secret"), None);
+        assert_eq!(file_of(dir, "/lod1/../../secret"), None);
+        // Stays in the directory
+        assert_eq!(file_of(dir, "//etc/passwd"), Some(dir.join("etc/passwd")));
+    }
+
+    #[test]
+    fn responses() {
+        let dir = tiles("responses");
+        let tile = "/lod1/15/17388/11332.glb";
+        let mut config = config(&["missing=16/*/*"]).unwrap();
+        config.dir = dir.clone();
+        let random = Random(AtomicU64::new(1));
+
+        let (status, headers, body) = respond(&config, &random, tile, None);
+        assert_eq!((status, body.as_slice()), ("200 OK", b"glTF".as_slice()));
+        assert!(headers.contains(&("Content-Type", "model/gltf-binary".into())));
+        let (_, etag) = headers.iter().find(|(name, _)| *name == "ETag").unwrap();
+
+        // The cache revalidates
+        let (status, _, body) = respond(&config, &random, tile, Some(etag));
+        assert_eq!((status, body.len()), ("304 Not Modified", 0));
+        let (status, _, _) = respond(&config, &random, tile, Some("\"other\""));
+        assert_eq!(status, "200 OK");
+
+        let (status, _, _) = respond(&config, &random, "/lod1/15/17388/11333.glb", None);
+        assert_eq!(status, "404 Not Found");
+        std::fs::create_dir_all(dir.join("lod1/16/1")).unwrap();
+        std::fs::write(dir.join("lod1/16/1/2.glb"), b"glTF").unwrap();
+        let (status, _, _) = respond(&config, &random, "/lod1/16/1/2.glb", None);
+        assert_eq!(status, "404 Not Found");
+
+        config.errors = 1.0;
+        let (status, _, _) = respond(&config, &random, tile, None);
+        assert_eq!(status, "503 Service Unavailable");
+        config.errors = 0.0;
+        config.throttle = 1.0;
+        let (status, headers, _) = respond(&config, &random, tile, None);
+        assert_eq!(status, "429 Too Many Requests");
+        assert_eq!(headers, [("Retry-After", "2".to_string())]);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn http() {
+        let dir = tiles("http");
+        let mut config = config(&[]).unwrap();
+        config.dir = dir.clone();
+        let random = Random(AtomicU64::new(1));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let client = std::thread::spawn(move || {
+            let mut stream = TcpStream::connect(address).unwrap();
+            let request = "GET /lod1/15/17388/11332.glb?v=1 HTTP/1.1\r\nHost: localhost\r\n\r\n";
+            stream.write_all(request.as_bytes()).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        });
+        let (stream, _) = listener.accept().unwrap();
+        serve(&config, &random, stream).unwrap();
+        let response = client.join().unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+        assert!(response.contains("Content-Length: 4\r\n"), "{response}");
+        assert!(response.ends_with("\r\n\r\nglTF"), "{response}");
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,178 @@
+use crate::tile::TileCoord;
+use glam::{DVec3, Vec2};
+use globe_rs::{CartesianPoint, GeographicPoint};
+use serde::{Deserialize, Serialize};
+use std::f32::consts::PI;
+
+/**
+ * Geo-coordinates on the (OSM-) world map (GPS position)
+ *
+ * Does also calculations: tile_Name, the coordinates aer located in
+ * and distance in meters from the tiles corner
+ */
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct GeoCoord {
+    pub lat: f32,
+    pub lon: f32,
+}
+
+impl GeoCoord {
+    /**
+     * Convert GPS coordinates to tile coordinates.
+     * We use the OSM naming for tiles:
+     * https://wiki.openstreetmap.org/wiki/Slippy_map_tilenames.
+     * x and y relate to a lonitude and latitude-position on Earth.
+     * In OSM he two values are only used as part of the filename of a tile.
+     * even if it is an x/y coordinate in numbers.
+     * @param zoom  Zoom level of the OSM tile-name(x/y) system
+     * @return coordinate in tile coordinates
+     */
+    pub fn to_tile_coordinates(self, zoom: u8) -> TileCoord {
+        let pow_zoom = 2_u32.pow(zoom.into()) as f32;
+
+        //if self.lat > OSM_LAT_LIMIT || self.lat < -OSM_LAT_LIMIT {
+        //    panic!("self.lat -> {self:?} ");
+        //}
+        //if self.lon > 360. || self.lon < 0.0 {
+        //    panic!("self.lon -> {self:?} ");
+        //}
+
+        // Longitude, (Längengrad) West/East "index"
+        let mut x = ((self.lon + 180.) / 360. * pow_zoom).rem_euclid(pow_zoom);
+        // y: Latitude, (Breitengrad) Nort/South "index"
+        let mut y =
+            (1. - (self.lat.to_radians().tan() + 1. / self.lat.to_radians().cos()).ln() / PI) / 2.
+                * pow_zoom;
+        // The Nort/South y tile name part is not linear, the tiles gets stretched to the poles
+        // to compensate the stretching if the stretching of the West/East projection
+
+        // If out of bounds, wrap around the globe.
+        // Note: only works if the gps coordinates weren't out of bounds enough to wrap around the planet beyond the equator.
+        // Todo: Whlat? Skip the pole and go the other side?!
+        // Funny idea but when is it usefull? Of objects are placed there? No! Ignore them.
+        // And for calculing tile_size, it is a disater!
+        // Limit to pow_zoom or 0? Ok for plus tile_size äää
+        if y > pow_zoom {
+            y = pow_zoom - y.rem_euclid(pow_zoom);
+            x = (x + pow_zoom / 2.0).rem_euclid(pow_zoom);
+        } else if y.is_sign_negative() {
+            y = y.abs();
+            x = (x + pow_zoom / 2.0).rem_euclid(pow_zoom);
+        }
+        if x > pow_zoom || y > pow_zoom {
+            panic!("{self:?} @ zoom {zoom} -> {x},{y}");
+        }
+        TileCoord::new(Vec2 { x, y }, zoom)
+    }
+
+    /// Compute the position on the surface, relative to the planet center.
+    pub fn to_cartesian(self) -> DVec3 {
+        let geo = GeographicPoint::new(
+            (self.lon as f64).to_radians(),
+            (self.lat as f64).to_radians(),
+            EARTH_RADIUS as f64,
+        );
+        let cart = CartesianPoint::from_geographic(&geo);
+        DVec3::new(-cart.x(), -cart.y(), cart.z())
+    }
+
+    pub fn from_cartesian(pos: DVec3) -> Self {
+        let cart = CartesianPoint::new(-pos.x, -pos.y, pos.z);
+        let geo = GeographicPoint::from_cartesian(&cart);
+        GeoCoord {
+            lat: geo.latitude().to_degrees() as f32,
+            lon: geo.longitude().to_degrees() as f32,
+        }
+    }
+
+    /// Tile width and height in meters (are equal)
+    pub fn tile_size(self, zoom: u8) -> f32 {
+        let coord = self.to_tile_coordinates(zoom);
+        let pos = self.to_cartesian();
+        coord.right().to_geo_coord().to_cartesian().distance(pos) as f32
+    }
+
+    /// Add a displacement
+    pub fn add_move(&mut self, moved: GeoDir) {
+        self.lat += moved.y;
+        self.lon += moved.x;
+    }
+}
+
+pub type GeoDir = Vec2;
+
+pub trait GeoDirTrait {
+    fn forward(dir: f32) -> Vec2;
+    fn right(dir: f32) -> Vec2;
+}
+
+impl GeoDirTrait for GeoDir {
+    fn forward(dir: f32) -> Vec2 {
+        let (sin, cos) = dir.sin_cos();
+        Vec2 { x: -sin, y: cos }
+    }
+
+    fn right(dir: f32) -> Vec2 {
+        let (sin, cos) = dir.sin_cos();
+        Vec2 { x: cos, y: sin }
+    }
+}
+
+pub const CLOUDS_HEIGHT: f32 = 100_000.0;
+pub const EARTH_RADIUS: f32 = 6_378_000.;
+pub const MOON_RADIUS: f32 = 01_737_400.;
+pub const MOON_ORBIT: f32 = 384_400_000. / 30.; //tttest
+pub const SHOW_SIZE: f32 = 100_000.;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tile::TileIndex;
+    use glam::UVec2;
+
+    const MUNICH: GeoCoord = GeoCoord {
+        lat: 48.1408,
+        lon: 11.5577,
+    };
+
+    #[test]
+    fn tile_coordinates() {
+        let coord = MUNICH.to_tile_coordinates(15);
+        assert_eq!(coord.zoom(), 15);
+        assert_eq!(
+            coord.as_tile_index(),
+            TileIndex::new(UVec2::new(17436, 11371), 15)
+        );
+
+        let back = coord.to_geo_coord();
+        assert!((back.lat - MUNICH.lat).abs() < 1e-4, "{back:?}");
+        assert!((back.lon - MUNICH.lon).abs() < 1e-4, "{back:?}");
+
+        let center = GeoCoord { lat: 0., lon: 0. }.to_tile_coordinates(1);
+        assert_eq!(*center, Vec2::new(1., 1.));
+        assert_eq!(center.to_geo_coord(), GeoCoord { lat: 0., lon: 0. });
+        // The upper left corner of the map
+        let corner = TileCoord::new(Vec2::ZERO, 1).to_geo_coord();
+        assert_eq!(corner.lon, -180.);
+        assert!((corner.lat - 85.0511).abs() < 1e-3, "{corner:?}");
+    }
+
+    #[test]
+    fn cartesian() {
+        let back = GeoCoord::from_cartesian(MUNICH.to_cartesian());
+        assert!((back.lat - MUNICH.lat).abs() < 1e-5, "{back:?}");
+        assert!((back.lon - MUNICH.lon).abs() < 1e-5, "{back:?}");
+        assert!((MUNICH.to_cartesian().length() - EARTH_RADIUS as f64).abs() < 1e-3);
+    }
+
+    #[test]
+    fn tile_size() {
+        // The circumference divided by the tiles, shrinking with the latitude
+        let equator = GeoCoord { lat: 0., lon: 0. }.tile_size(15);
+        assert!((equator - 1222.97).abs() < 0.1, "{equator}");
+        let munich = MUNICH.tile_size(15);
+        let expected = equator * MUNICH.lat.to_radians().cos();
+        assert!((munich - expected).abs() < 1., "{munich} != {expected}");
+    }
+}
@@ -0,0 +1,24 @@
+//! The renderer independent core of OSMeta: geo coordinates, OSM tile coordinates
+//! and the decision which tiles to show and to load next.
+//!
+//! There is no Bevy in here. Positions are plain `glam` vectors relative to the planet center,
+//! so other engines (or tests and tools) can use the same tile math.
+//!
+//! ```ignore
+//! let mut schedule = TileSchedule::default();
+//! let origin = ViewOrigin::new(GeoCoord { lat: 48.1408, lon: 11.5577 }.to_cartesian(), 2000.);
+//! while let Some(tile) = schedule.next_to_load(&origin) {
+//!     schedule.insert(tile); // and load it
+//! }
+//! ```
+
+pub mod geocoord;
+pub mod schedule;
+pub mod tile;
+
+pub use geocoord::GeoCoord;
+pub use schedule::{adapt_view_distance, TileSchedule, ViewOrigin, TILE_ZOOM};
+pub use tile::{TileCoord, TileIndex};
+
+/// The vector types of the API
+pub use glam;
@@ -0,0 +1,220 @@
+//! Which tiles are visible and which tile to load next, around a viewer position.
+
+use glam::{DVec3, IVec2};
+use std::collections::HashSet;
+
+use crate::geocoord::{GeoCoord, EARTH_RADIUS};
+use crate::tile::TileIndex;
+
+pub const TILE_ZOOM: u8 = 15;
+
+fn phytagoras(a: f32, b: f32) -> f32 {
+    (a * a + b * b).sqrt()
+}
+
+fn tile_faraway(
+    tile: &TileIndex,
+    origin: TileIndex,
+    elevation: f32,
+    radius: f32,
+    tile_size: f32,
+) -> bool {
+    // let distance = (tile.distance_squared(origin) as f32).sqrt() * tile_size;
+    // phytagoras(distance, elevation) > radius
+    let distance =
+        tile.distance_squared(origin) as f32 * elevation / tile_size * elevation / tile_size;
+    distance > radius / tile_size * radius / tile_size
+}
+
+/// The viewer, the tiles are shown and loaded around
+#[derive(Debug, Clone, Copy)]
+pub struct ViewOrigin {
+    /// The tile below the viewer
+    pub tile: TileIndex,
+    /// Tiles up to this distance in meters are shown
+    pub radius: f32,
+    /// Height of the viewer above the planet surface
+    pub elevation: f32,
+}
+
+impl ViewOrigin {
+    /// `pos` is the position of the viewer relative to the planet center
+    pub fn new(pos: DVec3, view_distance: f32) -> Self {
+        let origin = GeoCoord::from_cartesian(pos);
+        let tile_size = origin.tile_size(TILE_ZOOM);
+        let radius = view_distance + tile_size + 0.5;
+        let tile = origin.to_tile_coordinates(TILE_ZOOM).as_tile_index();
+        Self {
+            tile,
+            radius,
+            elevation: pos.length() as f32 - EARTH_RADIUS,
+        }
+    }
+
+    /// Tiles beyond the view distance should be hidden
+    pub fn is_visible(&self, tile: TileIndex) -> bool {
+        // FIXME: use tile zoom level to increase view-distance for lower zoom tiles.
+        let tile_size = tile.as_coord().to_geo_coord().tile_size(TILE_ZOOM);
+        !tile_faraway(&tile, self.tile, self.elevation, self.radius, tile_size)
+    }
+}
+
+/// All tiles loaded or in the process of loading
+#[derive(Debug, Default, Clone)]
+pub struct TileSchedule {
+    tiles: HashSet<TileIndex>,
+}
+
+impl TileSchedule {
+    /// Number of tiles loaded or in the process of loading
+    pub fn len(&self) -> usize {
+        self.tiles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tiles.is_empty()
+    }
+
+    pub fn contains(&self, tile: TileIndex) -> bool {
+        self.tiles.contains(&tile)
+    }
+
+    /// Mark a tile as loading. Returns `false` if it was already loaded or loading.
+    pub fn insert(&mut self, tile: TileIndex) -> bool {
+        self.tiles.insert(tile)
+    }
+
+    /// The not yet loaded tile within the view distance, which is most important to load
+    pub fn next_to_load(&self, origin: &ViewOrigin) -> Option<TileIndex> {
+        let mut best_score = f32::INFINITY;
+        let mut best_pos = None;
+        for (pos, offset) in in_view_distance(origin) {
+            let score = self.score(pos, offset);
+            if score < best_score {
+                best_pos = Some(pos);
+                best_score = score;
+            }
+        }
+        best_pos
+    }
+
+    /// Number of tiles within the view distance that are not yet loaded or loading
+    pub fn queued(&self, origin: &ViewOrigin) -> usize {
+        in_view_distance(origin)
+            .filter(|(pos, _)| !self.tiles.contains(pos))
+            .count()
+    }
+
+    /// Takes an offset to the player position and returns a score for how important
+    /// to load it is. Lower values are better.
+    // FIXME(#18): use a smarter algorithm
+    pub fn score(&self, pos: TileIndex, offset: IVec2) -> f32 {
+        if self.tiles.contains(&pos) {
+            return f32::INFINITY;
+        }
+
+        offset.as_vec2().length_squared()
+    }
+}
+
+/// The tiles within the view distance, with their offset to the tile of the viewer
+fn in_view_distance(origin: &ViewOrigin) -> impl Iterator<Item = (TileIndex, IVec2)> + '_ {
+    let tile_size = origin.tile.as_coord().to_geo_coord().tile_size(TILE_ZOOM);
+    let dist_max = (origin.radius / tile_size).ceil() as i32;
+    (-dist_max..=dist_max)
+        .flat_map(move |x_i| (-dist_max..=dist_max).map(move |y_i| IVec2::new(x_i, y_i)))
+        .filter(move |offset| {
+            let distance = (offset.length_squared() as f32).sqrt() * tile_size;
+            phytagoras(distance, origin.elevation) <= origin.radius
+        })
+        .map(move |offset| (origin.tile.offset(offset), offset))
+}
+
+/// Shrink the view distance if the frames per second are low, grow it if they are high
+pub fn adapt_view_distance(view_distance: f32, fps: f64) -> f32 {
+    let view_distance = if fps < 40.0 {
+        view_distance * 0.99
+    } else if fps > 59.5 {
+        view_distance * 1.01
+    } else {
+        view_distance
+    };
+    view_distance.clamp(2000.0, 10000.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MUNICH: GeoCoord = GeoCoord {
+        lat: 48.1408,
+        lon: 11.5577,
+    };
+
+    fn origin(elevation: f64) -> ViewOrigin {
+        let ground = MUNICH.to_cartesian();
+        let pos = ground * (ground.length() + elevation) / ground.length();
+        ViewOrigin::new(pos, 2000.)
+    }
+
+    #[test]
+    fn view_origin() {
+        let origin = origin(1000.);
+        assert_eq!(
+            origin.tile,
+            MUNICH.to_tile_coordinates(TILE_ZOOM).as_tile_index()
+        );
+        assert!((origin.elevation - 1000.).abs() < 0.5, "{origin:?}");
+        // The view distance and a tile
+        let tile_size = MUNICH.tile_size(TILE_ZOOM);
+        assert!(
+            (origin.radius - (2000. + tile_size + 0.5)).abs() < 0.1,
+            "{origin:?}"
+        );
+    }
+
+    #[test]
+    fn is_visible() {
+        let origin = origin(1000.);
+        assert!(origin.is_visible(origin.tile));
+        assert!(origin.is_visible(origin.tile.offset(IVec2::new(1, -1))));
+        assert!(!origin.is_visible(origin.tile.offset(IVec2::new(10, 0))));
+        // The other side of the planet
+        let far = origin.tile.offset(IVec2::splat(1 << (TILE_ZOOM - 1)));
+        assert!(!origin.is_visible(far));
+    }
+
+    #[test]
+    fn next_to_load() {
+        let origin = origin(1.4);
+        let mut schedule = TileSchedule::default();
+        let queued = schedule.queued(&origin);
+        assert!(queued > 1);
+
+        // The tile below the viewer first, then its neighbours
+        assert_eq!(schedule.next_to_load(&origin), Some(origin.tile));
+        assert!(schedule.insert(origin.tile));
+        assert!(!schedule.insert(origin.tile));
+        assert_eq!(schedule.queued(&origin), queued - 1);
+        let next = schedule.next_to_load(&origin).unwrap();
+        assert_eq!(next.distance_squared(origin.tile), 1);
+
+        while let Some(tile) = schedule.next_to_load(&origin) {
+            assert!(schedule.insert(tile));
+        }
+        assert_eq!(schedule.len(), queued);
+        assert_eq!(schedule.queued(&origin), 0);
+        assert_eq!(schedule.score(origin.tile, IVec2::ZERO), f32::INFINITY);
+    }
+
+    #[test]
+    fn view_distance() {
+        assert_eq!(adapt_view_distance(5000., 50.), 5000.);
+        assert!(adapt_view_distance(5000., 20.) < 5000.);
+        assert!(adapt_view_distance(5000., 60.) > 5000.);
+        // Limits
+        assert_eq!(adapt_view_distance(2000., 20.), 2000.);
+        assert_eq!(adapt_view_distance(10000., 60.), 10000.);
+        assert_eq!(adapt_view_distance(500., 50.), 2000.);
+    }
+}
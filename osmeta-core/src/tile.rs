@@ -0,0 +1,6 @@
+//! The OSM tile-name(x/y) system, as index of a whole tile and as coordinate within the tiles.
+
+mod coord;
+mod index;
+pub use coord::*;
+pub use index::*;
@@ -1,6 +1,6 @@
 use super::TileIndex;
 use crate::geocoord::GeoCoord;
-use bevy::prelude::*;
+use glam::Vec2;
 use std::f32::consts::PI;
 
 /// A coordinate in the OWM tile coordinate system.
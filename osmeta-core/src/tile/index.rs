@@ -1,11 +1,10 @@
-use bevy::prelude::*;
+use glam::{IVec2, UVec2};
 use std::fmt::Display;
 
 use super::coord::TileCoord;
-use crate::{player::Directions, GalacticTransformOwned};
 
 /// An x/y index of an OWM tile.
-#[derive(Debug, Copy, Clone, Component, Hash, PartialEq, Eq)]
+#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
 pub struct TileIndex {
     idx: UVec2,
     zoom: u8,
@@ -20,6 +19,10 @@ impl std::ops::Deref for TileIndex {
 }
 
 impl TileIndex {
+    pub fn new(idx: UVec2, zoom: u8) -> Self {
+        Self { idx, zoom }
+    }
+
     pub fn as_coord(self) -> TileCoord {
         self.into()
     }
@@ -63,15 +66,6 @@ impl TileIndex {
         }
     }
 
-    pub fn to_cartesian(self) -> GalacticTransformOwned {
-        let coord = self.as_coord().center();
-        let pos = coord.to_geo_coord().to_cartesian();
-        let Directions { up, north, west: _ } = pos.directions();
-        let mut galactic_transform = pos.to_galactic_transform_space().galactic_transform;
-        galactic_transform.transform.look_to(north, up);
-        galactic_transform
-    }
-
     pub fn zoom(&self) -> u8 {
         self.zoom
     }
@@ -89,3 +83,30 @@ impl Display for TileIndex {
         self.idx.fmt(f)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tile(x: u32, y: u32) -> TileIndex {
+        TileIndex::new(UVec2::new(x, y), 3)
+    }
+
+    #[test]
+    fn offset() {
+        assert_eq!(tile(2, 5).offset(IVec2::new(1, -2)), tile(3, 3));
+        // Wraps around the globe, there are 8 tiles at zoom 3
+        assert_eq!(tile(0, 5).offset(IVec2::new(-1, 2)), tile(7, 7));
+        assert_eq!(tile(7, 7).offset(IVec2::new(1, 1)), tile(0, 0));
+        assert_eq!(tile(4, 4).offset(IVec2::ZERO), tile(4, 4));
+    }
+
+    #[test]
+    fn distance_squared() {
+        assert_eq!(tile(1, 2).distance_squared(tile(1, 2)), 0);
+        assert_eq!(tile(1, 2).distance_squared(tile(4, 6)), 3 * 3 + 4 * 4);
+        assert_eq!(tile(4, 6).distance_squared(tile(1, 2)), 3 * 3 + 4 * 4);
+        // The short way around the globe
+        assert_eq!(tile(0, 0).distance_squared(tile(7, 6)), 1 + 2 * 2);
+    }
+}
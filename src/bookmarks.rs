@@ -0,0 +1,418 @@
+//! Named bookmarks of [`GeoView`]s, persisted and exchangeable as JSON or TOML files.
+//!
+//! A bookmark collection file looks like this (JSON):
+//! `{"version":1,"bookmarks":[{"id":"Digit9","name":"Munich","description":"",
+//!   "created":1700000000,"view":{"geo_coord":{"lat":48.1408,"lon":11.5577},"elevation":1.4, ...}}]}`
+//!
+//! The `version` is increased with every incompatible change of the format, files of newer
+//! versions are rejected. There is only version 1 yet. The space separated text file of the
+//! first OSMeta versions (see [`Bookmark::from_legacy`]) is read, if no JSON file is stored yet.
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::geocoord::GeoCoord;
+use crate::geoview::{GeoView, Views};
+
+/// The actual version of the bookmark file format
+pub const BOOKMARKS_VERSION: u32 = 1;
+
+/// Name of the file (native) or localStorage key (wasm) the views are persisted in
+const VIEWS_STORAGE_NAME: &str = "OSMeta_views";
+
+/// A stored [`GeoView`] with a name and some more infos
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Bookmark {
+    /// Unique key of the bookmark, like `Digit1` for the digit keys
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    /// Creation time in seconds since 1970 (UNIX epoch)
+    #[serde(default)]
+    pub created: u64,
+    pub view: GeoView,
+}
+
+impl Bookmark {
+    pub fn new(id: String, view: GeoView) -> Self {
+        Self {
+            name: id.clone(),
+            id,
+            description: String::new(),
+            created: now(),
+            view,
+        }
+    }
+
+    /// Parse a line of the legacy storage: the id, followed by the space separated view
+    pub fn from_legacy(line: &str) -> Result<Self, BookmarkError> {
+        let Some((id, view)) = line.split_once(' ') else {
+            return Err(BookmarkError::Parse(format!("no view in `{line}`")));
+        };
+        Ok(Self::new(id.into(), view.parse()?))
+    }
+}
+
+/// The content of a bookmark file
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BookmarkCollection {
+    pub version: u32,
+    pub bookmarks: Vec<Bookmark>,
+}
+
+#[derive(Debug)]
+pub enum BookmarkError {
+    Io(std::io::Error),
+    Parse(String),
+    /// The bookmarks could not be written in the format
+    Serialize(String),
+    /// The file was written by a newer OSMeta
+    UnsupportedVersion(u32),
+    /// The file extension is not `json` or `toml`
+    UnknownFormat(String),
+}
+
+impl std::fmt::Display for BookmarkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BookmarkError::Io(err) => write!(f, "bookmark file error: {err}"),
+            BookmarkError::Parse(msg) => write!(f, "invalid bookmarks: {msg}"),
+            BookmarkError::Serialize(msg) => write!(f, "could not write bookmarks: {msg}"),
+            BookmarkError::UnsupportedVersion(version) => write!(
+                f,
+                "bookmark version {version} is not supported (max {BOOKMARKS_VERSION})"
+            ),
+            BookmarkError::UnknownFormat(ext) => {
+                write!(f, "unknown bookmark format `{ext}`, use `json` or `toml`")
+            }
+        }
+    }
+}
+
+impl From<std::io::Error> for BookmarkError {
+    fn from(err: std::io::Error) -> Self {
+        BookmarkError::Io(err)
+    }
+}
+
+/// File formats for import and export
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BookmarkFormat {
+    Json,
+    Toml,
+}
+
+impl BookmarkFormat {
+    /// Select the format by the file extension
+    pub fn from_path(path: &Path) -> Result<Self, BookmarkError> {
+        let ext = path.extension().and_then(|ext| ext.to_str()).unwrap_or("");
+        match ext {
+            "json" => Ok(Self::Json),
+            "toml" => Ok(Self::Toml),
+            other => Err(BookmarkError::UnknownFormat(other.into())),
+        }
+    }
+}
+
+impl BookmarkCollection {
+    pub fn parse(text: &str, format: BookmarkFormat) -> Result<Self, BookmarkError> {
+        let collection: Self = match format {
+            BookmarkFormat::Json => {
+                serde_json::from_str(text).map_err(|err| BookmarkError::Parse(err.to_string()))?
+            }
+            BookmarkFormat::Toml => {
+                toml::from_str(text).map_err(|err| BookmarkError::Parse(err.to_string()))?
+            }
+        };
+        if collection.version > BOOKMARKS_VERSION {
+            return Err(BookmarkError::UnsupportedVersion(collection.version));
+        }
+        Ok(collection)
+    }
+
+    pub fn to_text(&self, format: BookmarkFormat) -> Result<String, BookmarkError> {
+        match format {
+            BookmarkFormat::Json => serde_json::to_string_pretty(self)
+                .map_err(|err| BookmarkError::Serialize(err.to_string())),
+            BookmarkFormat::Toml => toml::to_string_pretty(self)
+                .map_err(|err| BookmarkError::Serialize(err.to_string())),
+        }
+    }
+}
+
+impl std::str::FromStr for GeoView {
+    type Err = BookmarkError;
+
+    /// Parse the old, space separated view format: `lat lon elevation direction up_view distance fov`
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let floats = text
+            .split(' ')
+            .map(|float| {
+                float
+                    .parse::<f32>()
+                    .map_err(|err| BookmarkError::Parse(format!("`{float}`: {err}")))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let [lat, lon, elevation, direction, up_view, distance, camera_fov] = *floats else {
+            return Err(BookmarkError::Parse(format!(
+                "expected 7 values, got `{text}`"
+            )));
+        };
+        Ok(GeoView {
+            geo_coord: GeoCoord { lat, lon },
+            elevation,
+            direction,
+            up_view,
+            distance,
+            camera_fov,
+        })
+    }
+}
+
+impl Views {
+    /// Load the persisted views. Returns an empty map, if nothing was stored yet.
+    /// Broken storage is reported and ignored.
+    pub fn load() -> Self {
+        let mut views = Views::default();
+        if let Some(text) = read_storage() {
+            match BookmarkCollection::parse(&text, BookmarkFormat::Json) {
+                Ok(collection) => views.insert_collection(collection),
+                Err(err) => error!("stored views ignored: {err}"),
+            }
+        } else if let Some(text) = read_legacy_storage() {
+            for line in text.lines().filter(|line| !line.trim().is_empty()) {
+                match Bookmark::from_legacy(line) {
+                    Ok(bookmark) => {
+                        views.map.insert(bookmark.id.clone(), bookmark);
+                    }
+                    Err(err) => error!("stored view `{line}` ignored: {err}"),
+                }
+            }
+        }
+        views
+    }
+
+    /// Persist all views, to be restored by [[load]] at the next start
+    pub fn save(&self) {
+        match self.to_collection().to_text(BookmarkFormat::Json) {
+            Ok(text) => write_storage(&text),
+            Err(err) => error!("could not store views: {err}"),
+        }
+    }
+
+    /// All bookmarks, sorted by their id
+    pub fn to_collection(&self) -> BookmarkCollection {
+        let mut bookmarks: Vec<Bookmark> = self.map.values().cloned().collect();
+        bookmarks.sort_by(|a, b| a.id.cmp(&b.id));
+        BookmarkCollection {
+            version: BOOKMARKS_VERSION,
+            bookmarks,
+        }
+    }
+
+    /// Add or replace bookmarks with the same id
+    pub fn insert_collection(&mut self, collection: BookmarkCollection) {
+        for bookmark in collection.bookmarks {
+            self.map.insert(bookmark.id.clone(), bookmark);
+        }
+    }
+
+    /// Read a bookmark collection file (`.json` or `.toml`) and add it to the views
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn import(&mut self, path: &Path) -> Result<usize, BookmarkError> {
+        let format = BookmarkFormat::from_path(path)?;
+        let text = std::fs::read_to_string(path)?;
+        let collection = BookmarkCollection::parse(&text, format)?;
+        let count = collection.bookmarks.len();
+        self.insert_collection(collection);
+        Ok(count)
+    }
+
+    /// Write all bookmarks to a collection file (`.json` or `.toml`)
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn export(&self, path: &Path) -> Result<(), BookmarkError> {
+        let format = BookmarkFormat::from_path(path)?;
+        let text = self.to_collection().to_text(format)?;
+        std::fs::write(path, text)?;
+        Ok(())
+    }
+}
+
+/// Seconds since 1970
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+/// Seconds since 1970 (std::time::SystemTime panics in the browser)
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn now() -> u64 {
+    (js_sys::Date::now() / 1000.0) as u64
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn storage_path(ext: &str) -> Option<std::path::PathBuf> {
+    directories::ProjectDirs::from("org", "osmeta", "OSMeta").map(|dirs| {
+        dirs.config_dir()
+            .join(VIEWS_STORAGE_NAME)
+            .with_extension(ext)
+    })
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn read_storage() -> Option<String> {
+    std::fs::read_to_string(storage_path("json")?).ok()
+}
+
+/// The space separated format of the first OSMeta versions
+#[cfg(not(target_arch = "wasm32"))]
+fn read_legacy_storage() -> Option<String> {
+    std::fs::read_to_string(storage_path("txt")?).ok()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn write_storage(text: &str) {
+    let Some(path) = storage_path("json") else {
+        return;
+    };
+    let result =
+        std::fs::create_dir_all(path.parent().unwrap()).and_then(|_| std::fs::write(&path, text));
+    if let Err(err) = result {
+        warn!("could not store views in {path:?}: {err}");
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn local_storage() -> Option<web_sys::Storage> {
+    web_sys::window()?.local_storage().ok()?
+}
+
+#[cfg(target_arch = "wasm32")]
+fn read_storage() -> Option<String> {
+    local_storage()?
+        .get_item(&format!("{VIEWS_STORAGE_NAME}_json"))
+        .ok()?
+}
+
+#[cfg(target_arch = "wasm32")]
+fn read_legacy_storage() -> Option<String> {
+    local_storage()?.get_item(VIEWS_STORAGE_NAME).ok()?
+}
+
+#[cfg(target_arch = "wasm32")]
+fn write_storage(text: &str) {
+    let Some(storage) = local_storage() else {
+        return;
+    };
+    if storage
+        .set_item(&format!("{VIEWS_STORAGE_NAME}_json"), text)
+        .is_err()
+    {
+        warn!("could not store views in the localStorage");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn collection() -> BookmarkCollection {
+        let view = GeoView {
+            geo_coord: GeoCoord {
+                lat: 48.1408,
+                lon: 11.5577,
+            },
+            elevation: 1.4,
+            direction: -105.0,
+            up_view: -30.0,
+            distance: 500.0,
+            camera_fov: 30.0,
+        };
+        let mut bookmark = Bookmark::new("Digit1".into(), view);
+        bookmark.name = "Munich".into();
+        bookmark.description = "Main station".into();
+        BookmarkCollection {
+            version: BOOKMARKS_VERSION,
+            bookmarks: vec![bookmark],
+        }
+    }
+
+    fn round_trip(format: BookmarkFormat) {
+        let text = collection().to_text(format).unwrap();
+        let parsed = BookmarkCollection::parse(&text, format).unwrap();
+        let [bookmark] = &parsed.bookmarks[..] else {
+            panic!("one bookmark expected: {parsed:?}");
+        };
+        let expected = &collection().bookmarks[0];
+        assert_eq!(bookmark.id, expected.id);
+        assert_eq!(bookmark.name, expected.name);
+        assert_eq!(bookmark.description, expected.description);
+        assert_eq!(bookmark.view, expected.view);
+    }
+
+    #[test]
+    fn json_round_trip() {
+        round_trip(BookmarkFormat::Json);
+    }
+
+    #[test]
+    fn toml_round_trip() {
+        round_trip(BookmarkFormat::Toml);
+    }
+
+    #[test]
+    fn legacy() {
+        let bookmark = Bookmark::from_legacy("Digit9 48.1408 11.5577 1.4 0 -30 500 42").unwrap();
+        assert_eq!(bookmark.id, "Digit9");
+        assert_eq!(bookmark.view.geo_coord.lat, 48.1408);
+        assert_eq!(bookmark.view.geo_coord.lon, 11.5577);
+        assert_eq!(bookmark.view.up_view, -30.0);
+        assert_eq!(bookmark.view.camera_fov, 42.0);
+    }
+
+    #[test]
+    fn legacy_errors() {
+        for line in [
+            "Digit9",
+            "Digit9 48.1408 11.5577 1.4",
+            "Digit9 48.1408 east 1.4 0 -30 500 42",
+        ] {
+            let result = Bookmark::from_legacy(line);
+            assert!(matches!(result, Err(BookmarkError::Parse(_))), "{line}");
+        }
+    }
+
+    #[test]
+    fn newer_version() {
+        let mut collection = collection();
+        collection.version = BOOKMARKS_VERSION + 1;
+        let text = collection.to_text(BookmarkFormat::Json).unwrap();
+        let result = BookmarkCollection::parse(&text, BookmarkFormat::Json);
+        assert!(
+            matches!(result, Err(BookmarkError::UnsupportedVersion(v)) if v == BOOKMARKS_VERSION + 1)
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        let result = BookmarkCollection::parse("{\"version\": 1}", BookmarkFormat::Json);
+        assert!(matches!(result, Err(BookmarkError::Parse(_))));
+        let result = BookmarkCollection::parse("version = ", BookmarkFormat::Toml);
+        assert!(matches!(result, Err(BookmarkError::Parse(_))));
+    }
+
+    #[test]
+    fn unknown_format() {
+        let result = BookmarkFormat::from_path(Path::new("views.txt"));
+        assert!(matches!(result, Err(BookmarkError::UnknownFormat(ext)) if ext == "txt"));
+        assert_eq!(
+            BookmarkFormat::from_path(Path::new("views.toml")).unwrap(),
+            BookmarkFormat::Toml
+        );
+    }
+}
@@ -0,0 +1,1186 @@
+//! Storage of downloaded files, so they don't need to be downloaded again.
+//!
+//! [`CacheStore`] abstracts the place: a directory on native ([`DiskCache`]),
+//! the Cache API of the browser on the web ([`BrowserCache`]).
+//! Each entry has a [`CacheMeta`], to revalidate it with the server when it is stale.
+//! A [`LimitedCache`] keeps a store below a size limit and counts its use.
+//!
+//! Files are replaced atomically, and the meta data records their length and CRC32.
+//! A file that doesn't match is moved to `quarantine/` and downloaded again.
+//!
+//! The keys of an asset source are namespaced by the [`CACHE_VERSION`], the source and the
+//! server: `v1/tile/gltiles.osm2world.org_glb/15_17388_11332.glb`. So another server doesn't
+//! get the files of the previous one. The flat layout of older versions only had files of the
+//! default tile server. Its entries are moved to the namespace of that server when they are first
+//! read with it. Entries in the index of other sources are left to the eviction.
+
+use bevy::{
+    asset::AsyncWriteExt, log::debug, prelude::Resource, tasks::futures_lite::StreamExt,
+    utils::BoxedFuture,
+};
+use flate2::Crc;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    io,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use crate::bookmarks::now;
+use crate::http::HttpResponse;
+
+/// The keys of a [`CacheStore`] with the size of their entries
+pub type Listing = Vec<(String, u64)>;
+
+/// A key-value store for downloaded files. Keys are relative paths like `15_17388_11332.glb`.
+pub trait CacheStore: Send + Sync + 'static {
+    /// The stored bytes, `None` if there are none
+    fn read<'a>(&'a self, key: &'a str) -> BoxedFuture<'a, io::Result<Option<Vec<u8>>>>;
+    fn write<'a>(&'a self, key: &'a str, bytes: &'a [u8]) -> BoxedFuture<'a, io::Result<()>>;
+    /// Removing a missing entry is fine
+    fn remove<'a>(&'a self, key: &'a str) -> BoxedFuture<'a, io::Result<()>>;
+    /// All keys with the size of their entries, `None` if the store can't list them cheaply
+    fn list(&self) -> BoxedFuture<'_, io::Result<Option<Listing>>> {
+        Box::pin(async { Ok(None) })
+    }
+}
+
+/// Caches in a directory of the file system
+pub struct DiskCache {
+    pub root: PathBuf,
+}
+
+/// Makes the names of temporary files unique within the process
+static TEMP_FILES: AtomicU64 = AtomicU64::new(0);
+
+impl CacheStore for DiskCache {
+    fn read<'a>(&'a self, key: &'a str) -> BoxedFuture<'a, io::Result<Option<Vec<u8>>>> {
+        Box::pin(async move {
+            match async_fs::read(self.root.join(key)).await {
+                Ok(bytes) => Ok(Some(bytes)),
+                Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+                Err(err) => Err(err),
+            }
+        })
+    }
+
+    fn write<'a>(&'a self, key: &'a str, bytes: &'a [u8]) -> BoxedFuture<'a, io::Result<()>> {
+        Box::pin(async move {
+            let path = self.root.join(key);
+            if let Some(parent) = path.parent() {
+                async_fs::create_dir_all(parent).await?;
+            }
+            // Write a temporary file and rename it, so a crash can't leave a partial file
+            let mut temp = path.clone().into_os_string();
+            temp.push(format!(
+                ".{}-{}.tmp",
+                std::process::id(),
+                TEMP_FILES.fetch_add(1, Ordering::Relaxed)
+            ));
+            let result = async {
+                let mut file = async_fs::File::create(&temp).await?;
+                file.write_all(bytes).await?;
+                file.sync_data().await?;
+                async_fs::rename(&temp, &path).await
+            }
+            .await;
+            if result.is_err() {
+                let _ = async_fs::remove_file(&temp).await;
+            }
+            result
+        })
+    }
+
+    fn remove<'a>(&'a self, key: &'a str) -> BoxedFuture<'a, io::Result<()>> {
+        Box::pin(async move {
+            match async_fs::remove_file(self.root.join(key)).await {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+                _ => Ok(()),
+            }
+        })
+    }
+
+    fn list(&self) -> BoxedFuture<'_, io::Result<Option<Listing>>> {
+        Box::pin(async move {
+            let mut entries = vec![];
+            let mut dirs = vec![String::new()];
+            while let Some(dir) = dirs.pop() {
+                let mut read_dir = match async_fs::read_dir(self.root.join(&dir)).await {
+                    Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+                    read_dir => read_dir?,
+                };
+                while let Some(entry) = read_dir.next().await {
+                    let entry = entry?;
+                    let key = format!("{dir}{}", entry.file_name().to_string_lossy());
+                    let metadata = entry.metadata().await?;
+                    if metadata.is_dir() {
+                        dirs.push(format!("{key}/"));
+                    } else {
+                        entries.push((key, metadata.len()));
+                    }
+                }
+            }
+            Ok(Some(entries))
+        })
+    }
+}
+
+/// Caches in the Cache API storage of the browser, which is kept across page reloads
+#[cfg(target_arch = "wasm32")]
+pub struct BrowserCache {
+    /// Name of the cache in the `CacheStorage`
+    pub name: String,
+}
+
+#[cfg(target_arch = "wasm32")]
+mod browser {
+    use super::*;
+    use js_sys::Uint8Array;
+    use wasm_bindgen::{JsCast, JsValue};
+    use wasm_bindgen_futures::JsFuture;
+    use web_sys::{Cache, Response};
+
+    fn js_error(err: JsValue) -> io::Error {
+        io::Error::other(format!("{err:?}"))
+    }
+
+    /// The Cache API stores responses of requests, so the keys become URLs (relative to the page)
+    fn url(key: &str) -> String {
+        format!("osmeta-cache/{key}")
+    }
+
+    impl BrowserCache {
+        // The browser objects can't be kept, they are not `Send`
+        async fn open(&self) -> Result<Cache, JsValue> {
+            let window = web_sys::window().ok_or_else(|| JsValue::from_str("no window"))?;
+            let cache = JsFuture::from(window.caches()?.open(&self.name)).await?;
+            Ok(cache.unchecked_into())
+        }
+
+        async fn read_js(&self, key: &str) -> Result<Option<Vec<u8>>, JsValue> {
+            let cache = self.open().await?;
+            let response = JsFuture::from(cache.match_with_str(&url(key))).await?;
+            if response.is_undefined() {
+                return Ok(None);
+            }
+            let response: Response = response.unchecked_into();
+            let buffer = JsFuture::from(response.array_buffer()?).await?;
+            Ok(Some(Uint8Array::new(&buffer).to_vec()))
+        }
+
+        async fn write_js(&self, key: &str, bytes: &[u8]) -> Result<(), JsValue> {
+            let cache = self.open().await?;
+            let response = Response::new_with_opt_u8_array(Some(&mut bytes.to_vec()))?;
+            JsFuture::from(cache.put_with_str(&url(key), &response)).await?;
+            Ok(())
+        }
+
+        async fn remove_js(&self, key: &str) -> Result<(), JsValue> {
+            let cache = self.open().await?;
+            JsFuture::from(cache.delete_with_str(&url(key))).await?;
+            Ok(())
+        }
+    }
+
+    impl CacheStore for BrowserCache {
+        fn read<'a>(&'a self, key: &'a str) -> BoxedFuture<'a, io::Result<Option<Vec<u8>>>> {
+            Box::pin(async move { self.read_js(key).await.map_err(js_error) })
+        }
+
+        fn write<'a>(&'a self, key: &'a str, bytes: &'a [u8]) -> BoxedFuture<'a, io::Result<()>> {
+            Box::pin(async move { self.write_js(key, bytes).await.map_err(js_error) })
+        }
+
+        fn remove<'a>(&'a self, key: &'a str) -> BoxedFuture<'a, io::Result<()>> {
+            Box::pin(async move { self.remove_js(key).await.map_err(js_error) })
+        }
+    }
+}
+
+/// The cache directory of the OS for OSMeta, if there is one
+#[cfg(not(target_arch = "wasm32"))]
+pub fn default_cache_path() -> Option<PathBuf> {
+    directories::ProjectDirs::from("org", "osmeta", "OSMeta")
+        .map(|dirs| dirs.cache_dir().to_owned())
+}
+
+/// The default cache of the platform: the OS cache directory
+#[cfg(not(target_arch = "wasm32"))]
+pub fn default_cache() -> Option<Arc<dyn CacheStore>> {
+    default_cache_path().map(|root| Arc::new(DiskCache { root }) as Arc<dyn CacheStore>)
+}
+
+/// The default cache of the platform: the browser storage
+#[cfg(target_arch = "wasm32")]
+pub fn default_cache() -> Option<Arc<dyn CacheStore>> {
+    Some(Arc::new(BrowserCache {
+        name: "osmeta-tiles".into(),
+    }))
+}
+
+/// What is known about a cached file, stored next to it as `<key>.meta.json`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CacheMeta {
+    /// Seconds since 1970 of the download or the last revalidation
+    pub fetched: u64,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    /// Seconds the file is fresh, from the `Cache-Control` of the server
+    pub max_age: Option<u64>,
+    /// Length and CRC32 of the stored file, to detect broken files
+    pub length: Option<u64>,
+    pub crc32: Option<u32>,
+}
+
+impl CacheMeta {
+    /// The metadata of a download, `None` if the server does not allow to store it
+    pub fn from_response(response: &HttpResponse) -> Option<Self> {
+        let mut meta = CacheMeta::default();
+        meta.update(response).then_some(meta)
+    }
+
+    /// Take the actual time and headers of a download or revalidation.
+    /// Returns `false` if the server does not allow to store the file.
+    pub fn update(&mut self, response: &HttpResponse) -> bool {
+        self.fetched = now();
+        if let Some(etag) = response.header("etag") {
+            self.etag = Some(etag.into());
+        }
+        if let Some(last_modified) = response.header("last-modified") {
+            self.last_modified = Some(last_modified.into());
+        }
+        let mut store = true;
+        self.max_age = None;
+        for directive in response.header("cache-control").unwrap_or("").split(',') {
+            // The names are case-insensitive
+            let directive = directive.trim().to_ascii_lowercase();
+            match directive.split_once('=') {
+                Some((name, seconds)) if name.trim() == "max-age" => {
+                    self.max_age = seconds.trim().parse().ok()
+                }
+                None if directive == "no-cache" => self.max_age = Some(0),
+                None if directive == "no-store" => store = false,
+                _ => {}
+            }
+        }
+        store
+    }
+
+    /// Not yet to be revalidated. Without a max-age of the server `ttl` seconds are used.
+    pub fn is_fresh(&self, ttl: u64) -> bool {
+        now().saturating_sub(self.fetched) < self.max_age.unwrap_or(ttl)
+    }
+
+    /// Record the length and checksum of the file to store
+    pub fn seal(&mut self, bytes: &[u8]) {
+        let mut crc = Crc::new();
+        crc.update(bytes);
+        self.length = Some(bytes.len() as u64);
+        self.crc32 = Some(crc.sum());
+    }
+
+    /// Whether `bytes` are the stored file. Metadata of older versions has nothing to compare.
+    pub fn verify(&self, bytes: &[u8]) -> bool {
+        if self
+            .length
+            .is_some_and(|length| length != bytes.len() as u64)
+        {
+            return false;
+        }
+        self.crc32.is_none_or(|crc32| {
+            let mut crc = Crc::new();
+            crc.update(bytes);
+            crc.sum() == crc32
+        })
+    }
+
+    /// The headers of a conditional request, to download the file only if it changed
+    pub fn validators(&self) -> Vec<(&'static str, String)> {
+        let mut headers = vec![];
+        if let Some(etag) = &self.etag {
+            headers.push(("if-none-match", etag.clone()));
+        }
+        if let Some(last_modified) = &self.last_modified {
+            headers.push(("if-modified-since", last_modified.clone()));
+        }
+        headers
+    }
+}
+
+fn meta_key(key: &str) -> String {
+    format!("{key}.meta.json")
+}
+
+/// The metadata of a cache entry. Entries written by older versions have none.
+pub async fn read_meta(store: &dyn CacheStore, key: &str) -> Option<CacheMeta> {
+    let bytes = store.read(&meta_key(key)).await.ok()??;
+    serde_json::from_slice(&bytes).ok()
+}
+
+pub async fn write_meta(store: &dyn CacheStore, key: &str, meta: &CacheMeta) -> io::Result<()> {
+    let bytes = serde_json::to_vec(meta).map_err(io::Error::other)?;
+    store.write(&meta_key(key), &bytes).await
+}
+
+/// Store a downloaded file with its metadata, or remove it if it must not be stored.
+/// The metadata is written last, a crash in between leaves a file that fails [`CacheMeta::verify`].
+pub async fn store_entry(
+    store: &dyn CacheStore,
+    key: &str,
+    bytes: &[u8],
+    meta: Option<&CacheMeta>,
+) -> io::Result<()> {
+    match meta {
+        Some(meta) => {
+            let mut meta = meta.clone();
+            meta.seal(bytes);
+            store.write(key, bytes).await?;
+            write_meta(store, key, &meta).await
+        }
+        None => {
+            store.remove(key).await?;
+            store.remove(&meta_key(key)).await
+        }
+    }
+}
+
+/// Move a broken entry out of the way, to be downloaded again. It is kept for inspection
+/// under `quarantine/`, until it is evicted.
+pub async fn quarantine(store: &dyn CacheStore, key: &str, bytes: &[u8]) -> io::Result<()> {
+    store.write(&format!("quarantine/{key}"), bytes).await?;
+    store.remove(key).await?;
+    store.remove(&meta_key(key)).await
+}
+
+/// The format of the cache entries. Entries of other versions are not used, but evicted in time.
+pub const CACHE_VERSION: u32 = 1;
+
+/// Default size limit of the cache in bytes
+pub const DEFAULT_CACHE_SIZE: u64 = 1024 * 1024 * 1024;
+
+/// The entry of the [`LimitedCache`] that lists all other entries
+const INDEX_KEY: &str = "index.json";
+
+/// The server of all entries of the flat layout, see the module docs
+const LEGACY_SERVER: &str = "gltiles.osm2world.org/glb/";
+
+/// The asset source of the entries of the flat layout that are not in the index,
+/// written before there was one
+const LEGACY_SOURCE: &str = "tile";
+
+/// The index is stored after this many changes, so a crash loses at most these
+const INDEX_SAVE_INTERVAL: u32 = 32;
+
+/// After exceeding the limit, this fraction of it is kept, so not every write evicts
+const EVICT_TO: f64 = 0.9;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct IndexEntry {
+    /// The asset source that wrote the entry, like `tile`
+    source: String,
+    size: u64,
+    /// Seconds since 1970 of the last read or write
+    used: u64,
+}
+
+#[derive(Default)]
+struct Index {
+    entries: HashMap<String, IndexEntry>,
+    bytes: u64,
+    loaded: bool,
+    /// Changes since the index was stored
+    changes: u32,
+}
+
+impl Index {
+    fn insert(&mut self, key: &str, entry: IndexEntry) {
+        self.bytes += entry.size;
+        if let Some(old) = self.entries.insert(key.into(), entry) {
+            self.bytes -= old.size;
+        }
+        self.changes += 1;
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some(old) = self.entries.remove(key) {
+            self.bytes -= old.size;
+            self.changes += 1;
+        }
+    }
+
+    /// Forget the least recently used entries until the size is well below `max_bytes`
+    fn evict(&mut self, max_bytes: u64) -> Vec<String> {
+        if self.bytes <= max_bytes {
+            return vec![];
+        }
+        let mut by_use: Vec<(u64, String)> = self
+            .entries
+            .iter()
+            .map(|(key, entry)| (entry.used, key.clone()))
+            .collect();
+        by_use.sort_unstable();
+        let target = (max_bytes as f64 * EVICT_TO) as u64;
+        let mut evicted = vec![];
+        for (_, key) in by_use {
+            if self.bytes <= target {
+                break;
+            }
+            self.remove(&key);
+            evicted.push(key);
+        }
+        evicted
+    }
+}
+
+/// The statistics of a [`LimitedCache`], updated by the asset readers
+#[derive(Default)]
+pub struct CacheCounters {
+    pub hits: AtomicU64,
+    pub misses: AtomicU64,
+    pub bytes: AtomicU64,
+    pub entries: AtomicU64,
+}
+
+impl CacheCounters {
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+            entries: self.entries.load(Ordering::Relaxed),
+        }
+    }
+
+    fn update(&self, index: &Index) {
+        self.bytes.store(index.bytes, Ordering::Relaxed);
+        self.entries
+            .store(index.entries.len() as u64, Ordering::Relaxed);
+    }
+}
+
+/// The statistics of the tile cache: hits and misses of this session, size and entries in total
+#[derive(Resource, Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub bytes: u64,
+    pub entries: u64,
+}
+
+/// Keeps a [`CacheStore`] below a size limit, by removing the least recently used entries.
+/// The sizes and last uses of the entries are kept in an index entry of the store.
+/// When loading the index, it is fixed with the files in the store, if the store can list them.
+/// So files written after the index was stored, or by older versions without an index, are
+/// counted too.
+pub struct LimitedCache {
+    store: Arc<dyn CacheStore>,
+    pub max_bytes: u64,
+    index: Mutex<Index>,
+    counters: Arc<CacheCounters>,
+}
+
+impl LimitedCache {
+    pub fn new(store: Arc<dyn CacheStore>, max_bytes: u64, counters: Arc<CacheCounters>) -> Self {
+        Self {
+            store,
+            max_bytes,
+            index: Mutex::default(),
+            counters,
+        }
+    }
+
+    /// The store for one asset source and the server it downloads from, see the module docs.
+    /// It marks the entries written through it.
+    pub fn source(self: &Arc<Self>, source: &'static str, server: &str) -> SourceCache {
+        let server = server_dir(server);
+        SourceCache {
+            cache: self.clone(),
+            source,
+            legacy: server == server_dir(LEGACY_SERVER),
+            prefix: format!("v{CACHE_VERSION}/{source}/{server}/"),
+        }
+    }
+
+    async fn load_index(&self) {
+        if self.index.lock().unwrap().loaded {
+            return;
+        }
+        let mut entries: HashMap<String, IndexEntry> = match self.store.read(INDEX_KEY).await {
+            Ok(Some(bytes)) => serde_json::from_slice(&bytes).unwrap_or_default(),
+            _ => HashMap::new(),
+        };
+        let changes = match self.store.list().await {
+            Ok(Some(files)) => reconcile(&mut entries, files),
+            Ok(None) => 0,
+            Err(err) => {
+                debug!("could not list the cache: {err}");
+                0
+            }
+        };
+        let mut index = self.index.lock().unwrap();
+        if index.loaded {
+            return;
+        }
+        index.loaded = true;
+        // Entries written while loading are newer
+        for (key, entry) in entries {
+            if !index.entries.contains_key(&key) {
+                index.insert(&key, entry);
+            }
+        }
+        index.changes = changes;
+        self.counters.update(&index);
+    }
+
+    /// Store the index if it changed since the last time, like when the app ends
+    pub async fn save(&self) -> io::Result<()> {
+        let changed = {
+            let index = self.index.lock().unwrap();
+            index.loaded && index.changes > 0
+        };
+        if changed {
+            self.save_index().await?;
+        }
+        Ok(())
+    }
+
+    async fn save_index(&self) -> io::Result<()> {
+        let bytes = {
+            let mut index = self.index.lock().unwrap();
+            index.changes = 0;
+            serde_json::to_vec(&index.entries).map_err(io::Error::other)?
+        };
+        self.store.write(INDEX_KEY, &bytes).await
+    }
+
+    /// Store the index, if enough changed since the last time
+    async fn changed(&self) -> io::Result<()> {
+        let save = self.index.lock().unwrap().changes >= INDEX_SAVE_INTERVAL;
+        if save {
+            self.save_index().await?;
+        }
+        Ok(())
+    }
+
+    pub async fn read(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        self.load_index().await;
+        let bytes = self.store.read(key).await?;
+        {
+            let mut index = self.index.lock().unwrap();
+            match (&bytes, index.entries.get_mut(key)) {
+                (Some(_), Some(entry)) => {
+                    entry.used = now();
+                    index.changes += 1;
+                }
+                // Removed by someone else
+                (None, Some(_)) => index.remove(key),
+                _ => {}
+            }
+            self.counters.update(&index);
+        }
+        self.changed().await?;
+        Ok(bytes)
+    }
+
+    pub async fn write(&self, source: &str, key: &str, bytes: &[u8]) -> io::Result<()> {
+        self.load_index().await;
+        self.store.write(key, bytes).await?;
+        let evicted = {
+            let mut index = self.index.lock().unwrap();
+            let entry = IndexEntry {
+                source: source.into(),
+                size: bytes.len() as u64,
+                used: now(),
+            };
+            index.insert(key, entry);
+            let evicted = index.evict(self.max_bytes);
+            self.counters.update(&index);
+            evicted
+        };
+        for key in evicted {
+            self.store.remove(&key).await?;
+        }
+        self.changed().await
+    }
+
+    pub async fn remove(&self, key: &str) -> io::Result<()> {
+        self.load_index().await;
+        self.store.remove(key).await?;
+        {
+            let mut index = self.index.lock().unwrap();
+            index.remove(key);
+            self.counters.update(&index);
+        }
+        self.changed().await
+    }
+
+    /// Move an entry of the flat layout of older versions to its namespaced `key`, if the
+    /// index lists it for this asset source. Entries without an index are of the tile source.
+    /// Returns its bytes.
+    async fn migrate(&self, source: &str, legacy: &str, key: &str) -> io::Result<Option<Vec<u8>>> {
+        if legacy == INDEX_KEY {
+            return Ok(None);
+        }
+        self.load_index().await;
+        let owner = {
+            let index = self.index.lock().unwrap();
+            index.entries.get(legacy).map(|entry| entry.source.clone())
+        };
+        // Other sources don't look for unlisted entries, so their misses cost no read of the store
+        if owner.as_deref().unwrap_or(LEGACY_SOURCE) != source {
+            return Ok(None);
+        }
+        let Some(bytes) = self.store.read(legacy).await? else {
+            return Ok(None);
+        };
+        debug!("migrating cache entry {legacy} to {key}");
+        self.write(source, key, &bytes).await?;
+        self.remove(legacy).await?;
+        Ok(Some(bytes))
+    }
+
+    /// Remove all entries of an asset source, or all entries. Returns the number of entries.
+    pub async fn clear(&self, source: Option<&str>) -> io::Result<usize> {
+        self.load_index().await;
+        let keys: Vec<String> = {
+            let mut index = self.index.lock().unwrap();
+            let keys: Vec<String> = index
+                .entries
+                .iter()
+                .filter(|(_, entry)| source.is_none_or(|source| entry.source == source))
+                .map(|(key, _)| key.clone())
+                .collect();
+            for key in &keys {
+                index.remove(key);
+            }
+            self.counters.update(&index);
+            keys
+        };
+        for key in &keys {
+            self.store.remove(key).await?;
+        }
+        self.save_index().await?;
+        Ok(keys.len())
+    }
+}
+
+/// Fix the stored `index` with the `files` actually in the store: files removed by someone
+/// else are forgotten, files written after the index was stored or before there was one are
+/// added as least recently used. Returns the number of changes.
+fn reconcile(index: &mut HashMap<String, IndexEntry>, files: Listing) -> u32 {
+    let mut changes = 0;
+    let mut unlisted: HashMap<String, IndexEntry> = std::mem::take(index);
+    for (key, size) in files {
+        if key == INDEX_KEY {
+            continue;
+        }
+        let entry = match unlisted.remove(&key) {
+            Some(entry) if entry.size == size => entry,
+            Some(entry) => {
+                changes += 1;
+                IndexEntry { size, ..entry }
+            }
+            None => {
+                changes += 1;
+                IndexEntry {
+                    source: source_of(&key).into(),
+                    size,
+                    used: 0,
+                }
+            }
+        };
+        index.insert(key, entry);
+    }
+    changes + unlisted.len() as u32
+}
+
+/// The asset source of a key, see the module docs. Keys of the flat layout are tiles.
+fn source_of(key: &str) -> &str {
+    match key.split('/').collect::<Vec<_>>()[..] {
+        [version, source, _, ..]
+            if version.starts_with('v') && version[1..].parse::<u32>().is_ok() =>
+        {
+            source
+        }
+        _ => LEGACY_SOURCE,
+    }
+}
+
+/// The directory of a server in the cache: its host and path, without the scheme
+fn server_dir(server: &str) -> String {
+    let server = server
+        .trim_start_matches("http://")
+        .trim_start_matches("https://");
+    server
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '.' | '-' => c,
+            _ => '_',
+        })
+        .collect::<String>()
+        .trim_matches('_')
+        .into()
+}
+
+/// A [`LimitedCache`] as seen by one asset source
+pub struct SourceCache {
+    cache: Arc<LimitedCache>,
+    source: &'static str,
+    /// The server of the flat layout, whose entries are migrated
+    legacy: bool,
+    /// Of all keys, see the module docs
+    prefix: String,
+}
+
+impl CacheStore for SourceCache {
+    fn read<'a>(&'a self, key: &'a str) -> BoxedFuture<'a, io::Result<Option<Vec<u8>>>> {
+        Box::pin(async move {
+            let namespaced = format!("{}{key}", self.prefix);
+            match self.cache.read(&namespaced).await? {
+                Some(bytes) => Ok(Some(bytes)),
+                None if self.legacy => self.cache.migrate(self.source, key, &namespaced).await,
+                None => Ok(None),
+            }
+        })
+    }
+
+    fn write<'a>(&'a self, key: &'a str, bytes: &'a [u8]) -> BoxedFuture<'a, io::Result<()>> {
+        Box::pin(async move {
+            let key = format!("{}{key}", self.prefix);
+            self.cache.write(self.source, &key, bytes).await
+        })
+    }
+
+    fn remove<'a>(&'a self, key: &'a str) -> BoxedFuture<'a, io::Result<()>> {
+        Box::pin(async move { self.cache.remove(&format!("{}{key}", self.prefix)).await })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::tasks::futures_lite::future::block_on;
+
+    #[derive(Default)]
+    struct MemoryStore {
+        files: Mutex<HashMap<String, Vec<u8>>>,
+        reads: AtomicU64,
+        /// Whether it lists its entries, like a `DiskCache`
+        listed: bool,
+    }
+
+    impl MemoryStore {
+        fn keys(&self) -> Vec<String> {
+            let mut keys: Vec<String> = self.files.lock().unwrap().keys().cloned().collect();
+            keys.sort();
+            keys
+        }
+    }
+
+    impl CacheStore for MemoryStore {
+        fn read<'a>(&'a self, key: &'a str) -> BoxedFuture<'a, io::Result<Option<Vec<u8>>>> {
+            Box::pin(async move {
+                self.reads.fetch_add(1, Ordering::Relaxed);
+                Ok(self.files.lock().unwrap().get(key).cloned())
+            })
+        }
+
+        fn write<'a>(&'a self, key: &'a str, bytes: &'a [u8]) -> BoxedFuture<'a, io::Result<()>> {
+            Box::pin(async move {
+                self.files.lock().unwrap().insert(key.into(), bytes.into());
+                Ok(())
+            })
+        }
+
+        fn remove<'a>(&'a self, key: &'a str) -> BoxedFuture<'a, io::Result<()>> {
+            Box::pin(async move {
+                self.files.lock().unwrap().remove(key);
+                Ok(())
+            })
+        }
+
+        fn list(&self) -> BoxedFuture<'_, io::Result<Option<Listing>>> {
+            Box::pin(async move {
+                let files = self.files.lock().unwrap();
+                let entries = files
+                    .iter()
+                    .map(|(key, bytes)| (key.clone(), bytes.len() as u64));
+                Ok(self.listed.then(|| entries.collect()))
+            })
+        }
+    }
+
+    fn response(headers: &[(&str, &str)]) -> HttpResponse {
+        HttpResponse {
+            status: 200,
+            headers: headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            body: vec![],
+        }
+    }
+
+    #[test]
+    fn meta_from_response() {
+        let meta = CacheMeta::from_response(&response(&[
+            ("etag", "\"v1\""),
+            ("last-modified", "Wed, 21 Oct 2015 07:28:00 GMT"),
+            ("cache-control", "public, max-age = 3600"),
+        ]))
+        .unwrap();
+        assert_eq!(meta.etag.as_deref(), Some("\"v1\""));
+        assert_eq!(
+            meta.last_modified.as_deref(),
+            Some("Wed, 21 Oct 2015 07:28:00 GMT")
+        );
+        assert_eq!(meta.max_age, Some(3600));
+        assert_eq!(
+            meta.validators(),
+            [
+                ("if-none-match", "\"v1\"".to_string()),
+                ("if-modified-since", "Wed, 21 Oct 2015 07:28:00 GMT".into())
+            ]
+        );
+
+        let meta = CacheMeta::from_response(&response(&[])).unwrap();
+        assert_eq!((meta.etag, meta.max_age), (None, None));
+        assert!(CacheMeta::default().validators().is_empty());
+        let meta = CacheMeta::from_response(&response(&[("cache-control", "no-cache")]));
+        assert_eq!(meta.unwrap().max_age, Some(0));
+        let meta = CacheMeta::from_response(&response(&[("cache-control", "max-age=soon")]));
+        assert_eq!(meta.unwrap().max_age, None);
+        assert!(
+            CacheMeta::from_response(&response(&[("cache-control", "max-age=60, No-Store")]))
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn meta_update() {
+        let mut meta = CacheMeta::from_response(&response(&[
+            ("etag", "\"v1\""),
+            ("cache-control", "max-age=60"),
+        ]))
+        .unwrap();
+        meta.fetched = 0;
+        // A revalidation without validators keeps the old ones, but not the old max-age
+        assert!(meta.update(&response(&[("last-modified", "today")])));
+        assert_eq!(meta.etag.as_deref(), Some("\"v1\""));
+        assert_eq!(meta.last_modified.as_deref(), Some("today"));
+        assert_eq!(meta.max_age, None);
+        assert!(meta.fetched > 0);
+    }
+
+    #[test]
+    fn freshness() {
+        let mut meta = CacheMeta {
+            fetched: now() - 100,
+            ..Default::default()
+        };
+        // The TTL without a max-age of the server
+        assert!(meta.is_fresh(1000));
+        assert!(!meta.is_fresh(50));
+        assert!(!meta.is_fresh(0));
+        meta.max_age = Some(1000);
+        assert!(meta.is_fresh(50));
+        meta.max_age = Some(0);
+        assert!(!meta.is_fresh(1000));
+        // Fetched in the future, by a clock that was wrong
+        meta.fetched = now() + 100;
+        meta.max_age = None;
+        assert!(meta.is_fresh(1));
+    }
+
+    fn limited(max_bytes: u64) -> (Arc<MemoryStore>, Arc<LimitedCache>) {
+        let store = Arc::new(MemoryStore::default());
+        let cache = LimitedCache::new(store.clone(), max_bytes, Arc::default());
+        (store, Arc::new(cache))
+    }
+
+    fn entry(size: u64, used: u64) -> IndexEntry {
+        IndexEntry {
+            source: "tile".into(),
+            size,
+            used,
+        }
+    }
+
+    #[test]
+    fn index() {
+        let mut index = Index::default();
+        index.insert("a", entry(10, 1));
+        index.insert("b", entry(20, 1));
+        assert_eq!((index.bytes, index.changes), (30, 2));
+        // Replaced
+        index.insert("a", entry(5, 2));
+        assert_eq!((index.bytes, index.entries.len()), (25, 2));
+        index.remove("b");
+        assert_eq!((index.bytes, index.changes), (5, 4));
+        index.remove("b");
+        assert_eq!((index.bytes, index.changes), (5, 4));
+    }
+
+    #[test]
+    fn evict_least_recently_used() {
+        let mut index = Index::default();
+        for used in [5, 1, 9, 3, 7, 2, 10, 4, 8, 6] {
+            index.insert(&used.to_string(), entry(10, used));
+        }
+        assert!(index.evict(100).is_empty());
+        index.insert("11", entry(10, 11));
+        // Down to 90% of the limit
+        assert_eq!(index.evict(100), ["1", "2"]);
+        assert_eq!(index.bytes, 90);
+        assert!(!index.entries.contains_key("1"));
+        assert!(index.entries.contains_key("3"));
+    }
+
+    #[test]
+    fn evict_from_store() {
+        let (store, cache) = limited(100);
+        for i in 0..11 {
+            block_on(cache.write("tile", &i.to_string(), &[0; 10])).unwrap();
+        }
+        assert_eq!(store.keys().len(), 9);
+        let stats = cache.counters.stats();
+        assert_eq!((stats.bytes, stats.entries), (90, 9));
+    }
+
+    #[test]
+    fn save_index() {
+        let (store, cache) = limited(DEFAULT_CACHE_SIZE);
+        for i in 0..INDEX_SAVE_INTERVAL - 1 {
+            block_on(cache.write("tile", &i.to_string(), b"tile")).unwrap();
+        }
+        assert!(!store.keys().contains(&INDEX_KEY.to_string()));
+        block_on(cache.write("tile", "last", b"tile")).unwrap();
+        assert!(store.keys().contains(&INDEX_KEY.to_string()));
+
+        // The next session
+        let cache = LimitedCache::new(store.clone(), DEFAULT_CACHE_SIZE, Arc::default());
+        assert_eq!(
+            block_on(cache.read("last")).unwrap(),
+            Some(b"tile".to_vec())
+        );
+        let stats = cache.counters.stats();
+        assert_eq!(stats.entries, INDEX_SAVE_INTERVAL as u64);
+        assert_eq!(stats.bytes, 4 * INDEX_SAVE_INTERVAL as u64);
+    }
+
+    #[test]
+    fn clear() {
+        let (store, cache) = limited(DEFAULT_CACHE_SIZE);
+        let tiles = cache.source("tile", "gltiles.osm2world.org/glb/");
+        let raster = cache.source("raster", "{a,b,c}.tile.openstreetmap.org/");
+        for key in ["1.glb", "2.glb", "3.glb"] {
+            block_on(tiles.write(key, b"tile")).unwrap();
+        }
+        for key in ["1.png", "2.png"] {
+            block_on(raster.write(key, b"raster")).unwrap();
+        }
+
+        assert_eq!(block_on(cache.clear(Some("tile"))).unwrap(), 3);
+        assert_eq!(block_on(tiles.read("1.glb")).unwrap(), None);
+        assert_eq!(
+            block_on(raster.read("1.png")).unwrap(),
+            Some(b"raster".to_vec())
+        );
+        assert_eq!(cache.counters.stats().entries, 2);
+
+        assert_eq!(block_on(cache.clear(None)).unwrap(), 2);
+        assert_eq!(store.keys(), [INDEX_KEY]);
+        assert_eq!(cache.counters.stats().bytes, 0);
+    }
+
+    #[test]
+    fn migrate_legacy_server_only() {
+        let (store, cache) = limited(DEFAULT_CACHE_SIZE);
+        // Written by a version with the flat layout
+        block_on(cache.write("tile", "15_1_2.glb", b"legacy")).unwrap();
+
+        // Another server doesn't get it, and doesn't look for it
+        let other = cache.source("tile", "http://localhost:8080/");
+        let reads = store.reads.load(Ordering::Relaxed);
+        assert_eq!(block_on(other.read("15_1_2.glb")).unwrap(), None);
+        assert_eq!(store.reads.load(Ordering::Relaxed), reads + 1);
+        assert!(store.keys().contains(&"15_1_2.glb".to_string()));
+
+        // Nor another source of the same server
+        let default = cache.source("default", LEGACY_SERVER);
+        assert_eq!(block_on(default.read("15_1_2.glb")).unwrap(), None);
+
+        let tiles = cache.source("tile", "https://gltiles.osm2world.org/glb/");
+        assert_eq!(
+            block_on(tiles.read("15_1_2.glb")).unwrap(),
+            Some(b"legacy".to_vec())
+        );
+        assert_eq!(
+            store.keys(),
+            ["v1/tile/gltiles.osm2world.org_glb/15_1_2.glb"]
+        );
+        assert_eq!(block_on(other.read("15_1_2.glb")).unwrap(), None);
+
+        // Misses of other sources don't read the store twice
+        let reads = store.reads.load(Ordering::Relaxed);
+        assert_eq!(block_on(default.read("15_3_4.glb")).unwrap(), None);
+        assert_eq!(store.reads.load(Ordering::Relaxed), reads + 1);
+    }
+
+    #[test]
+    fn migrate_without_index() {
+        // Written by a version without an index
+        let (store, cache) = limited(DEFAULT_CACHE_SIZE);
+        for key in ["15_1_2.glb", "15_3_4.glb"] {
+            block_on(store.write(key, b"legacy")).unwrap();
+        }
+
+        let raster = cache.source("raster", LEGACY_SERVER);
+        assert_eq!(block_on(raster.read("15_1_2.glb")).unwrap(), None);
+        let tiles = cache.source("tile", LEGACY_SERVER);
+        assert_eq!(
+            block_on(tiles.read("15_1_2.glb")).unwrap(),
+            Some(b"legacy".to_vec())
+        );
+        assert_eq!(block_on(tiles.read("15_5_6.glb")).unwrap(), None);
+        assert_eq!(
+            store.keys(),
+            ["15_3_4.glb", "v1/tile/gltiles.osm2world.org_glb/15_1_2.glb"]
+        );
+        // Now counted, and evicted in time
+        let stats = cache.counters.stats();
+        assert_eq!((stats.bytes, stats.entries), (6, 1));
+        block_on(cache.clear(None)).unwrap();
+        assert_eq!(store.keys(), ["15_3_4.glb", INDEX_KEY]);
+    }
+
+    #[test]
+    fn save_on_exit() {
+        let (store, cache) = limited(DEFAULT_CACHE_SIZE);
+        block_on(cache.save()).unwrap();
+        assert!(store.keys().is_empty());
+        block_on(cache.write("tile", "a", b"tile")).unwrap();
+        block_on(cache.save()).unwrap();
+        assert_eq!(store.keys(), ["a", INDEX_KEY]);
+
+        let cache = LimitedCache::new(store.clone(), DEFAULT_CACHE_SIZE, Arc::default());
+        block_on(cache.read("a")).unwrap();
+        assert_eq!(cache.counters.stats().entries, 1);
+    }
+
+    #[test]
+    fn list_disk_cache() {
+        let root = std::env::temp_dir().join(format!("osmeta_list_{}", std::process::id()));
+        let disk = DiskCache { root: root.clone() };
+        assert_eq!(block_on(disk.list()).unwrap(), Some(vec![]));
+        block_on(disk.write("15_1_2.glb", b"legacy")).unwrap();
+        block_on(disk.write("v1/tile/a/1.glb", b"tile")).unwrap();
+        let mut listing = block_on(disk.list()).unwrap().unwrap();
+        listing.sort();
+        assert_eq!(
+            listing,
+            [("15_1_2.glb".into(), 6), ("v1/tile/a/1.glb".into(), 4)]
+        );
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn seal_verify() {
+        let mut meta = CacheMeta::default();
+        // Metadata of older versions has nothing to compare
+        assert!(meta.verify(b"anything"));
+        meta.seal(b"tile");
+        assert_eq!(meta.length, Some(4));
+        assert!(meta.verify(b"tile"));
+        assert!(!meta.verify(b"til"));
+        assert!(!meta.verify(b"tilE"));
+        assert!(!meta.verify(b""));
+    }
+
+    #[test]
+    fn disk_write() {
+        let root = std::env::temp_dir().join(format!("osmeta_write_{}", std::process::id()));
+        let disk = DiskCache { root: root.clone() };
+        block_on(disk.write("a/b.glb", b"old")).unwrap();
+        block_on(disk.write("a/b.glb", b"new")).unwrap();
+        assert_eq!(
+            block_on(disk.read("a/b.glb")).unwrap(),
+            Some(b"new".to_vec())
+        );
+
+        // A failed write, here to a key that is a directory, leaves no file behind
+        std::fs::create_dir(root.join("a/c.glb")).unwrap();
+        assert!(block_on(disk.write("a/c.glb", b"new")).is_err());
+        assert!(root.join("a/c.glb").is_dir());
+        let mut files: Vec<String> = std::fs::read_dir(root.join("a"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into())
+            .collect();
+        files.sort();
+        assert_eq!(files, ["b.glb", "c.glb"]);
+
+        block_on(disk.remove("a/b.glb")).unwrap();
+        block_on(disk.remove("a/b.glb")).unwrap();
+        assert_eq!(block_on(disk.read("a/b.glb")).unwrap(), None);
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn store_and_quarantine() {
+        let (store, _) = limited(DEFAULT_CACHE_SIZE);
+        let meta = CacheMeta::default();
+        block_on(store_entry(&*store, "a.glb", b"tile", Some(&meta))).unwrap();
+        let stored = block_on(read_meta(&*store, "a.glb")).unwrap();
+        assert!(stored.verify(b"tile"));
+
+        block_on(quarantine(&*store, "a.glb", b"til")).unwrap();
+        assert_eq!(store.keys(), ["quarantine/a.glb"]);
+        assert_eq!(
+            block_on(store.read("quarantine/a.glb")).unwrap(),
+            Some(b"til".to_vec())
+        );
+
+        // Files that must not be stored are removed
+        block_on(store_entry(&*store, "b.glb", b"tile", Some(&meta))).unwrap();
+        block_on(store_entry(&*store, "b.glb", b"tile", None)).unwrap();
+        assert_eq!(store.keys(), ["quarantine/a.glb"]);
+    }
+
+    #[test]
+    fn reconcile_with_store() {
+        let store = Arc::new(MemoryStore {
+            listed: true,
+            ..Default::default()
+        });
+        let cache = LimitedCache::new(store.clone(), 100, Arc::default());
+        for key in ["v1/raster/a/1.png", "v1/tile/b/1.glb", "v1/tile/b/2.glb"] {
+            block_on(cache.write(key.split('/').nth(1).unwrap(), key, &[0; 10])).unwrap();
+        }
+        block_on(cache.save()).unwrap();
+        // Changed after the index was stored, and written by older versions
+        block_on(store.remove("v1/tile/b/2.glb")).unwrap();
+        block_on(store.write("v1/tile/b/1.glb", &[0; 20])).unwrap();
+        block_on(store.write("v1/raster/a/2.png", &[0; 10])).unwrap();
+        block_on(store.write("15_1_2.glb", &[0; 10])).unwrap();
+
+        let cache = LimitedCache::new(store.clone(), 100, Arc::default());
+        block_on(cache.read("v1/tile/b/1.glb")).unwrap();
+        let stats = cache.counters.stats();
+        assert_eq!((stats.bytes, stats.entries), (50, 4));
+        {
+            let index = cache.index.lock().unwrap();
+            assert_eq!(index.entries["v1/raster/a/2.png"].source, "raster");
+            assert_eq!(index.entries["15_1_2.glb"].source, "tile");
+            // 4 fixes and the read
+            assert_eq!(index.changes, 5);
+        }
+        // The files of older versions are used least recently
+        block_on(cache.write("raster", "v1/raster/a/3.png", &[0; 60])).unwrap();
+        assert!(!store.keys().contains(&"15_1_2.glb".to_string()));
+        assert_eq!(block_on(cache.clear(Some("tile"))).unwrap(), 1);
+    }
+}
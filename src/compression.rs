@@ -0,0 +1,169 @@
+//! Decompression of downloaded files. The encoding is detected from the content itself
+//! (gzip and zstd have magic bytes) and the `Content-Encoding` of the response (brotli has none).
+//!
+//! Browsers already decompress what the server declared in `Content-Encoding`, but keep
+//! the header. Detecting by content makes that work like a native download.
+
+use flate2::read::GzDecoder;
+use std::io::{self, Read};
+
+/// The encodings to ask the servers for
+pub const ACCEPT_ENCODING: &str = "gzip, br, zstd";
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Identity,
+    Gzip,
+    Brotli,
+    Zstd,
+}
+
+impl Encoding {
+    pub fn detect(content_encoding: Option<&str>, bytes: &[u8]) -> Self {
+        if bytes.starts_with(&GZIP_MAGIC) {
+            Self::Gzip
+        } else if bytes.starts_with(&ZSTD_MAGIC) {
+            Self::Zstd
+        } else if content_encoding.is_some_and(|encoding| encoding.trim() == "br") {
+            Self::Brotli
+        } else {
+            Self::Identity
+        }
+    }
+}
+
+/// Decompress `bytes`, see the module docs. Brotli can't be recognized by its content,
+/// so undecodable data declared as brotli is taken as already decompressed by the browser.
+/// Fails with [`io::ErrorKind::InvalidData`] if the result is bigger than `max` bytes,
+/// without decompressing more than that.
+pub fn decompress(
+    content_encoding: Option<&str>,
+    bytes: Vec<u8>,
+    max: usize,
+) -> io::Result<Vec<u8>> {
+    let mut decoded = vec![];
+    // One byte more than allowed, to notice that there is more
+    let limit = max as u64 + 1;
+    match Encoding::detect(content_encoding, &bytes) {
+        Encoding::Identity => decoded = bytes,
+        Encoding::Gzip => {
+            GzDecoder::new(bytes.as_slice())
+                .take(limit)
+                .read_to_end(&mut decoded)?;
+        }
+        Encoding::Zstd => {
+            ruzstd::StreamingDecoder::new(bytes.as_slice())
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?
+                .take(limit)
+                .read_to_end(&mut decoded)?;
+        }
+        Encoding::Brotli => {
+            let result = brotli_decompressor::Decompressor::new(bytes.as_slice(), 4096)
+                .take(limit)
+                .read_to_end(&mut decoded);
+            if result.is_err() {
+                decoded = bytes;
+            }
+        }
+    }
+    if decoded.len() > max {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("more than {max} bytes after decompression"),
+        ));
+    }
+    Ok(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::{write::GzEncoder, Compression};
+    use std::io::Write;
+
+    /// Brotli of `XXXXXXXXXXYYYYYYYYYY`, there is no brotli encoder in the dependencies
+    const BROTLI: [u8; 12] = [
+        0x1b, 0x13, 0x00, 0x00, 0xa4, 0xb0, 0xb2, 0xea, 0x81, 0x47, 0x02, 0x8a,
+    ];
+    const BROTLI_DECODED: &[u8] = b"XXXXXXXXXXYYYYYYYYYY";
+
+    fn gzip(bytes: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        encoder.write_all(bytes).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    /// A zstd frame of one block, with `len` times `byte`. There is no zstd encoder in the
+    /// dependencies.
+    fn zstd_rle(byte: u8, len: u16) -> Vec<u8> {
+        assert!(len >= 256);
+        let mut frame = ZSTD_MAGIC.to_vec();
+        // Single segment, two bytes content size - 256
+        frame.push(0x60);
+        frame.extend((len - 256).to_le_bytes());
+        // Last block, type RLE, size
+        let header = 1 | 1 << 1 | (len as u32) << 3;
+        frame.extend(&header.to_le_bytes()[..3]);
+        frame.push(byte);
+        frame
+    }
+
+    fn is_too_big(result: io::Result<Vec<u8>>) -> bool {
+        result.is_err_and(|err| err.kind() == io::ErrorKind::InvalidData)
+    }
+
+    #[test]
+    fn detect() {
+        assert_eq!(Encoding::detect(None, &gzip(b"tile")), Encoding::Gzip);
+        assert_eq!(Encoding::detect(Some("br"), &gzip(b"tile")), Encoding::Gzip);
+        assert_eq!(Encoding::detect(None, &zstd_rle(0, 300)), Encoding::Zstd);
+        assert_eq!(Encoding::detect(Some(" br "), &BROTLI), Encoding::Brotli);
+        assert_eq!(Encoding::detect(None, &BROTLI), Encoding::Identity);
+        assert_eq!(Encoding::detect(Some("gzip"), b"glTF"), Encoding::Identity);
+    }
+
+    #[test]
+    fn identity() {
+        assert_eq!(decompress(None, b"glTF".to_vec(), 4).unwrap(), b"glTF");
+        assert!(is_too_big(decompress(None, b"glTF".to_vec(), 3)));
+    }
+
+    #[test]
+    fn gzip_bomb() {
+        let tile = b"glTF tile".repeat(100);
+        assert_eq!(decompress(None, gzip(&tile), tile.len()).unwrap(), tile);
+        let bomb = gzip(&vec![0; 10 * 1024 * 1024]);
+        assert!(bomb.len() < 100 * 1024);
+        assert!(is_too_big(decompress(Some("gzip"), bomb, 1024 * 1024)));
+    }
+
+    #[test]
+    fn zstd_bomb() {
+        assert_eq!(
+            decompress(None, zstd_rle(b'a', 1000), 1000).unwrap(),
+            vec![b'a'; 1000]
+        );
+        assert!(is_too_big(decompress(None, zstd_rle(b'a', 1000), 999)));
+        assert!(decompress(None, ZSTD_MAGIC.to_vec(), 1000).is_err());
+    }
+
+    #[test]
+    fn brotli() {
+        assert_eq!(
+            decompress(Some("br"), BROTLI.to_vec(), 20).unwrap(),
+            BROTLI_DECODED
+        );
+        assert!(is_too_big(decompress(Some("br"), BROTLI.to_vec(), 19)));
+    }
+
+    #[test]
+    fn brotli_fallback() {
+        // Already decompressed by the browser, which keeps the header
+        let tile = b"glTF tile".to_vec();
+        assert_eq!(decompress(Some("br"), tile.clone(), 100).unwrap(), tile);
+        assert!(is_too_big(decompress(Some("br"), tile, 8)));
+    }
+}
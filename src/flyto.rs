@@ -0,0 +1,466 @@
+//! Animated transitions between two [`GeoView`]s, instead of teleporting the camera.
+//!
+//! The flight follows the great circle between the two places. On the way the camera
+//! climbs up to get an overview and descends at the target (zoom out, travel, zoom in).
+//! Any key, mouse button or wheel input of the user cancels the flight where it is,
+//! except the keys of the tours and the stored views, which start flights themselves.
+//!
+//! To start a flight, send a [`FlyToRequest`] event.
+
+use bevy::input::mouse::MouseWheel;
+use bevy::math::DVec3;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::f32::consts::PI;
+
+use crate::geocoord::{GeoCoord, EARTH_RADIUS};
+use crate::geoview::GeoView;
+use crate::player::{ControlValues, PlayerQuery};
+
+/// How the progress of an animation is distributed over the time
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum Easing {
+    Linear,
+    /// Slow start and slow end (cubic)
+    #[default]
+    EaseInOut,
+    /// Like [`Easing::EaseInOut`], but softer
+    EaseInOutSine,
+}
+
+impl Easing {
+    /// Maps the time ratio `t` (0..=1) to the progress ratio (0..=1)
+    pub fn apply(self, t: f32) -> f32 {
+        let t = t.clamp(0., 1.);
+        match self {
+            Easing::Linear => t,
+            Easing::EaseInOut => {
+                if t < 0.5 {
+                    4. * t * t * t
+                } else {
+                    1. - (-2. * t + 2.).powi(3) / 2.
+                }
+            }
+            Easing::EaseInOutSine => -((PI * t).cos() - 1.) / 2.,
+        }
+    }
+}
+
+/// Send this event to let the camera fly to a view
+#[derive(Event, Clone, Copy)]
+pub struct FlyToRequest {
+    pub view: GeoView,
+    /// Flight time in seconds. If `None`, it is calculated by the distance.
+    pub duration: Option<f32>,
+    pub easing: Easing,
+}
+
+impl FlyToRequest {
+    pub fn new(view: GeoView) -> Self {
+        Self {
+            view,
+            duration: None,
+            easing: Easing::default(),
+        }
+    }
+}
+
+/// The flight in progress, if any
+#[derive(Resource, Default)]
+pub struct Flight {
+    pub active: Option<FlightPath>,
+    /// The last flight was stopped by the user
+    pub cancelled: bool,
+}
+
+impl Flight {
+    pub fn start(&mut self, path: FlightPath) {
+        self.active = Some(path);
+        self.cancelled = false;
+    }
+}
+
+pub struct FlightPath {
+    pub start: GeoView,
+    pub target: GeoView,
+    pub duration: f32,
+    pub elapsed: f32,
+    pub easing: Easing,
+    /// Additional elevation in the middle of the flight
+    pub arc_height: f32,
+}
+
+impl FlightPath {
+    pub fn new(start: GeoView, target: GeoView, duration: Option<f32>, easing: Easing) -> Self {
+        let distance = great_circle_distance(start.geo_coord, target.geo_coord);
+        // Climb up about a half of the way. Then, the start and the target are both in the view.
+        let arc_height = distance * 0.5;
+        // Far away places need longer, but even a flight around the world should not be boring.
+        let duration = duration.unwrap_or_else(|| (1.0 + (distance / 1000.).sqrt() / 10.).min(8.0));
+        Self {
+            start,
+            target,
+            duration,
+            elapsed: 0.,
+            easing,
+            arc_height,
+        }
+    }
+
+    pub fn finished(&self) -> bool {
+        self.elapsed >= self.duration
+    }
+
+    /// The view at the actual time of the flight
+    pub fn view(&self) -> GeoView {
+        let t = if self.duration > 0. {
+            self.elapsed / self.duration
+        } else {
+            1.
+        };
+        interpolate(
+            self.start,
+            self.target,
+            self.easing.apply(t),
+            self.arc_height,
+        )
+    }
+}
+
+/// The view at the ratio `t` (0..=1) between `a` and `b`, lifted by an arc of `arc_height`.
+/// Exactly `a` and `b` at the ends, without rounding errors.
+pub fn interpolate(a: GeoView, b: GeoView, t: f32, arc_height: f32) -> GeoView {
+    if t <= 0. {
+        return a;
+    }
+    if t >= 1. {
+        return b;
+    }
+    GeoView {
+        geo_coord: slerp_geo_coord(a.geo_coord, b.geo_coord, t),
+        elevation: lerp(a.elevation, b.elevation, t) + arc_height * (PI * t).sin(),
+        direction: lerp_angle(a.direction, b.direction, t),
+        up_view: lerp(a.up_view, b.up_view, t),
+        distance: lerp(a.distance, b.distance, t),
+        camera_fov: lerp(a.camera_fov, b.camera_fov, t),
+    }
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+/// Interpolate degrees the short way around
+fn lerp_angle(a: f32, b: f32, t: f32) -> f32 {
+    let delta = (b - a + 180.).rem_euclid(360.) - 180.;
+    a + delta * t
+}
+
+/// A unit vector, pointing from the planet center to the geo coordinates
+fn unit_vector(geo_coord: GeoCoord) -> DVec3 {
+    let lat = (geo_coord.lat as f64).to_radians();
+    let lon = (geo_coord.lon as f64).to_radians();
+    DVec3::new(lat.cos() * lon.cos(), lat.cos() * lon.sin(), lat.sin())
+}
+
+fn great_circle_distance(a: GeoCoord, b: GeoCoord) -> f32 {
+    (unit_vector(a).angle_between(unit_vector(b)) * EARTH_RADIUS as f64) as f32
+}
+
+/// Spherical interpolation along the great circle, exactly `a` and `b` at the ends
+pub fn slerp_geo_coord(a: GeoCoord, b: GeoCoord, t: f32) -> GeoCoord {
+    if t <= 0. {
+        return a;
+    }
+    let (va, vb) = (unit_vector(a), unit_vector(b));
+    let angle = va.angle_between(vb);
+    if angle < 1e-9 || t >= 1. {
+        return b;
+    }
+    let sin = angle.sin();
+    if sin.abs() < 1e-9 {
+        // Exactly the other side of the planet: any great circle is fine, use the lon/lat one
+        return GeoCoord {
+            lat: lerp(a.lat, b.lat, t),
+            lon: lerp_angle(a.lon, b.lon, t),
+        };
+    }
+    let t = t as f64;
+    let v = va * (((1. - t) * angle).sin() / sin) + vb * ((t * angle).sin() / sin);
+    GeoCoord {
+        lat: v.z.clamp(-1., 1.).asin().to_degrees() as f32,
+        lon: v.y.atan2(v.x).to_degrees() as f32,
+    }
+}
+
+/// Keys that control the tours and the stored views, not the camera
+fn controls_flights(key: KeyCode) -> bool {
+    matches!(
+        key,
+        KeyCode::KeyK
+            | KeyCode::KeyL
+            | KeyCode::ShiftLeft
+            | KeyCode::ShiftRight
+            | KeyCode::Digit0
+            | KeyCode::Digit1
+            | KeyCode::Digit2
+            | KeyCode::Digit3
+            | KeyCode::Digit4
+            | KeyCode::Digit5
+            | KeyCode::Digit6
+            | KeyCode::Digit7
+            | KeyCode::Digit8
+            | KeyCode::Digit9
+    )
+}
+
+/// Any input of the user stops the flight
+fn cancel_flight_on_input(
+    keys: Res<ButtonInput<KeyCode>>,
+    mouse_input: Res<ButtonInput<MouseButton>>,
+    mut scroll_events: EventReader<MouseWheel>,
+    mut flight: ResMut<Flight>,
+) {
+    let scrolled = scroll_events.read().count() > 0;
+    if flight.active.is_none() {
+        return;
+    }
+    let key = keys.get_just_pressed().any(|key| !controls_flights(*key));
+    if key || mouse_input.get_just_pressed().len() > 0 || scrolled {
+        debug!("flight cancelled");
+        flight.active = None;
+        flight.cancelled = true;
+    }
+}
+
+fn start_flight(
+    mut requests: EventReader<FlyToRequest>,
+    mut flight: ResMut<Flight>,
+    control_values: Res<ControlValues>,
+    player: PlayerQuery,
+) {
+    for request in requests.read() {
+        let start = match &flight.active {
+            Some(path) => path.view(),
+            None => GeoView::from_control(&player, &control_values),
+        };
+        flight.start(FlightPath::new(
+            start,
+            request.view,
+            request.duration,
+            request.easing,
+        ));
+    }
+}
+
+fn update_flight(
+    time: Res<Time>,
+    mut flight: ResMut<Flight>,
+    mut player: PlayerQuery,
+    mut control_values: ResMut<ControlValues>,
+) {
+    let Some(path) = &mut flight.active else {
+        return;
+    };
+    path.elapsed += time.delta_seconds();
+    if path.finished() {
+        path.target
+            .set_camera_view(&mut player, &mut control_values);
+        flight.active = None;
+    } else {
+        path.view()
+            .set_camera_view(&mut player, &mut control_values);
+    }
+}
+
+pub struct Plugin;
+
+impl bevy::prelude::Plugin for Plugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Flight>()
+            .add_event::<FlyToRequest>()
+            .add_systems(
+                Update,
+                (cancel_flight_on_input, start_flight, update_flight).chain(),
+            );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn view(lat: f32, lon: f32) -> GeoView {
+        GeoView {
+            geo_coord: GeoCoord { lat, lon },
+            ..default()
+        }
+    }
+
+    #[test]
+    fn easing() {
+        for easing in [Easing::Linear, Easing::EaseInOut, Easing::EaseInOutSine] {
+            assert_eq!(easing.apply(0.), 0., "{easing:?}");
+            assert_eq!(easing.apply(1.), 1., "{easing:?}");
+            assert_eq!(easing.apply(-1.), 0., "{easing:?}");
+            assert_eq!(easing.apply(2.), 1., "{easing:?}");
+            assert!((easing.apply(0.5) - 0.5).abs() < 1e-6, "{easing:?}");
+        }
+        assert!(Easing::EaseInOut.apply(0.1) < Easing::Linear.apply(0.1));
+    }
+
+    #[test]
+    fn endpoints() {
+        let start = GeoView {
+            elevation: 300.,
+            direction: 350.,
+            ..view(48.1408, 11.5577)
+        };
+        let target = GeoView {
+            elevation: 2.,
+            direction: 10.,
+            camera_fov: 30.,
+            ..view(-33.8568, 151.2153)
+        };
+        let mut path = FlightPath::new(start, target, None, Easing::EaseInOut);
+        assert_eq!(path.view(), start);
+        path.elapsed = path.duration / 2.;
+        let middle = path.view();
+        assert!(middle.elevation > path.arc_height * 0.9, "{middle:?}");
+        // The short way around
+        assert!(
+            middle.direction > 355. && middle.direction < 365.,
+            "{middle:?}"
+        );
+        path.elapsed = path.duration;
+        assert!(path.finished());
+        assert_eq!(path.view(), target);
+
+        let a = GeoCoord { lat: 10., lon: 20. };
+        let b = GeoCoord { lat: -5., lon: 25. };
+        assert_eq!(slerp_geo_coord(a, b, 0.), a);
+        assert_eq!(slerp_geo_coord(a, b, 1.), b);
+        assert_eq!(slerp_geo_coord(a, a, 0.5), a);
+    }
+
+    #[test]
+    fn antimeridian() {
+        let a = GeoCoord { lat: 0., lon: 170. };
+        let b = GeoCoord {
+            lat: 0.,
+            lon: -170.,
+        };
+        // Over the antimeridian, not around the world
+        let middle = slerp_geo_coord(a, b, 0.5);
+        assert!(middle.lon.abs() > 179.9, "{middle:?}");
+        assert!(middle.lat.abs() < 1e-3, "{middle:?}");
+        let quarter = slerp_geo_coord(a, b, 0.25);
+        assert!((quarter.lon - 175.).abs() < 1e-3, "{quarter:?}");
+        let distance = great_circle_distance(a, b);
+        assert!(
+            (distance - EARTH_RADIUS * 20f32.to_radians()).abs() < 10.,
+            "{distance}"
+        );
+
+        // The other side of the planet
+        let c = GeoCoord { lat: 0., lon: -10. };
+        let middle = slerp_geo_coord(a, c, 0.5);
+        assert!(middle.lat.is_finite() && middle.lon.is_finite());
+    }
+
+    #[test]
+    fn duration() {
+        let munich = view(48.1408, 11.5577);
+        let sydney = view(-33.8568, 151.2153);
+        assert_eq!(
+            FlightPath::new(munich, sydney, None, default()).duration,
+            8.
+        );
+        let here = FlightPath::new(munich, munich, None, default()).duration;
+        assert!((here - 1.).abs() < 0.01, "{here}");
+        let near = FlightPath::new(munich, view(48.2, 11.6), None, default()).duration;
+        assert!(near > 1. && near < 2., "{near}");
+        // Given durations are kept
+        assert_eq!(
+            FlightPath::new(munich, sydney, Some(20.), default()).duration,
+            20.
+        );
+        let instant = FlightPath::new(munich, sydney, Some(0.), default());
+        assert!(instant.finished());
+        assert_eq!(instant.view(), sydney);
+    }
+
+    fn app() -> App {
+        let mut app = App::new();
+        app.init_resource::<ButtonInput<KeyCode>>()
+            .init_resource::<ButtonInput<MouseButton>>()
+            .add_event::<MouseWheel>()
+            .init_resource::<Flight>()
+            .add_systems(Update, cancel_flight_on_input);
+        app
+    }
+
+    fn fly(app: &mut App) {
+        let path = FlightPath::new(view(0., 0.), view(1., 1.), None, default());
+        app.world.resource_mut::<Flight>().start(path);
+    }
+
+    fn cancelled(app: &App) -> bool {
+        let flight = app.world.resource::<Flight>();
+        assert_eq!(flight.active.is_none(), flight.cancelled);
+        flight.cancelled
+    }
+
+    #[test]
+    fn cancellation() {
+        let mut app = app();
+        fly(&mut app);
+        app.update();
+        assert!(!cancelled(&app));
+
+        // The keys of the tours and the stored views don't
+        for key in [KeyCode::KeyK, KeyCode::ShiftLeft, KeyCode::Digit1] {
+            app.world.resource_mut::<ButtonInput<KeyCode>>().press(key);
+            app.update();
+            app.world.resource_mut::<ButtonInput<KeyCode>>().reset_all();
+            assert!(!cancelled(&app), "{key:?}");
+        }
+
+        app.world
+            .resource_mut::<ButtonInput<KeyCode>>()
+            .press(KeyCode::KeyW);
+        app.update();
+        assert!(cancelled(&app));
+        app.world.resource_mut::<ButtonInput<KeyCode>>().reset_all();
+
+        // Only just pressed keys
+        app.world
+            .resource_mut::<ButtonInput<KeyCode>>()
+            .press(KeyCode::KeyA);
+        app.world
+            .resource_mut::<ButtonInput<KeyCode>>()
+            .clear_just_pressed(KeyCode::KeyA);
+        fly(&mut app);
+        app.update();
+        assert!(!cancelled(&app));
+
+        app.world
+            .resource_mut::<ButtonInput<MouseButton>>()
+            .press(MouseButton::Left);
+        app.update();
+        assert!(cancelled(&app));
+        app.world
+            .resource_mut::<ButtonInput<MouseButton>>()
+            .reset_all();
+
+        fly(&mut app);
+        app.world.send_event(MouseWheel {
+            unit: bevy::input::mouse::MouseScrollUnit::Line,
+            x: 0.,
+            y: 1.,
+            window: Entity::PLACEHOLDER,
+        });
+        app.update();
+        assert!(cancelled(&app));
+    }
+}
@@ -1,17 +1,23 @@
 use super::geocoord::*;
 use super::GalacticTransformOwned;
+use crate::bookmarks::Bookmark;
+use crate::flyto::FlyToRequest;
 use crate::player::{
-    CamControlMode, ControlValues, PlayerGalacticTransform, PlayerQuery, OSM_LAT_LIMIT,
+    CamControlMode, ControlValues, PlanetaryPosition, PlayerGalacticTransform, PlayerQuery,
+    OSM_LAT_LIMIT,
 };
 use bevy::{
+    math::DVec3,
     prelude::*,
     utils::tracing::{self, instrument},
 };
+use serde::{Deserialize, Serialize};
 use std::{collections::HashMap, f32::consts::FRAC_PI_2};
 
-#[derive(Resource)]
+/// All stored views (bookmarks) by their id
+#[derive(Resource, Default)]
 pub struct Views {
-    pub map: HashMap<String, String>,
+    pub map: HashMap<String, Bookmark>,
 }
 
 /**
@@ -26,13 +32,15 @@ pub struct Views {
  *
  * The GPU scene uses it internal to read and set the browser url.
  */
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub struct GeoView {
     pub geo_coord: GeoCoord, // lat/lon
     pub elevation: f32,
     pub direction: f32,
     pub up_view: f32,
-    pub distance: f32, // todo: use distance and camera_fov (by ArcControl)
+    /// Distance of the orbiting camera to the view point (F4 control only)
+    pub distance: f32,
+    /// Vertical field of view in degrees
     pub camera_fov: f32,
 }
 
@@ -49,6 +57,10 @@ impl Default for GeoView {
     }
 }
 
+/// Field of view limits in degrees
+pub const FOV_MIN: f32 = 5.0;
+pub const FOV_MAX: f32 = 120.0;
+
 impl GeoView {
     pub fn limit(&mut self) {
         const ELEVATION_LIMIT: f32 = 20_000_000_000.0; // meter
@@ -56,71 +68,62 @@ impl GeoView {
         self.up_view = self.up_view.clamp(-OSM_LAT_LIMIT, OSM_LAT_LIMIT);
         self.elevation = self.elevation.clamp(0.4, ELEVATION_LIMIT);
         self.distance = self.distance.clamp(0.4, ELEVATION_LIMIT);
+        self.camera_fov = self.camera_fov.clamp(FOV_MIN, FOV_MAX);
         self.direction %= 360.0;
     }
 
     /**
-     * Store self GeoView in a browser cookie
+     * Store self GeoView in the views map
      * To restore it into your viewer, use [[GeoView]].[[restore]]
-     * internal, util [[restore]] is called.
-     * @param id  "name" of the cookie
+     * To keep it over the next start, use [[Views]].[[save]]
+     * @param id  "name" of the view
      */
-    pub fn store(&self, id: KeyCode, views_map: &mut HashMap<String, String>) {
-        // todo: Add a name for the view
-        //                                      id la lo he di vi ra fo
-        //t cookie = format!("OSM2World_GeoView_{}={} {} {} {} {} {} {};samesite=strict",  //  todo? {:.2}
-        let id_string = format!("{:?}", id).to_string();
-        let cookie = format!(
-            "{} {} {} {} {} {} {}",
-            self.geo_coord.lat,
-            self.geo_coord.lon,
-            self.elevation,
-            self.direction, // alpha, compas
-            self.up_view,   // beta, headupdown
-            self.distance,
-            self.camera_fov,
-        );
-        println!(">>> id: {} cookie: {}", id_string, cookie);
+    pub fn store(&self, id: KeyCode, views_map: &mut HashMap<String, Bookmark>) {
+        self.store_bookmark(format!("{:?}", id), None, None, views_map);
+    }
 
-        // html/wasm: document.cookie = cookie;
-        views_map.insert(id_string, cookie);
+    /// Store self GeoView as bookmark `id`. A replaced bookmark keeps its name and
+    /// description, unless new ones are given.
+    pub fn store_bookmark(
+        &self,
+        id: String,
+        name: Option<String>,
+        description: Option<String>,
+        views_map: &mut HashMap<String, Bookmark>,
+    ) {
+        info!(">>> id: {} view: {:?}", id, self);
+        let mut bookmark = Bookmark::new(id.clone(), *self);
+        if let Some(old) = views_map.get(&id) {
+            bookmark.name = old.name.clone();
+            bookmark.description = old.description.clone();
+        }
+        if let Some(name) = name {
+            bookmark.name = name;
+        }
+        if let Some(description) = description {
+            bookmark.description = description;
+        }
+        views_map.insert(id, bookmark);
     }
 
     /**
-     * restore this geo pos from browser cookie
-     * @param id  "name" of the cookie to restore it
+     * restore this geo pos from the views map
+     * @param id  "name" of the view to restore it
      * @return restored GeoView
      */
-    pub fn restore(id: String, views: &mut HashMap<String, String>) -> Option<GeoView> {
-        let cookie = views.get(&id); //.unwrap();//_or(&or);
-
-        if let Some(cookie) = cookie {
-            println!("<<< id: {} cookie: {}", id, cookie);
-
-            let floats: Vec<&str> = cookie.split(' ').collect();
-
-            let geo_coord = GeoCoord {
-                lat: (floats[0]).parse().unwrap(),
-                lon: (floats[1]).parse().unwrap(),
-            };
-
-            Some(GeoView {
-                geo_coord,
-                elevation: (floats[2]).parse().unwrap(),
-                direction: (floats[3]).parse().unwrap(),
-                up_view: (floats[4]).parse().unwrap(),
-                distance: (floats[5]).parse().unwrap(),
-                camera_fov: (floats[6]).parse().unwrap(),
-            })
-        } else {
-            None
-        }
+    pub fn restore(id: String, views: &HashMap<String, Bookmark>) -> Option<GeoView> {
+        let bookmark = views.get(&id)?;
+        info!(
+            "<<< id: {} name: {} view: {:?}",
+            id, bookmark.name, bookmark.view
+        );
+        Some(bookmark.view)
     }
 
     pub fn to_galactic_transform(self, use_distance: bool) -> GalacticTransformOwned {
         // Position on Earth ground
         let p_starting_transform: PlayerGalacticTransform =
-            self.geo_coord.to_cartesian().to_galactic_transform_space();
+            PlanetaryPosition::from(self.geo_coord).to_galactic_transform_space();
 
         let directions = p_starting_transform.directions();
 
@@ -168,49 +171,139 @@ impl GeoView {
         player.set_pos(galactic_transform);
     }
 
+    /// The actual view of the camera. The orbit control (F4) looks at a point in front
+    /// of the camera, other controls are at the view point.
+    pub fn from_control(player: &PlayerQuery, control_values: &ControlValues) -> Self {
+        if control_values.cam_control_mode == CamControlMode::F4 {
+            GeoView::from_player(player, Some(control_values.view.elevation))
+        } else {
+            let mut geo_view = GeoView::from_player(player, None);
+            geo_view.distance = control_values.view.distance; // keep distance of orbid control
+            geo_view
+        }
+    }
+
+    /// This view with the fields of the JSON object `update` replaced. Nested objects are
+    /// merged too, so `{"geo_coord": {"lat": 48}}` keeps the longitude.
+    pub fn merged(&self, update: serde_json::Value) -> serde_json::Result<Self> {
+        let mut merged = serde_json::to_value(self)?;
+        merge_json(&mut merged, update);
+        serde_json::from_value(merged)
+    }
+
+    /// Calculate the view from the player (camera) position and rotation.
+    /// This is the reverse of [[to_galactic_transform]].
+    /// @param orbit_elevation  If the camera orbits a point (F4 control), the elevation of that point.
+    ///                         The distance to it is calculated by the view direction of the camera.
     #[instrument(level = "debug", skip(player), ret)]
-    pub fn from_player(player: &PlayerQuery) -> Self {
-        let position = player.pos();
+    pub fn from_player(player: &PlayerQuery, orbit_elevation: Option<f32>) -> Self {
+        Self::from_transform(
+            player.pos().galactic_transform,
+            player.fov(),
+            orbit_elevation,
+        )
+    }
+
+    /// The view of a camera at `position`, see [[from_player]].
+    pub fn from_transform(
+        position: GalacticTransformOwned,
+        camera_fov: f32,
+        orbit_elevation: Option<f32>,
+    ) -> Self {
+        let camera_spot = position.position_double();
+        let forward = position.transform.forward();
+
+        let distance = match orbit_elevation {
+            Some(elevation) => {
+                orbit_distance(camera_spot, forward.as_dvec3(), elevation).unwrap_or_else(|| {
+                    // Looking into the sky: There is no point to orbit around, so use the ground below
+                    (camera_spot.length() - (EARTH_RADIUS + elevation) as f64).max(0.)
+                })
+            }
+            None => 0.,
+        };
+        // The point the camera looks at, or the camera itself
+        let view_spot = PlanetaryPosition {
+            pos: camera_spot + forward.as_dvec3() * distance,
+        };
 
-        let geo_coord = position.to_planetary_position().to_geocoord();
-        let elevation = position.position_double().length() as f32 - crate::geocoord::EARTH_RADIUS;
+        let geo_coord = view_spot.to_geocoord();
+        let elevation = view_spot.length() as f32 - EARTH_RADIUS;
 
-        let forward = position.galactic_transform.transform.forward();
-        let directions = position.directions();
+        let directions = view_spot.directions();
         let up_view = (forward.angle_between(-directions.up) - FRAC_PI_2).to_degrees();
 
         // we have to "rotate back the up" before calculating delta north
         let flat_forward = directions
             .up // rotate back up ?    https://en.wikipedia.org/wiki/Cross_product   cross product or vector product
-            .cross(*position.galactic_transform.transform.right()); // now we have a vector pointing forward, but parallel to the ground.
-
-        // Cannot use `angle_between` naively, as that gives us a positive angle between 0 and 180 degrees
-        let north_angle = flat_forward.angle_between(directions.north).to_degrees();
-        let west_angle = flat_forward.angle_between(directions.west).to_degrees();
-        // So we pick a positive or negative angle depending on how far away from west we are.
-        let direction = if west_angle < FRAC_PI_2 {
-            north_angle
-        } else {
-            -north_angle
-        };
+            .cross(*position.transform.right()); // now we have a vector pointing forward, but parallel to the ground.
+
+        // Cannot use `angle_between` naively, as that gives us a positive angle between 0 and 180 degrees.
+        // The signed angle around the up axis is the reverse of `rotate_axis` in [[to_galactic_transform]].
+        let direction = directions
+            .north
+            .cross(flat_forward)
+            .dot(directions.up)
+            .atan2(directions.north.dot(flat_forward))
+            .to_degrees();
 
         Self {
             geo_coord,
             elevation,
             direction,
             up_view,
-            distance: 0.0,
-            camera_fov: 77.,
+            distance: distance as f32,
+            camera_fov,
         }
     }
 }
 
+fn merge_json(target: &mut serde_json::Value, update: serde_json::Value) {
+    use serde_json::Value;
+    match (target, update) {
+        (Value::Object(target), Value::Object(update)) => {
+            for (key, value) in update {
+                match target.get_mut(&key) {
+                    Some(field) => merge_json(field, value),
+                    None => {
+                        target.insert(key, value);
+                    }
+                }
+            }
+        }
+        (target, update) => *target = update,
+    }
+}
+
+/// Distance from the camera to the sphere of the orbit point at `elevation`, along `forward`.
+/// None, if the camera does not look at that sphere.
+fn orbit_distance(camera_spot: DVec3, forward: DVec3, elevation: f32) -> Option<f64> {
+    let radius = (EARTH_RADIUS + elevation) as f64;
+    // Solve |camera_spot + forward * distance| = radius, forward is normalized
+    let b = camera_spot.dot(forward);
+    let c = camera_spot.length_squared() - radius * radius;
+    let discriminant = b * b - c;
+    if discriminant < 0. {
+        return None;
+    }
+    // Outside of the sphere, the camera looks at its near side. Inside (looking up to the orbit
+    // point), the sphere is hit from within, at the far root only.
+    let distance = if c < 0. {
+        -b + discriminant.sqrt()
+    } else {
+        -b - discriminant.sqrt()
+    };
+    (distance >= 0.).then_some(distance)
+}
+
 // System: If keys pressed, store and restore camera views
 fn keys_ui(
     keys: Res<ButtonInput<KeyCode>>,
-    mut player: PlayerQuery,
-    mut control_values: ResMut<ControlValues>,
+    starting_values: Res<crate::StartingValues>,
+    player: PlayerQuery,
+    control_values: Res<ControlValues>,
     mut views: ResMut<Views>,
+    mut fly_to: EventWriter<FlyToRequest>,
 ) {
     {
         for key in keys.get_just_pressed() {
@@ -233,20 +326,16 @@ fn keys_ui(
                         info!("*** KEY: {:?}", key_string);
                         if key != KeyCode::Digit0 {
 
-                            let is_orbit_control = control_values.cam_control_mode == CamControlMode::F4;
-                            let mut geo_view = if is_orbit_control {
-                                control_values.view
-                            } else {
-                                GeoView::from_player(&player)
-                            };
-                            geo_view.distance = control_values.view.distance; // keep distance of orbid control
+                            let geo_view = GeoView::from_control(&player, &control_values);
                             geo_view.store(key, &mut views.map);
+                            views.save();
+                            export(&views, &starting_values);
                         }
                     } else {
                         info!("*** key: {:?}", key_string);
-                        let view3 = GeoView::restore(key_string, &mut views.map);
+                        let view3 = GeoView::restore(key_string, &views.map);
                         if let Some(view3) = view3 {
-                            view3.set_camera_view(&mut player, &mut control_values);
+                            fly_to.send(FlyToRequest::new(view3));
                         }
                     }
                 }
@@ -256,6 +345,19 @@ fn keys_ui(
     }
 }
 
+/// Write all bookmarks to the `export=` file, if there is one
+fn export(views: &Views, starting_values: &crate::StartingValues) {
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(path) = &starting_values.export {
+        match views.export(std::path::Path::new(path)) {
+            Ok(()) => info!("exported {} bookmarks to {path}", views.map.len()),
+            Err(err) => error!("{path}: {err}"),
+        }
+    }
+    #[cfg(target_arch = "wasm32")]
+    let _ = (views, starting_values);
+}
+
 fn keys_ui_setup(
     starting_values: Res<crate::StartingValues>,
     mut player: PlayerQuery,
@@ -265,6 +367,17 @@ fn keys_ui_setup(
     // The start view is placed for Key0 and
     // all controls are set here (also the camera)
     starting_values.view.store(KeyCode::Digit0, &mut views.map);
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(path) = &starting_values.import {
+        match views.import(std::path::Path::new(path)) {
+            Ok(count) => {
+                info!("imported {count} bookmarks from {path}");
+                views.save();
+            }
+            Err(err) => error!("{path}: {err}"),
+        }
+    }
+    export(&views, &starting_values);
     starting_values
         .view
         .set_camera_view(&mut player, &mut control_values);
@@ -277,37 +390,121 @@ impl bevy::prelude::Plugin for Plugin {
         // todo: Is there a OnKeyPressed instead of Update?
         // todo: the reaction is bad? Mayh be this helps: Pairing with bevy_framepace to smooth out input latency
         app.add_systems(Update, keys_ui);
-        let mut map = HashMap::new();
+        // Persisted views of the last session. The test keys are only set, if not stored by the user
+        let mut views = Views::load();
 
         // Test key 9: About the initial View at Munic
-        GeoView {
-            geo_coord: GeoCoord {
-                lat: 48.1408,
-                lon: 11.5577,
-            },
-            up_view: -30.0,
-            elevation: 1.4,
-            distance: 500.,
-            ..Default::default()
+        if !views.map.contains_key("Digit9") {
+            GeoView {
+                geo_coord: GeoCoord {
+                    lat: 48.1408,
+                    lon: 11.5577,
+                },
+                up_view: -30.0,
+                elevation: 1.4,
+                distance: 500.,
+                ..Default::default()
+            }
+            .store(KeyCode::Digit9, &mut views.map);
         }
-        .store(KeyCode::Digit9, &mut map);
 
         // Test key 8: View below the clouds at Munic
-        GeoView {
-            geo_coord: GeoCoord {
-                lat: 48.1408,
-                lon: 11.5577,
-            },
-            up_view: -OSM_LAT_LIMIT,
-            elevation: 1.4,
-            distance: 4000.0,
-            ..Default::default()
+        if !views.map.contains_key("Digit8") {
+            GeoView {
+                geo_coord: GeoCoord {
+                    lat: 48.1408,
+                    lon: 11.5577,
+                },
+                up_view: -OSM_LAT_LIMIT,
+                elevation: 1.4,
+                distance: 4000.0,
+                ..Default::default()
+            }
+            .store(KeyCode::Digit8, &mut views.map);
         }
-        .store(KeyCode::Digit8, &mut map);
 
-        app.insert_resource(Views { map });
+        app.insert_resource(views);
         app.add_systems(PostStartup, keys_ui_setup);
     }
 }
 
 // Dodo?: implement old code: pub fn to_camera_view(&self, osm_scene: &OsmScene) -> CameraView {
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_round_trip(view: GeoView, orbit: bool) {
+        let transform = view.to_galactic_transform(orbit);
+        let mut back =
+            GeoView::from_transform(transform, view.camera_fov, orbit.then_some(view.elevation));
+        if !orbit {
+            // Other controls keep the distance of the control values
+            back.distance = view.distance;
+        }
+        let close = |a: f32, b: f32, tolerance: f32| (a - b).abs() < tolerance;
+        assert!(
+            close(back.geo_coord.lat, view.geo_coord.lat, 1e-4)
+                && close(back.geo_coord.lon, view.geo_coord.lon, 1e-4)
+                && close(back.elevation, view.elevation, 1.)
+                && close(back.direction, view.direction, 0.01)
+                && close(back.up_view, view.up_view, 0.01)
+                && close(back.distance, view.distance, 1.)
+                && back.camera_fov == view.camera_fov,
+            "orbit {orbit}: {view:?} came back as {back:?}"
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        for up_view in [-60., -30., 0., 20., 45.] {
+            let view = GeoView {
+                geo_coord: GeoCoord {
+                    lat: 48.1,
+                    lon: 11.5,
+                },
+                elevation: 300.,
+                direction: 30.,
+                up_view,
+                distance: 500.,
+                camera_fov: 42.,
+            };
+            assert_round_trip(view, true);
+            assert_round_trip(view, false);
+        }
+    }
+
+    #[test]
+    fn merged() {
+        let view = GeoView::default();
+        let update = serde_json::json!({ "geo_coord": { "lat": 48.5 }, "elevation": 100. });
+        let merged = view.merged(update).unwrap();
+        assert_eq!(
+            merged,
+            GeoView {
+                geo_coord: GeoCoord {
+                    lat: 48.5,
+                    lon: view.geo_coord.lon
+                },
+                elevation: 100.,
+                ..view
+            }
+        );
+        assert_eq!(view.merged(serde_json::json!({})).unwrap(), view);
+        assert!(view
+            .merged(serde_json::json!({ "geo_coord": { "lat": "north" } }))
+            .is_err());
+        assert!(view.merged(serde_json::json!({ "geo_coord": 48 })).is_err());
+    }
+
+    #[test]
+    fn orbit_from_inside() {
+        let center = DVec3::new(EARTH_RADIUS as f64 + 100., 0., 0.);
+        // Outside of the sphere at elevation 0, looking down to it
+        assert_eq!(orbit_distance(center, DVec3::NEG_X, 0.), Some(100.));
+        // Inside of the sphere at elevation 200, looking up to it
+        assert_eq!(orbit_distance(center, DVec3::X, 200.), Some(100.));
+        // Looking away from the sphere
+        assert_eq!(orbit_distance(center, DVec3::X, 0.), None);
+    }
+}
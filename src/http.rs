@@ -0,0 +1,155 @@
+//! Plain HTTP GET requests of the asset readers, with access to the headers needed for caching.
+//! Native builds use `surf`, the web build uses the `fetch` of the browser.
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::OnceLock;
+use std::{collections::HashMap, io, time::Duration};
+
+use crate::share::WEB_URL;
+
+/// Sent with every request, so server operators know who is downloading and how to
+/// contact them. Public tile servers require it.
+pub fn default_user_agent() -> String {
+    format!("osmeta/{} (+{WEB_URL})", env!("CARGO_PKG_VERSION"))
+}
+
+pub struct HttpResponse {
+    pub status: u16,
+    /// Header names are lowercase
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+impl HttpResponse {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).map(String::as_str)
+    }
+}
+
+/// A host that doesn't answer within this time counts as failed. The browser has its own timeout.
+#[cfg(not(target_arch = "wasm32"))]
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// GET `url` (with `https://`) with additional request `headers`
+#[cfg(not(target_arch = "wasm32"))]
+pub async fn get(url: &str, headers: &[(&'static str, String)]) -> io::Result<HttpResponse> {
+    // One client for all requests, so connections are reused
+    static CLIENT: OnceLock<surf::Client> = OnceLock::new();
+    let client = CLIENT.get_or_init(|| {
+        surf::Config::new()
+            .set_timeout(Some(TIMEOUT))
+            .try_into()
+            .expect("valid HTTP client config")
+    });
+    let mut request = client.get(url);
+    for (name, value) in headers {
+        request = request.header(*name, value.as_str());
+    }
+    let mut response = request
+        .await
+        .map_err(|err| io::Error::other(format!("{url}: {err}")))?;
+    let headers = response
+        .iter()
+        .map(|(name, values)| {
+            let name = name.as_str().to_lowercase();
+            (name, values.last().as_str().to_owned())
+        })
+        .collect();
+    let body = response
+        .body_bytes()
+        .await
+        .map_err(|err| io::Error::other(format!("{url}: {err}")))?;
+    Ok(HttpResponse {
+        status: response.status() as u16,
+        headers,
+        body,
+    })
+}
+
+/// GET `url` with additional request `headers`. The browser sends its own `user-agent` and
+/// `accept-encoding`, these headers are skipped.
+#[cfg(target_arch = "wasm32")]
+pub async fn get(url: &str, headers: &[(&'static str, String)]) -> io::Result<HttpResponse> {
+    browser::get(url, headers)
+        .await
+        .map_err(|err| io::Error::other(format!("{url}: {err:?}")))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub async fn sleep(duration: Duration) {
+    async_io::Timer::after(duration).await;
+}
+
+#[cfg(target_arch = "wasm32")]
+pub async fn sleep(duration: Duration) {
+    browser::sleep(duration).await
+}
+
+#[cfg(target_arch = "wasm32")]
+mod browser {
+    use super::*;
+    use js_sys::Uint8Array;
+    use wasm_bindgen::{JsCast, JsValue};
+    use wasm_bindgen_futures::JsFuture;
+    use web_sys::{Request, RequestInit, Response};
+
+    /// The response headers of interest. The `Headers` of `fetch` are not easy to iterate.
+    const HEADERS: [&str; 8] = [
+        "cache-control",
+        "content-encoding",
+        "content-length",
+        "content-type",
+        "date",
+        "etag",
+        "last-modified",
+        "retry-after",
+    ];
+
+    /// Request headers only the browser may set. `fetch` ignores them, or fails in older browsers.
+    const FORBIDDEN: [&str; 2] = ["accept-encoding", "user-agent"];
+
+    pub(super) async fn sleep(duration: Duration) {
+        let promise = js_sys::Promise::new(&mut |resolve, _| {
+            let timeout = web_sys::window().and_then(|window| {
+                window
+                    .set_timeout_with_callback_and_timeout_and_arguments_0(
+                        &resolve,
+                        duration.as_millis() as i32,
+                    )
+                    .ok()
+            });
+            if timeout.is_none() {
+                let _ = resolve.call0(&JsValue::NULL);
+            }
+        });
+        let _ = JsFuture::from(promise).await;
+    }
+
+    pub(super) async fn get(
+        url: &str,
+        headers: &[(&'static str, String)],
+    ) -> Result<HttpResponse, JsValue> {
+        let mut init = RequestInit::new();
+        init.method("GET");
+        let request = Request::new_with_str_and_init(url, &init)?;
+        for (name, value) in headers.iter().filter(|(name, _)| !FORBIDDEN.contains(name)) {
+            request.headers().set(name, value)?;
+        }
+        let window = web_sys::window().ok_or_else(|| JsValue::from_str("no window"))?;
+        let response: Response = JsFuture::from(window.fetch_with_request(&request))
+            .await?
+            .unchecked_into();
+        let mut headers = HashMap::new();
+        for name in HEADERS {
+            if let Some(value) = response.headers().get(name)? {
+                headers.insert(name.to_owned(), value);
+            }
+        }
+        let buffer = JsFuture::from(response.array_buffer()?).await?;
+        Ok(HttpResponse {
+            status: response.status(),
+            headers,
+            body: Uint8Array::new(&buffer).to_vec(),
+        })
+    }
+}
@@ -1,34 +1,347 @@
-use async_fs::File;
 use bevy::{
-    asset::{
-        io::{
-            AssetReader, AssetReaderError, AssetSource, AssetSourceId, PathStream, Reader,
-            VecReader,
-        },
-        AsyncReadExt,
+    app::AppExit,
+    asset::io::{
+        AssetReader, AssetReaderError, AssetSource, AssetSourceId, PathStream, Reader, VecReader,
     },
+    diagnostic::{Diagnostic, DiagnosticPath, Diagnostics, RegisterDiagnostic},
     prelude::*,
-    utils::BoxedFuture,
+    tasks::IoTaskPool,
+    utils::{BoxedFuture, Instant},
 };
-use flate2::read::GzDecoder;
+use serde::Deserialize;
 use std::{
     collections::HashSet,
-    io::Read,
-    path::{Path, PathBuf},
-    sync::{Arc, RwLock},
+    io,
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+};
+
+use crate::cache::{
+    quarantine, read_meta, store_entry, write_meta, CacheCounters, CacheMeta, CacheStats,
+    CacheStore, LimitedCache,
 };
+use crate::compression::{decompress, ACCEPT_ENCODING};
+use crate::http::{self, HttpResponse};
+use crate::mirrors::Mirrors;
+use crate::validate::{validate, DownloadLimits};
+
+/// Cached files without a max-age of the server are revalidated after a week
+pub const DEFAULT_CACHE_TTL: u64 = 7 * 24 * 60 * 60;
+
+/// Parallel requests per host. The OpenStreetMap tile usage policy allows two.
+pub const DEFAULT_MAX_REQUESTS: usize = 2;
+
+/// Seconds to wait after an HTTP 429 without a usable `Retry-After`
+const DEFAULT_RETRY_AFTER: u64 = 30;
+
+pub const CACHE_HITS: DiagnosticPath = DiagnosticPath::const_new("cache/hits");
+pub const CACHE_MISSES: DiagnosticPath = DiagnosticPath::const_new("cache/misses");
+/// In MiB
+pub const CACHE_SIZE: DiagnosticPath = DiagnosticPath::const_new("cache/size");
+pub const CACHE_ENTRIES: DiagnosticPath = DiagnosticPath::const_new("cache/entries");
+/// In percent of all cache reads
+pub const CACHE_HIT_RATIO: DiagnosticPath = DiagnosticPath::const_new("cache/hit_ratio");
+/// The index of the [`NetworkPolicy`] in [`NetworkPolicy::ALL`]
+pub const NETWORK_POLICY: DiagnosticPath = DiagnosticPath::const_new("network/policy");
+/// Downloaded MiB, before the decompression
+pub const NETWORK_BYTES: DiagnosticPath = DiagnosticPath::const_new("network/bytes");
+/// Average milliseconds from a request to its response, of the responses of a frame
+/// of all asset sources
+pub const NETWORK_LATENCY: DiagnosticPath = DiagnosticPath::const_new("network/latency");
+/// Like [`NETWORK_LATENCY`], of the 3D tiles only
+pub const TILE_LATENCY: DiagnosticPath = DiagnosticPath::const_new("network/tile_latency");
+
+/// How the asset readers use the network and the cache. Selected at startup.
+#[derive(Resource, Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum NetworkPolicy {
+    /// Use fresh cached files, download missing ones and revalidate stale ones
+    #[default]
+    Online,
+    /// Only use cached files, even stale ones. Missing files fail at once.
+    Offline,
+    /// Download all files again and update the cache
+    Refresh,
+    /// Download all files, don't use the cache at all
+    NoCache,
+}
+
+impl NetworkPolicy {
+    pub const ALL: [Self; 4] = [Self::Online, Self::Offline, Self::Refresh, Self::NoCache];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Online => "online",
+            Self::Offline => "offline",
+            Self::Refresh => "refresh",
+            Self::NoCache => "no-cache",
+        }
+    }
+
+    fn reads_cache(self) -> bool {
+        matches!(self, Self::Online | Self::Offline)
+    }
+
+    fn writes_cache(self) -> bool {
+        matches!(self, Self::Online | Self::Refresh)
+    }
+
+    /// Whether files may be downloaded
+    pub fn is_online(self) -> bool {
+        self != Self::Offline
+    }
+}
+
+impl std::fmt::Display for NetworkPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl std::str::FromStr for NetworkPolicy {
+    type Err = ();
+
+    fn from_str(name: &str) -> Result<Self, ()> {
+        Self::ALL
+            .into_iter()
+            .find(|policy| policy.name() == name)
+            .ok_or(())
+    }
+}
+
+/// The downloads of all asset sources, for the diagnostics
+#[derive(Default)]
+struct NetworkCounters {
+    responses: AtomicU64,
+    /// Before the decompression
+    bytes: AtomicU64,
+    /// Sum of the response times in microseconds
+    latency: AtomicU64,
+    /// Of the `tile://` source only
+    tile_responses: AtomicU64,
+    tile_latency: AtomicU64,
+}
+
+/// Where and how the files of an asset source are downloaded
+#[derive(Clone)]
+struct Source {
+    mirrors: Arc<Mirrors>,
+    network: Arc<NetworkCounters>,
+    user_agent: String,
+    /// Whether to load tiles from this path
+    tile: bool,
+    limits: DownloadLimits,
+}
+
+impl Source {
+    fn url(&self, base_url: &str, path: &str) -> String {
+        // `https://` unless the scheme is given, like `http://` of a local test server
+        let scheme = if base_url.starts_with("http://") || base_url.starts_with("https://") {
+            ""
+        } else {
+            "https://"
+        };
+        if !self.tile {
+            return format!("{scheme}{base_url}{path}");
+        }
+        // `tile://` urls are special for now, because we can't use `/` in the tile paths,
+        // as that will cause texture loading to be attempted in the subfolders instead of the root.
+        let [zoom, x, rest] = *path.splitn(3, '_').collect::<Vec<_>>() else {
+            unreachable!()
+        };
+        format!("{scheme}{base_url}lod1/{zoom}/{x}/{rest}")
+    }
+}
+
+/// The result of a download
+enum Fetched {
+    /// The new file, with its cache metadata (`None` if it must not be cached)
+    Modified(Vec<u8>, Option<CacheMeta>),
+    /// The cached file is still valid, with updated metadata
+    NotModified(CacheMeta),
+}
+
+/// Download `path`. With the metadata of a cached version, the server only sends a modified file.
+/// The mirrors are tried in turn, until one of them answers. If all of them asked to wait
+/// (HTTP 429), they are tried once more after the wait.
+async fn fetch(
+    source: &Source,
+    path: &str,
+    cached: Option<&CacheMeta>,
+) -> Result<Fetched, AssetReaderError> {
+    // The tile servers we're using have their files compressed, the decompression is transparent.
+    // Browsers send their own headers for both, see `http::get`.
+    let mut headers = vec![
+        ("user-agent", source.user_agent.clone()),
+        ("accept-encoding", ACCEPT_ENCODING.into()),
+    ];
+    if let Some(meta) = cached {
+        headers.extend(meta.validators());
+    }
+    let mut error = None;
+    for _ in 0..2 {
+        let mut throttled = false;
+        for host in source.mirrors.order() {
+            let url = source.url(source.mirrors.base_url(host), path);
+            let _request = source.mirrors.request(host).await;
+            info!("loading {url}");
+            let start = Instant::now();
+            let response = match http::get(&url, &headers).await {
+                Ok(response) => {
+                    let network = &source.network;
+                    let latency = start.elapsed().as_micros() as u64;
+                    network.responses.fetch_add(1, Ordering::Relaxed);
+                    network.latency.fetch_add(latency, Ordering::Relaxed);
+                    if source.tile {
+                        network.tile_responses.fetch_add(1, Ordering::Relaxed);
+                        network.tile_latency.fetch_add(latency, Ordering::Relaxed);
+                    }
+                    let bytes = response.body.len() as u64;
+                    network.bytes.fetch_add(bytes, Ordering::Relaxed);
+                    response
+                }
+                Err(err) => {
+                    warn!("{err}");
+                    source.mirrors.failure(host);
+                    error = Some(err.into());
+                    continue;
+                }
+            };
+            if let Some(seconds) = retry_after(&response) {
+                warn!(
+                    "{url}: HTTP status {}, retry after {seconds} s",
+                    response.status
+                );
+                source.mirrors.retry_after(host, seconds);
+                throttled = true;
+            }
+            if response.status == 429 || response.status >= 500 {
+                // The host has a problem, not the file
+                if response.status != 429 {
+                    source.mirrors.failure(host);
+                }
+                error = Some(AssetReaderError::HttpError(response.status));
+                continue;
+            }
+            source.mirrors.success(host);
+            return handle_response(source, path, cached, response);
+        }
+        if !throttled {
+            break;
+        }
+    }
+    Err(error.expect("at least one mirror"))
+}
+
+/// Seconds to wait, if the server asks for it: `Retry-After` of a 429 or 503 response.
+/// Only the seconds format is understood, a date counts as [`DEFAULT_RETRY_AFTER`].
+fn retry_after(response: &HttpResponse) -> Option<u64> {
+    match response.status {
+        429 => Some(DEFAULT_RETRY_AFTER),
+        503 if response.header("retry-after").is_some() => Some(DEFAULT_RETRY_AFTER),
+        _ => None,
+    }
+    .map(|default| {
+        response
+            .header("retry-after")
+            .and_then(|value| value.trim().parse().ok())
+            .unwrap_or(default)
+    })
+}
+
+/// Rejects files that exceed the [`DownloadLimits`] or are malformed, with an
+/// [`io::ErrorKind::InvalidData`] error that fails the asset like a missing one.
+fn handle_response(
+    source: &Source,
+    path: &str,
+    cached: Option<&CacheMeta>,
+    response: HttpResponse,
+) -> Result<Fetched, AssetReaderError> {
+    match (response.status, cached) {
+        (304, Some(meta)) => {
+            let mut meta = meta.clone();
+            meta.update(&response);
+            Ok(Fetched::NotModified(meta))
+        }
+        (200..=299, _) => {
+            let meta = CacheMeta::from_response(&response);
+            let encoding = response.header("content-encoding").map(str::to_owned);
+            let limits = &source.limits;
+            let invalid = |err: String| -> AssetReaderError {
+                warn!("rejected {path}: {err}");
+                io::Error::new(io::ErrorKind::InvalidData, err).into()
+            };
+            if response.body.len() > limits.max_compressed {
+                return Err(invalid(format!(
+                    "download of {} bytes, at most {} allowed",
+                    response.body.len(),
+                    limits.max_compressed
+                )));
+            }
+            let bytes = decompress(encoding.as_deref(), response.body, limits.max_decompressed)
+                .map_err(|err| invalid(err.to_string()))?;
+            validate(path, &bytes, limits).map_err(invalid)?;
+            Ok(Fetched::Modified(bytes, meta))
+        }
+        (404, _) => Err(AssetReaderError::NotFound(path.into())),
+        (status, _) => Err(AssetReaderError::HttpError(status)),
+    }
+}
+
+/// Ask the server whether a stale cache entry changed, and update it
+async fn revalidate(
+    source: &Source,
+    cache: &dyn CacheStore,
+    path: &str,
+    meta: Option<CacheMeta>,
+) -> Result<(), AssetReaderError> {
+    match fetch(source, path, meta.as_ref()).await? {
+        Fetched::NotModified(meta) => write_meta(cache, path, &meta).await?,
+        Fetched::Modified(bytes, meta) => {
+            debug!("updated: {path}");
+            store_entry(cache, path, &bytes, meta.as_ref()).await?
+        }
+    }
+    Ok(())
+}
+
+/// Marks a path as being written to the cache, until it is dropped
+struct Writing {
+    sync: Arc<RwLock<HashSet<String>>>,
+    path: String,
+}
+
+impl Writing {
+    /// `None` if someone else is already writing the path
+    fn start(sync: &Arc<RwLock<HashSet<String>>>, path: &str) -> Option<Self> {
+        sync.write().unwrap().insert(path.into()).then(|| Self {
+            sync: sync.clone(),
+            path: path.into(),
+        })
+    }
+}
+
+impl Drop for Writing {
+    fn drop(&mut self) {
+        self.sync.write().unwrap().remove(&self.path);
+    }
+}
 
 /// A custom asset reader implementation that wraps a given asset reader implementation
 struct HttpAssetReader {
-    pub base_url: String,
-    /// Whether to load tiles from this path
-    pub tile: bool,
-    /// Used to ensure the same asset doesn't get its cache file written twice at the same time,
-    /// as that depends on the OS whether it succeeds (could result in broken cache files).
-    pub sync: Arc<RwLock<HashSet<PathBuf>>>,
-    pub cache_path: Option<PathBuf>,
+    source: Source,
+    /// The paths being written to the cache (or revalidated), so the same asset doesn't get
+    /// written twice at the same time.
+    pub sync: Arc<RwLock<HashSet<String>>>,
+    pub cache: Option<Arc<dyn CacheStore>>,
+    pub counters: Arc<CacheCounters>,
+    /// Seconds a cached file is used without asking the server, if the server gives no max-age
+    pub cache_ttl: u64,
+    pub policy: NetworkPolicy,
 }
-impl HttpAssetReader {}
 
 impl AssetReader for HttpAssetReader {
     fn read<'a>(
@@ -36,64 +349,69 @@ impl AssetReader for HttpAssetReader {
         path: &'a Path,
     ) -> BoxedFuture<'a, Result<Box<Reader<'a>>, AssetReaderError>> {
         Box::pin(async move {
-            let cache_path = self.cache_path.as_ref().map(|p| p.join(path));
+            let path = path.display().to_string();
             // Load from cache if the asset exists there.
-            if let Some(cache_path) = &cache_path {
+            if let Some(cache) = self.cache.as_ref().filter(|_| self.policy.reads_cache()) {
                 debug!("READ: {:?}", path);
-                if cache_path.exists() {
-                    let file = File::open(&cache_path).await?;
-                    return Ok(Box::new(file) as Box<Reader>);
+                match cache.read(&path).await {
+                    Ok(Some(bytes)) => {
+                        let meta = read_meta(&**cache, &path).await;
+                        if meta.as_ref().is_some_and(|meta| !meta.verify(&bytes)) {
+                            warn!("cached {path} is broken, downloading it again");
+                            if let Err(err) = quarantine(&**cache, &path, &bytes).await {
+                                warn!("could not quarantine {path}: {err}");
+                            }
+                            self.counters.misses.fetch_add(1, Ordering::Relaxed);
+                        } else {
+                            self.counters.hits.fetch_add(1, Ordering::Relaxed);
+                            let fresh = meta
+                                .as_ref()
+                                .is_some_and(|meta| meta.is_fresh(self.cache_ttl));
+                            // Stale files are used anyway, while the server is asked for a new version
+                            let writing = if fresh || !self.policy.is_online() {
+                                None
+                            } else {
+                                Writing::start(&self.sync, &path)
+                            };
+                            if let Some(writing) = writing {
+                                let source = self.source.clone();
+                                let cache = cache.clone();
+                                IoTaskPool::get()
+                                    .spawn(async move {
+                                        let path = &writing.path;
+                                        if let Err(err) =
+                                            revalidate(&source, &*cache, path, meta).await
+                                        {
+                                            warn!("could not revalidate {path}: {err}");
+                                        }
+                                    })
+                                    .detach();
+                            }
+                            return Ok(Box::new(VecReader::new(bytes)) as Box<Reader>);
+                        }
+                    }
+                    Ok(None) => {
+                        self.counters.misses.fetch_add(1, Ordering::Relaxed);
+                    }
+                    Err(err) => warn!("could not read {path} from the cache: {err}"),
                 }
             }
-            let path = path.display().to_string();
 
-            let mut bytes = vec![];
-            if self.tile {
-                // `tile://` urls are special for now, because we can't use `/` in the tile paths,
-                // as that will cause texture loading to be attempted in the subfolders instead of the root.
-                let [zoom, x, rest] = *path.splitn(3, '_').collect::<Vec<_>>() else {
-                    unreachable!()
-                };
-                // The tile servers we're using have their files gzipped, so we download that and unzip it
-                // transparently and act as if there's a .glb file there.
-                let ext = if cfg!(target_arch = "wasm32") {
-                    // The server signals the websocket to add .gz and unpack, but does that for `.gz` files, too.
-                    // So if we try to download a `.gz` file, we get an error because we get the decompressed
-                    // version of the file?!
-                    ""
-                } else {
-                    ".gz"
-                };
-                let path = format!("{}lod1/{zoom}/{x}/{rest}{ext}", self.base_url);
-                info!("loading {path}");
-                let mut bytes_compressed = Vec::new();
-                bevy_web_asset::WebAssetReader::Https
-                    .read(Path::new(&path))
-                    .await?
-                    .read_to_end(&mut bytes_compressed)
-                    .await?;
-
-                if cfg!(target_arch = "wasm32") {
-                    bytes = bytes_compressed;
-                } else {
-                    let mut decoder = GzDecoder::new(bytes_compressed.as_slice());
-
-                    decoder.read_to_end(&mut bytes)?;
-                }
-            } else {
-                let path = format!("{}{path}", self.base_url);
-                bevy_web_asset::WebAssetReader::Https
-                    .read(Path::new(&path))
-                    .await?
-                    .read_to_end(&mut bytes)
-                    .await?;
+            if !self.policy.is_online() {
+                debug!("offline, {path} is not cached");
+                return Err(AssetReaderError::NotFound(path.into()));
+            }
+            let (bytes, meta) = match fetch(&self.source, &path, None).await? {
+                Fetched::Modified(bytes, meta) => (bytes, meta),
+                Fetched::NotModified(_) => unreachable!("no conditional request"),
             };
-            if let Some(cache_path) = cache_path {
+            if let Some(cache) = self.cache.as_ref().filter(|_| self.policy.writes_cache()) {
                 // Write asset to cache, but ensure only one HttpAssetReader writes at any given point in time
-                if self.sync.write().unwrap().insert(cache_path.clone()) {
+                if let Some(_writing) = Writing::start(&self.sync, &path) {
                     debug!("write: {path}");
-                    async_fs::create_dir_all(cache_path.parent().unwrap()).await?;
-                    async_fs::write(cache_path, &bytes).await?;
+                    if let Err(err) = store_entry(&**cache, &path, &bytes, meta.as_ref()).await {
+                        warn!("could not cache {path}: {err}");
+                    }
                 }
             }
             Ok(Box::new(VecReader::new(bytes)) as Box<Reader<'static>>)
@@ -122,42 +440,513 @@ impl AssetReader for HttpAssetReader {
     }
 }
 
-/// A plugins that registers the `HttpAssetReader` as an asset source.
+/// Removes cached files: those of one asset source (`default`, `tile` or `raster`), or all
+#[derive(Event, Debug, Clone, Default)]
+pub struct ClearCache {
+    pub source: Option<String>,
+}
+
+/// The cache of the asset sources, shared with the readers
+#[derive(Resource)]
+struct TileCache {
+    cache: Option<Arc<LimitedCache>>,
+    counters: Arc<CacheCounters>,
+    network: Arc<NetworkCounters>,
+}
+
+fn update_cache_stats(
+    tile_cache: Res<TileCache>,
+    policy: Res<NetworkPolicy>,
+    mut stats: ResMut<CacheStats>,
+    mut diagnostics: Diagnostics,
+) {
+    let policy = NetworkPolicy::ALL.iter().position(|p| p == &*policy);
+    diagnostics.add_measurement(&NETWORK_POLICY, || policy.unwrap_or_default() as f64);
+    let new_stats = tile_cache.counters.stats();
+    stats.set_if_neq(new_stats);
+    diagnostics.add_measurement(&CACHE_HITS, || new_stats.hits as f64);
+    diagnostics.add_measurement(&CACHE_MISSES, || new_stats.misses as f64);
+    if let Some(ratio) = hit_ratio(&new_stats) {
+        diagnostics.add_measurement(&CACHE_HIT_RATIO, || ratio);
+    }
+    diagnostics.add_measurement(&CACHE_SIZE, || new_stats.bytes as f64 / (1024. * 1024.));
+    diagnostics.add_measurement(&CACHE_ENTRIES, || new_stats.entries as f64);
+}
+
+/// Percent of the cache reads that found the file, `None` without reads
+fn hit_ratio(stats: &CacheStats) -> Option<f64> {
+    let reads = stats.hits + stats.misses;
+    (reads > 0).then(|| stats.hits as f64 * 100. / reads as f64)
+}
+
+/// The average milliseconds of the responses since `last`, `None` without new responses.
+/// `last` are the responses and their latency sum in microseconds, they are updated.
+fn average_latency(last: &mut (u64, u64), responses: u64, latency: u64) -> Option<f64> {
+    let new = responses.saturating_sub(last.0);
+    let average = (new > 0).then(|| latency.saturating_sub(last.1) as f64 / new as f64 / 1000.);
+    *last = (responses, latency);
+    average
+}
+
+/// `last` are the responses and latencies of all sources and of the tiles of the previous frame
+fn update_network_stats(
+    tile_cache: Res<TileCache>,
+    mut last: Local<[(u64, u64); 2]>,
+    mut diagnostics: Diagnostics,
+) {
+    let network = &tile_cache.network;
+    let bytes = network.bytes.load(Ordering::Relaxed);
+    diagnostics.add_measurement(&NETWORK_BYTES, || bytes as f64 / (1024. * 1024.));
+    let [all, tiles] = &mut *last;
+    let responses = network.responses.load(Ordering::Relaxed);
+    let latency = network.latency.load(Ordering::Relaxed);
+    if let Some(average) = average_latency(all, responses, latency) {
+        diagnostics.add_measurement(&NETWORK_LATENCY, || average);
+    }
+    let responses = network.tile_responses.load(Ordering::Relaxed);
+    let latency = network.tile_latency.load(Ordering::Relaxed);
+    if let Some(average) = average_latency(tiles, responses, latency) {
+        diagnostics.add_measurement(&TILE_LATENCY, || average);
+    }
+}
+
+/// Store the index of the cache, so the next session knows the entries of this one
+fn save_cache_index(mut exit: EventReader<AppExit>, tile_cache: Res<TileCache>) {
+    if exit.read().count() == 0 {
+        return;
+    }
+    let Some(cache) = tile_cache.cache.clone() else {
+        return;
+    };
+    // The app ends, a task would not be run to the end
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Err(err) = bevy::tasks::block_on(cache.save()) {
+        warn!("could not save the cache index: {err}");
+    }
+    // The browser can't block, but the page usually lives on
+    #[cfg(target_arch = "wasm32")]
+    IoTaskPool::get()
+        .spawn(async move {
+            if let Err(err) = cache.save().await {
+                warn!("could not save the cache index: {err}");
+            }
+        })
+        .detach();
+}
+
+fn clear_cache(mut events: EventReader<ClearCache>, tile_cache: Res<TileCache>) {
+    for ClearCache { source } in events.read() {
+        let Some(cache) = tile_cache.cache.clone() else {
+            warn!("there is no cache to clear");
+            continue;
+        };
+        let source = source.clone();
+        IoTaskPool::get()
+            .spawn(async move {
+                let name = source.as_deref().unwrap_or("all sources");
+                match cache.clear(source.as_deref()).await {
+                    Ok(count) => info!("removed {count} cached files of {name}"),
+                    Err(err) => warn!("could not clear the cache of {name}: {err}"),
+                }
+            })
+            .detach();
+    }
+}
+
+/// A plugins that registers the `HttpAssetReader` as asset sources: the default one and
+/// `tile://` for the 3D tiles, `raster://` for the flat map tiles.
 pub struct HttpAssetReaderPlugin {
+    /// Host and path of the 3D tile server, several separated by commas (mirrors)
     pub base_url: String,
+    /// Host and path of the raster tile server, in the same format
+    pub raster_url: String,
+    /// Where to cache the downloaded files, `None` to disable caching
+    pub cache: Option<Arc<dyn CacheStore>>,
+    /// Seconds a cached file is used without asking the server, see [`DEFAULT_CACHE_TTL`]
+    pub cache_ttl: u64,
+    /// The least recently used files are removed above this size in bytes,
+    /// see [`crate::cache::DEFAULT_CACHE_SIZE`]
+    pub cache_size: u64,
+    /// For all asset sources
+    pub policy: NetworkPolicy,
+    /// Sent with every request, see [`crate::default_user_agent`]
+    pub user_agent: String,
+    /// Parallel requests per host, see [`DEFAULT_MAX_REQUESTS`]
+    pub max_requests: usize,
+    /// Downloads above these limits are rejected, like malformed glb files
+    pub limits: DownloadLimits,
 }
 
 impl Plugin for HttpAssetReaderPlugin {
     fn build(&self, app: &mut App) {
-        let base_url = self.base_url.clone();
+        let counters = Arc::new(CacheCounters::default());
+        let network = Arc::new(NetworkCounters::default());
+        let limited = self
+            .cache
+            .clone()
+            .map(|store| Arc::new(LimitedCache::new(store, self.cache_size, counters.clone())));
+        app.insert_resource(TileCache {
+            cache: limited.clone(),
+            counters: counters.clone(),
+            network: network.clone(),
+        })
+        .insert_resource(self.policy)
+        .init_resource::<CacheStats>()
+        .add_event::<ClearCache>()
+        .register_diagnostic(Diagnostic::new(CACHE_HITS))
+        .register_diagnostic(Diagnostic::new(CACHE_MISSES))
+        .register_diagnostic(Diagnostic::new(CACHE_SIZE).with_suffix(" MiB"))
+        .register_diagnostic(Diagnostic::new(CACHE_ENTRIES))
+        .register_diagnostic(Diagnostic::new(CACHE_HIT_RATIO).with_suffix("%"))
+        .register_diagnostic(Diagnostic::new(NETWORK_POLICY))
+        .register_diagnostic(Diagnostic::new(NETWORK_BYTES).with_suffix(" MiB"))
+        .register_diagnostic(Diagnostic::new(NETWORK_LATENCY).with_suffix(" ms"))
+        .register_diagnostic(Diagnostic::new(TILE_LATENCY).with_suffix(" ms"))
+        .add_systems(
+            Update,
+            (update_cache_stats, update_network_stats, clear_cache),
+        )
+        .add_systems(Last, save_cache_index);
+
+        let cache_ttl = self.cache_ttl;
+        let policy = self.policy;
+        info!("network policy: {policy}, caching: {}", limited.is_some());
         let sync = Arc::new(RwLock::new(HashSet::new()));
-        let sync2 = sync.clone();
-        let cache_path = directories::ProjectDirs::from("org", "osmeta", "OSMeta")
-            .map(|dirs| dirs.cache_dir().to_owned());
-        let cache_path2 = cache_path.clone();
-        app.register_asset_source(
-            AssetSourceId::Default,
-            AssetSource::build().with_reader(move || {
-                Box::new(HttpAssetReader {
-                    base_url: base_url.clone(),
-                    tile: false,
-                    sync: sync.clone(),
-                    cache_path: cache_path2.clone(),
-                })
-            }),
+        let tile_mirrors = Arc::new(Mirrors::new(&self.base_url, self.max_requests));
+        let raster_mirrors = Arc::new(Mirrors::new(&self.raster_url, self.max_requests));
+        for (id, name, mirrors, tile) in [
+            (
+                AssetSourceId::Default,
+                "default",
+                tile_mirrors.clone(),
+                false,
+            ),
+            (
+                AssetSourceId::Name("tile".into()),
+                "tile",
+                tile_mirrors,
+                true,
+            ),
+            (
+                AssetSourceId::Name("raster".into()),
+                "raster",
+                raster_mirrors,
+                false,
+            ),
+        ] {
+            let source = Source {
+                mirrors,
+                network: network.clone(),
+                user_agent: self.user_agent.clone(),
+                tile,
+                limits: self.limits,
+            };
+            let cache = limited.as_ref().map(|cache| {
+                let server = source.mirrors.base_url(0);
+                Arc::new(cache.source(name, server)) as Arc<dyn CacheStore>
+            });
+            let sync = sync.clone();
+            let counters = counters.clone();
+            app.register_asset_source(
+                id,
+                AssetSource::build().with_reader(move || {
+                    Box::new(HttpAssetReader {
+                        source: source.clone(),
+                        sync: sync.clone(),
+                        cache: cache.clone(),
+                        counters: counters.clone(),
+                        cache_ttl,
+                        policy,
+                    })
+                }),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::{asset::AsyncReadExt, tasks::block_on};
+    use std::path::PathBuf;
+
+    use crate::cache::DiskCache;
+
+    fn source(base_url: &str) -> Source {
+        Source {
+            mirrors: Arc::new(Mirrors::new(base_url, 1)),
+            network: Arc::default(),
+            user_agent: "test".into(),
+            tile: false,
+            limits: DownloadLimits::default(),
+        }
+    }
+
+    /// A reader of files in `cache`, from a server that doesn't answer
+    fn reader(policy: NetworkPolicy, cache: Option<Arc<dyn CacheStore>>) -> HttpAssetReader {
+        HttpAssetReader {
+            source: source("http://127.0.0.1:9/"),
+            sync: Arc::default(),
+            cache,
+            counters: Arc::default(),
+            cache_ttl: 0,
+            policy,
+        }
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("osmeta_{name}_{}", std::process::id()))
+    }
+
+    /// Answers one request with `response`. Returns the base URL and the request, lowercase.
+    fn serve_once(response: &'static str) -> (String, std::thread::JoinHandle<String>) {
+        use std::io::{Read, Write};
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}/", listener.local_addr().unwrap());
+        let request = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = vec![];
+            let mut buffer = [0; 1024];
+            while !request.ends_with(b"\r\n\r\n") {
+                let read = stream.read(&mut buffer).unwrap();
+                if read == 0 {
+                    break;
+                }
+                request.extend_from_slice(&buffer[..read]);
+            }
+            stream.write_all(response.as_bytes()).unwrap();
+            String::from_utf8_lossy(&request).to_lowercase()
+        });
+        (base_url, request)
+    }
+
+    /// A cache with `a.json`, stored with an ETag and a date, and its metadata
+    fn cached(name: &str) -> (DiskCache, CacheMeta) {
+        let cache = DiskCache {
+            root: temp_dir(name),
+        };
+        let meta = CacheMeta {
+            etag: Some("\"v1\"".into()),
+            last_modified: Some("Wed, 21 Oct 2015 07:28:00 GMT".into()),
+            ..default()
+        };
+        block_on(store_entry(&cache, "a.json", b"cached", Some(&meta))).unwrap();
+        let meta = block_on(read_meta(&cache, "a.json")).unwrap();
+        (cache, meta)
+    }
+
+    #[test]
+    fn not_modified() {
+        let (cache, meta) = cached("not_modified");
+        let (base_url, request) = serve_once(
+            "HTTP/1.1 304 Not Modified\r\netag: \"v1\"\r\ncache-control: max-age=60\r\n\
+             content-length: 0\r\nconnection: close\r\n\r\n",
         );
-        let base_url = self.base_url.clone();
-        app.register_asset_source(
-            AssetSourceId::Name("tile".into()),
-            AssetSource::build().with_reader(move || {
-                info!(?cache_path);
-                Box::new(HttpAssetReader {
-                    base_url: base_url.clone(),
-                    tile: true,
-                    sync: sync2.clone(),
-                    cache_path: cache_path.clone(),
-                })
-            }),
+        block_on(revalidate(&source(&base_url), &cache, "a.json", Some(meta))).unwrap();
+        let request = request.join().unwrap();
+        assert!(request.starts_with("get /a.json "), "{request}");
+        assert!(request.contains("if-none-match: \"v1\""), "{request}");
+        assert!(
+            request.contains("if-modified-since: wed, 21 oct 2015 07:28:00 gmt"),
+            "{request}"
         );
+
+        // The file is kept, with new metadata
+        let bytes = block_on(cache.read("a.json")).unwrap().unwrap();
+        assert_eq!(bytes, b"cached");
+        let meta = block_on(read_meta(&cache, "a.json")).unwrap();
+        assert_eq!(meta.max_age, Some(60));
+        assert!(meta.is_fresh(0));
+        assert!(meta.verify(&bytes));
+        std::fs::remove_dir_all(cache.root).unwrap();
+    }
+
+    #[test]
+    fn modified() {
+        let (cache, meta) = cached("modified");
+        let (base_url, request) = serve_once(
+            "HTTP/1.1 200 OK\r\netag: \"v2\"\r\ncontent-length: 3\r\nconnection: close\r\n\r\nnew",
+        );
+        block_on(revalidate(&source(&base_url), &cache, "a.json", Some(meta))).unwrap();
+        request.join().unwrap();
+        let bytes = block_on(cache.read("a.json")).unwrap().unwrap();
+        assert_eq!(bytes, b"new");
+        let meta = block_on(read_meta(&cache, "a.json")).unwrap();
+        assert_eq!(meta.etag.as_deref(), Some("\"v2\""));
+        assert_eq!(meta.last_modified, None);
+        assert!(meta.verify(&bytes));
+        std::fs::remove_dir_all(cache.root).unwrap();
+    }
+
+    #[test]
+    fn broken_entries() {
+        let root = temp_dir("broken");
+        let cache: Arc<dyn CacheStore> = Arc::new(DiskCache { root: root.clone() });
+        let mut reader = reader(NetworkPolicy::Online, Some(cache.clone()));
+        let meta = CacheMeta {
+            max_age: Some(3600),
+            ..default()
+        };
+        // Truncated, and of the same length but another CRC
+        for broken in [&b"cach"[..], b"cacheD"] {
+            block_on(store_entry(&*cache, "a.json", b"cached", Some(&meta))).unwrap();
+            block_on(cache.write("a.json", broken)).unwrap();
+            let (base_url, request) =
+                serve_once("HTTP/1.1 200 OK\r\ncontent-length: 3\r\nconnection: close\r\n\r\nnew");
+            reader.source = source(&base_url);
+
+            let mut bytes = vec![];
+            block_on(async {
+                let mut file = reader.read(Path::new("a.json")).await.unwrap();
+                file.read_to_end(&mut bytes).await.unwrap();
+            });
+            request.join().unwrap();
+            assert_eq!(bytes, b"new");
+            assert_eq!(
+                block_on(cache.read("a.json")).unwrap(),
+                Some(b"new".to_vec())
+            );
+            let quarantined = block_on(cache.read("quarantine/a.json")).unwrap();
+            assert_eq!(quarantined.as_deref(), Some(broken));
+        }
+        let stats = reader.counters.stats();
+        assert_eq!((stats.hits, stats.misses), (0, 2));
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn handle_not_modified() {
+        let source = &source("a/");
+        let meta = CacheMeta::default();
+        assert!(matches!(
+            handle_response(source, "a.json", Some(&meta), response(304, &[])),
+            Ok(Fetched::NotModified(_))
+        ));
+        // Only asked for with a cached file
+        assert!(matches!(
+            handle_response(source, "a.json", None, response(304, &[])),
+            Err(AssetReaderError::HttpError(304))
+        ));
+    }
+
+    #[test]
+    fn response(status: u16, headers: &[(&str, &str)]) -> HttpResponse {
+        HttpResponse {
+            status,
+            headers: headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            body: vec![],
+        }
+    }
+
+    #[test]
+    fn retry_after_header() {
+        assert_eq!(
+            retry_after(&response(429, &[("retry-after", "120")])),
+            Some(120)
+        );
+        assert_eq!(
+            retry_after(&response(503, &[("retry-after", " 5 ")])),
+            Some(5)
+        );
+        // Dates are not understood
+        let date = [("retry-after", "Wed, 21 Oct 2015 07:28:00 GMT")];
+        assert_eq!(
+            retry_after(&response(429, &date)),
+            Some(DEFAULT_RETRY_AFTER)
+        );
+        assert_eq!(
+            retry_after(&response(503, &date)),
+            Some(DEFAULT_RETRY_AFTER)
+        );
+        assert_eq!(retry_after(&response(429, &[])), Some(DEFAULT_RETRY_AFTER));
+        // A 503 without the header is a failure of the host, not a request to wait
+        assert_eq!(retry_after(&response(503, &[])), None);
+        assert_eq!(retry_after(&response(200, &[("retry-after", "5")])), None);
+        assert_eq!(retry_after(&response(500, &[])), None);
+    }
+
+    #[test]
+    fn hit_ratios() {
+        let stats = |hits, misses| CacheStats {
+            hits,
+            misses,
+            ..default()
+        };
+        assert_eq!(hit_ratio(&stats(0, 0)), None);
+        assert_eq!(hit_ratio(&stats(0, 4)), Some(0.));
+        assert_eq!(hit_ratio(&stats(3, 1)), Some(75.));
+        assert_eq!(hit_ratio(&stats(5, 0)), Some(100.));
+    }
+
+    #[test]
+    fn latencies() {
+        let mut last = (0, 0);
+        assert_eq!(average_latency(&mut last, 0, 0), None);
+        assert_eq!(average_latency(&mut last, 2, 30_000), Some(15.));
+        // Of the new responses only
+        assert_eq!(average_latency(&mut last, 3, 40_000), Some(10.));
+        assert_eq!(average_latency(&mut last, 3, 40_000), None);
+        assert_eq!(last, (3, 40_000));
+        // The latency of a response counted before its latency
+        assert_eq!(average_latency(&mut last, 4, 40_000), Some(0.));
+    }
+
+    #[test]
+    fn policies() {
+        use NetworkPolicy::*;
+        let uses = |policy: NetworkPolicy| {
+            (
+                policy.reads_cache(),
+                policy.writes_cache(),
+                policy.is_online(),
+            )
+        };
+        assert_eq!(uses(Online), (true, true, true));
+        assert_eq!(uses(Offline), (true, false, false));
+        assert_eq!(uses(Refresh), (false, true, true));
+        assert_eq!(uses(NoCache), (false, false, true));
+    }
+
+    #[test]
+    fn policy_names() {
+        for policy in NetworkPolicy::ALL {
+            assert_eq!(policy.to_string().parse(), Ok(policy));
+            let json = format!("\"{policy}\"");
+            assert_eq!(
+                serde_json::from_str::<NetworkPolicy>(&json).unwrap(),
+                policy
+            );
+        }
+        assert_eq!("no-cache".parse(), Ok(NetworkPolicy::NoCache));
+        assert_eq!("NoCache".parse::<NetworkPolicy>(), Err(()));
+        assert_eq!("".parse::<NetworkPolicy>(), Err(()));
+    }
+
+    #[test]
+    fn offline() {
+        let root = temp_dir("offline");
+        let cache: Arc<dyn CacheStore> = Arc::new(DiskCache { root: root.clone() });
+        block_on(cache.write("cached.png", b"cached")).unwrap();
+        let reader = reader(NetworkPolicy::Offline, Some(cache));
+
+        // Missing files fail at once, without asking the server
+        let result = block_on(reader.read(Path::new("missing.png")));
+        assert!(matches!(result, Err(AssetReaderError::NotFound(_))));
+        assert_eq!(reader.source.network.responses.load(Ordering::Relaxed), 0);
+
+        // Cached files are used, even without metadata to know whether they are fresh
+        let mut bytes = vec![];
+        block_on(async {
+            let mut file = reader.read(Path::new("cached.png")).await.unwrap();
+            file.read_to_end(&mut bytes).await.unwrap();
+        });
+        assert_eq!(bytes, b"cached");
+        assert!(reader.sync.read().unwrap().is_empty());
+        let stats = reader.counters.stats();
+        assert_eq!((stats.hits, stats.misses), (1, 1));
+        std::fs::remove_dir_all(root).unwrap();
     }
 }
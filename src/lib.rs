@@ -1,9 +1,23 @@
-//! Loads and renders a glTF file as a scene.
+//! Loads and renders OpenStreetMap 3D tiles (glTF) around a camera on a globe.
+//!
+//! Use [`OsmetaPlugin`] to add the viewer to your own Bevy app:
+//!
+//! ```ignore
+//! App::new()
+//!     .add_plugins(
+//!         OsmetaPlugin::new()
+//!             .start_view(GeoView { geo_coord: GeoCoord { lat: 48.1408, lon: 11.5577 }, ..default() })
+//!             .control_mode(CamControlMode::F4)
+//!             .diagnostics(false),
+//!     )
+//!     .run();
+//! ```
 
 use crate::big_space::{
     world_query::{GridTransform, GridTransformOwned},
     FloatingOriginPlugin, GridCell,
 };
+use bevy::diagnostic::FrameTimeDiagnosticsPlugin;
 use bevy::prelude::*;
 use bevy_embedded_assets::EmbeddedAssetPlugin;
 use bevy_screen_diagnostics::{
@@ -11,28 +25,71 @@ use bevy_screen_diagnostics::{
     ScreenFrameDiagnosticsPlugin,
 };
 use flycontrol::update_camera_orientations;
-use geocoord::GeoCoord;
-use geoview::GeoView;
-use http_assets::HttpAssetReaderPlugin;
-use player::{CamControlMode, ControlValues, PlanetaryPosition};
-use tilemap::TileMap;
+#[cfg(not(target_arch = "wasm32"))]
+use options::{OptionsError, HELP};
+use player::PlanetaryPosition;
+use search::GazetteerGeocoder;
+use std::sync::Arc;
 #[cfg(all(feature = "xr", not(any(target_os = "macos", target_arch = "wasm32"))))]
 use xr::pull_to_ground;
 
 mod big_space;
+mod bookmarks;
+mod cache;
 mod compass;
+mod compression;
 mod f4control;
 mod flycontrol;
+mod flyto;
 mod geocoord;
 mod geoview;
+mod http;
 mod http_assets;
+mod marker;
+mod mirrors;
+mod options;
 mod player;
+#[cfg(not(target_arch = "wasm32"))]
+mod remote;
+#[cfg(not(target_arch = "wasm32"))]
+mod replay;
+mod search;
+mod share;
 mod sky;
 mod tilemap;
+mod tour;
+mod validate;
+#[cfg(target_arch = "wasm32")]
+mod web_api;
 
 #[cfg(all(feature = "xr", not(any(target_os = "macos", target_arch = "wasm32"))))]
 mod xr;
 
+pub use bookmarks::{Bookmark, BookmarkCollection, BookmarkError, BookmarkFormat};
+#[cfg(target_arch = "wasm32")]
+pub use cache::BrowserCache;
+pub use cache::{
+    CacheMeta, CacheStats, CacheStore, DiskCache, LimitedCache, Listing, CACHE_VERSION,
+    DEFAULT_CACHE_SIZE,
+};
+pub use flyto::{Easing, FlyToRequest};
+pub use geocoord::GeoCoord;
+pub use geoview::{GeoView, Views};
+pub use http::default_user_agent;
+pub use http_assets::{
+    ClearCache, HttpAssetReaderPlugin, NetworkPolicy, DEFAULT_CACHE_TTL, DEFAULT_MAX_REQUESTS,
+};
+pub use marker::{AddMarker, Marker};
+pub use options::Options;
+pub use player::{CamControlMode, ControlValues};
+pub use search::{
+    Geocoder, NominatimGeocoder, Place, Search, SearchError, SearchRequest, SearchResult,
+};
+pub use share::{camera_height, geo_uri, osm_url, share_url};
+pub use tilemap::{TileCoord, TileEvent, TileIndex, TileMap};
+pub use tour::{Keyframe, Tour, TourPlayer};
+pub use validate::DownloadLimits;
+
 type GridPrecision = i64;
 // "Galctic.." = "Grid.." with GridPrecision included
 type GalacticGrid = GridCell<GridPrecision>;
@@ -45,13 +102,311 @@ type GalacticTransformItem = GridTransform<GridPrecision>;
 
 #[derive(Resource)]
 struct StartingValues {
-    // was Args
-    _args: Vec<String>, // Todo: You never know where you may need it
     planetary_position: PlanetaryPosition,
     view: GeoView,
     cam_control_mode: CamControlMode,
     xr: bool,
     gamification: i8, // May become an enum
+    search: Option<String>,
+    import: Option<String>, // Bookmark file to add to the views
+    export: Option<String>, // Bookmark file to write the views to
+    tour: Option<String>,
+    tour_loop: bool,
+}
+
+/// The OSMeta viewer as a Bevy plugin. Configure it with the builder methods.
+///
+/// By default it also adds Bevy's `DefaultPlugins`, because the tile asset sources
+/// must be registered before the `AssetPlugin`, and the `TransformPlugin` must be disabled
+/// for the floating origin. If your app adds the `DefaultPlugins` itself, use
+/// `.default_plugins(false)` and add, in this order:
+/// * an [`HttpAssetReaderPlugin`] with the tile servers, cache and limits
+/// * `bevy_embedded_assets::EmbeddedAssetPlugin` for the `embedded://` assets
+///   (the compass, the sky and the Galactica)
+/// * `bevy_web_asset::WebAssetPlugin` for assets from `http(s)://` URLs
+/// * your `DefaultPlugins`, with the `TransformPlugin` disabled
+///
+/// VR (`xr=true`) brings its own default plugins, so it is only available with
+/// `.default_plugins(true)`.
+pub struct OsmetaPlugin {
+    options: Options,
+    diagnostics: bool,
+    default_plugins: bool,
+}
+
+impl Default for OsmetaPlugin {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OsmetaPlugin {
+    pub fn new() -> Self {
+        Self::from_options(Options::default())
+    }
+
+    /// Use parsed command line or URL arguments
+    pub fn from_options(options: Options) -> Self {
+        Self {
+            options,
+            diagnostics: true,
+            default_plugins: true,
+        }
+    }
+
+    /// Where the camera starts
+    pub fn start_view(mut self, view: GeoView) -> Self {
+        let options = &mut self.options;
+        options.lat = view.geo_coord.lat;
+        options.lon = view.geo_coord.lon;
+        options.elevation = view.elevation;
+        options.direction = view.direction;
+        options.up_view = view.up_view;
+        options.distance = view.distance;
+        options.camera_fov = view.camera_fov;
+        self
+    }
+
+    /// The server of the 3D tiles: host and path, without `https://`.
+    /// Mirrors are separated by commas, `{a,b}.host/` are subdomains.
+    pub fn tile_server(mut self, base_url: impl Into<String>) -> Self {
+        self.options.tile_server = base_url.into();
+        self
+    }
+
+    /// The server of the flat map tiles, shown where there are no 3D tiles.
+    /// The same format as [`Self::tile_server`].
+    pub fn raster_server(mut self, base_url: impl Into<String>) -> Self {
+        self.options.raster_server = base_url.into();
+        self
+    }
+
+    /// The directory to cache the tiles in. Default is the cache directory of the OS.
+    pub fn cache_dir(mut self, dir: impl Into<String>) -> Self {
+        self.options.cache = Some(dir.into());
+        self
+    }
+
+    /// Don't cache the tiles
+    pub fn no_cache(self) -> Self {
+        self.cache_dir("none")
+    }
+
+    /// Seconds cached tiles are used before asking the server for changes,
+    /// if the server does not tell. Default is [`DEFAULT_CACHE_TTL`].
+    pub fn cache_ttl(mut self, seconds: u64) -> Self {
+        self.options.cache_ttl = seconds;
+        self
+    }
+
+    /// Sent to the tile servers. Public servers require contact information in it,
+    /// like `myapp/1.0 (+https://example.com/contact)`. Default is [`default_user_agent`].
+    /// Browsers send their own user agent instead, the web build can't change it.
+    pub fn user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.options.user_agent = user_agent.into();
+        self
+    }
+
+    /// Downloaded tiles above these limits are rejected and fail like missing ones
+    pub fn download_limits(mut self, limits: DownloadLimits) -> Self {
+        self.options.max_download = limits.max_compressed / (1024 * 1024);
+        self.options.max_unpacked = limits.max_decompressed / (1024 * 1024);
+        self.options.max_texture = limits.max_texture_size;
+        self.options.max_vertices = limits.max_vertices;
+        self
+    }
+
+    /// Parallel requests per tile server host, see [`DEFAULT_MAX_REQUESTS`]
+    pub fn max_requests(mut self, max_requests: usize) -> Self {
+        self.options.max_requests = max_requests;
+        self
+    }
+
+    /// How the network and the tile cache are used, default is online
+    pub fn network_policy(mut self, policy: NetworkPolicy) -> Self {
+        self.options.network = policy;
+        self
+    }
+
+    /// Size limit of the tile cache in MiB, the least recently used tiles are removed above it
+    pub fn cache_size(mut self, mib: u64) -> Self {
+        self.options.cache_size = mib;
+        self
+    }
+
+    pub fn control_mode(mut self, cam_control_mode: CamControlMode) -> Self {
+        self.options.cam_control_mode = cam_control_mode;
+        self
+    }
+
+    /// Use a VR headset (only with the `xr` feature)
+    pub fn xr(mut self, xr: bool) -> Self {
+        self.options.xr = xr;
+        self
+    }
+
+    pub fn gamification(mut self, gamification: i8) -> Self {
+        self.options.gamification = gamification;
+        self
+    }
+
+    /// Serve the JSON-RPC remote control on `address`, like `127.0.0.1:9001` (not in the browser)
+    pub fn remote_control(mut self, address: impl Into<String>) -> Self {
+        self.options.remote = Some(address.into());
+        self
+    }
+
+    /// Allow other computers to use the remote control
+    pub fn remote_public(mut self, public: bool) -> Self {
+        self.options.remote_public = public;
+        self
+    }
+
+    /// Web pages that may use the remote control, comma separated, like
+    /// `https://dashboard.example.org`. Pages of this computer always may.
+    pub fn remote_origins(mut self, origins: impl Into<String>) -> Self {
+        self.options.remote_origins = origins.into();
+        self
+    }
+
+    /// Show FPS and entity counts on the screen
+    pub fn diagnostics(mut self, diagnostics: bool) -> Self {
+        self.diagnostics = diagnostics;
+        self
+    }
+
+    /// Add Bevy's `DefaultPlugins`, the asset sources and VR, see [`OsmetaPlugin`]
+    pub fn default_plugins(mut self, default_plugins: bool) -> Self {
+        self.default_plugins = default_plugins;
+        self
+    }
+}
+
+impl Plugin for OsmetaPlugin {
+    fn build(&self, app: &mut App) {
+        let options = &self.options;
+        let start_view = options.start_view();
+        let cam_control_mode = options.cam_control_mode;
+        let xr = options.xr;
+
+        app.insert_resource(ViewDistance(options.view_distance));
+
+        if xr && !self.default_plugins {
+            warn!(
+                "xr=true needs the default plugins of OSMeta, see `OsmetaPlugin::default_plugins`"
+            );
+        }
+        if self.default_plugins {
+            app.add_plugins(HttpAssetReaderPlugin {
+                base_url: options.tile_server.clone(),
+                raster_url: options.raster_server.clone(),
+                cache: options.cache(),
+                cache_ttl: options.cache_ttl,
+                cache_size: options.cache_size.saturating_mul(1024 * 1024),
+                policy: options.network,
+                user_agent: options.user_agent.clone(),
+                max_requests: options.max_requests,
+                limits: options.download_limits(),
+            });
+
+            // Offer assets via `embedded://`
+            app.add_plugins(EmbeddedAssetPlugin::default());
+            app.add_plugins(bevy_web_asset::WebAssetPlugin {
+                user_agent: Some(options.user_agent.clone()),
+            });
+
+            if xr {
+                #[cfg(all(feature = "xr", not(any(target_os = "macos", target_arch = "wasm32"))))]
+                {
+                    app.add_plugins(xr::Plugin);
+                    app.add_systems(Update, pull_to_ground);
+                }
+            } else {
+                app.add_plugins(DefaultPlugins.build().disable::<TransformPlugin>());
+            }
+        }
+        app.add_plugins(FloatingOriginPlugin::<GridPrecision>::default())
+            .insert_resource(Msaa::Sample4); // Msaa::Sample4  Msaa::default()   -- Todo: tut nichts?
+
+        if self.diagnostics {
+            app.add_plugins(ScreenDiagnosticsPlugin {
+                timestep: 1.0,
+                ..default()
+            })
+            .add_plugins(ScreenFrameDiagnosticsPlugin)
+            .add_plugins(ScreenEntityDiagnosticsPlugin)
+            .add_systems(Startup, setup);
+        }
+        // The view distance is adapted to the FPS
+        if !app.is_plugin_added::<FrameTimeDiagnosticsPlugin>() {
+            app.add_plugins(FrameTimeDiagnosticsPlugin);
+        }
+
+        app.add_plugins(sky::Plugin)
+            .add_plugins(player::Plugin)
+            //.add_systems(Update, init_controls);
+            .init_resource::<ControlValues>();
+
+        match cam_control_mode {
+            CamControlMode::F4 => {
+                app.add_plugins(f4control::Plugin);
+            }
+            CamControlMode::Fly => {
+                app.add_plugins(flycontrol::Plugin)
+                    .add_systems(Update, update_camera_orientations)
+                    .add_systems(PostUpdate, compass::reposition_compass);
+            }
+        }
+
+        app.insert_resource(StartingValues {
+            planetary_position: start_view.geo_coord.into(),
+            view: start_view,
+            cam_control_mode,
+            xr,
+            gamification: options.gamification,
+            search: options.search.clone(),
+            import: options.import.clone(),
+            export: options.export.clone(),
+            tour: options.tour.clone(),
+            tour_loop: options.tour_loop,
+        })
+        .add_plugins(geoview::Plugin)
+        .add_plugins(flyto::Plugin)
+        .add_plugins(tour::Plugin)
+        .add_plugins(marker::Plugin)
+        .add_plugins(share::Plugin)
+        .insert_resource(TileMap::default())
+        .add_plugins(tilemap::Plugin);
+
+        if let Some(path) = &options.gazetteer {
+            app.insert_resource(Search {
+                geocoder: Arc::new(GazetteerGeocoder { path: path.into() }),
+            });
+        }
+        app.add_plugins(search::Plugin);
+
+        #[cfg(not(target_arch = "wasm32"))]
+        app.add_plugins(replay::Plugin {
+            record: options.record.clone().map(Into::into),
+            replay: options.replay.clone().map(Into::into),
+            report: options.report.clone().map(Into::into),
+        });
+        #[cfg(target_arch = "wasm32")]
+        app.add_plugins(web_api::Plugin);
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(address) = &options.remote {
+            app.add_plugins(remote::Plugin {
+                address: address.clone(),
+                public: options.remote_public,
+                origins: options
+                    .remote_origins
+                    .split(',')
+                    .filter(|origin| !origin.is_empty())
+                    .map(Into::into)
+                    .collect(),
+            });
+        }
+    }
 }
 
 #[bevy_main]
@@ -71,6 +426,14 @@ pub fn main() {
         if let Some(addr) = raw_search.strip_prefix('?') {
             args.extend(addr.split('&').map(Into::into));
         }
+        // A view of openstreetmap.org: `#map=zoom/lat/lon`
+        if let Some(map) = location
+            .hash()
+            .ok()
+            .and_then(|hash| hash.strip_prefix("#map=").map(|map| format!("map={map}")))
+        {
+            args.push(map);
+        }
     }
 
     #[cfg(not(target_arch = "wasm32"))]
@@ -79,136 +442,67 @@ pub fn main() {
         args.extend(std::env::args().skip(1));
     }
 
-    let mut cam_control_mode = CamControlMode::Fly; // default: F4,  test: Fly
-
-    let mut geo_coord = GeoCoord {
-        lat: 48.1408, // Germany, Munic, Main railway station
-        lon: 11.5577,
-    };
-    let mut elevation: f32 = 1.4; // default 1.4 for f4control
-
-    // GeoView to city center, Marienplatz
-    let mut direction: f32 = -105.0; // Compass view-direction to Oeast-Southeast. 0 = Nord, -90 = East Todo: Why minus?
-    let mut up_view: f32 = -30.0; // Up-view slightly down. -90 = down, 0 = horizontal 90 = Up
-    let mut distance: f32 = 500.; // radius of the sphere, the arc rotate camera rotates on
-    let mut camera_fov: f32 = 30.; // todo: default?  field of view, the angle widht of the world, the camera is showing
-
-    let mut xr = false;
-    let mut gamification = 2; // 0: off  1: Galactica
-
-    for arg in &args {
-        if arg.is_empty() {
-            continue; // skip unneeded & in the browser URL
-        };
-        let (k, v) = arg
-            .split_once('=')
-            .expect("arguments must be `key=value` pairs");
-        match k {
-            "con" => {
-                let arg: String = v.parse().unwrap();
-                let arg: &str = arg.as_str(); // todo: better rust?
-                match arg {
-                    "fly" => cam_control_mode = CamControlMode::Fly,
-                    "ufo" => cam_control_mode = CamControlMode::Fly,
-                    _ => cam_control_mode = CamControlMode::F4,
-                }
-            }
-            "lat" => geo_coord.lat = v.parse().unwrap(),
-            "lon" => geo_coord.lon = v.parse().unwrap(),
-            "ele" => elevation = v.parse().unwrap(),
-            "view" => up_view = v.parse().unwrap(),
-            "dir" => direction = v.parse().unwrap(),
-            "dist" => distance = v.parse().unwrap(),
-            "fov" => camera_fov = v.parse().unwrap(),
-
-            "xr" => xr = v.parse().unwrap(),
-            "gam" => gamification = v.parse().unwrap(),
-            other => panic!("unknown key `{other}`"),
+    #[cfg(not(target_arch = "wasm32"))]
+    let options = match Options::from_args(&args) {
+        Ok(options) => options,
+        Err(OptionsError::Help) => {
+            println!("{HELP}");
+            return;
+        }
+        Err(err) => {
+            eprintln!("osmeta: {err}\nUse --help to see all options.");
+            std::process::exit(2);
         }
-    }
-
-    let start_view = GeoView {
-        geo_coord,
-        elevation,
-        direction,
-        up_view,
-        distance,
-        camera_fov,
-    };
-
-    let _start_view = GeoView {
-        // test only
-        geo_coord, //: GeoCoord { lat: 33., lon: 0. }, // up,dir
-        elevation: 1000.,
-        direction: 0.,
-        up_view: 0.,
-        distance: 1000.,
-        camera_fov: 44.,
     };
 
-    let mut app = App::new();
-    app.insert_resource(ViewDistance(2000.0));
-    app.add_plugins(HttpAssetReaderPlugin {
-        base_url: "gltiles.osm2world.org/glb/".into(),
-    });
-
-    // Offer assets via `embedded://`
-    app.add_plugins(EmbeddedAssetPlugin::default());
-    app.add_plugins(bevy_web_asset::WebAssetPlugin {
-        user_agent: Some("osmeta 0.1.0".into()),
-    });
-
-    if xr {
-        #[cfg(all(feature = "xr", not(any(target_os = "macos", target_arch = "wasm32"))))]
-        {
-            app.add_plugins(xr::Plugin);
-            app.add_systems(Update, pull_to_ground);
-        }
-    } else {
-        app.add_plugins(DefaultPlugins.build().disable::<TransformPlugin>());
-    }
-    app.add_plugins(FloatingOriginPlugin::<GridPrecision>::default())
-        .insert_resource(Msaa::Sample4) // Msaa::Sample4  Msaa::default()   -- Todo: tut nichts?
-        .add_plugins(ScreenDiagnosticsPlugin {
-            timestep: 1.0,
-            ..default()
-        })
-        .add_plugins(ScreenFrameDiagnosticsPlugin)
-        .add_plugins(ScreenEntityDiagnosticsPlugin)
-        .add_plugins(sky::Plugin)
-        .add_plugins(player::Plugin)
-        //.add_systems(Update, init_controls);
-        .init_resource::<ControlValues>();
-
-    match cam_control_mode {
-        CamControlMode::F4 => {
-            app.add_plugins(f4control::Plugin);
-        }
-        CamControlMode::Fly => {
-            app.add_plugins(flycontrol::Plugin)
-                .add_systems(Update, update_camera_orientations)
-                .add_systems(PostUpdate, compass::reposition_compass);
+    // In the browser, there is no one to read a message before the exit. So just skip bad arguments.
+    #[cfg(target_arch = "wasm32")]
+    let options = {
+        let mut options = Options::default();
+        for arg in &args {
+            if let Err(err) = options.set_arg(arg) {
+                error!("{err}");
+            }
         }
-    }
+        options
+    };
 
-    app.insert_resource(StartingValues {
-        _args: args,
-        planetary_position: geo_coord.to_cartesian(),
-        view: start_view,
-        cam_control_mode,
-        xr,
-        gamification,
-    })
-    .add_plugins(geoview::Plugin)
-    .insert_resource(TileMap::default())
-    .add_systems(Startup, setup)
-    .add_plugins(tilemap::Plugin)
-    .run();
+    App::new()
+        .add_plugins(OsmetaPlugin::from_options(options))
+        .run();
 }
 // todo: check what is different in  oli-obk/bevy_screen_diagnostics
 fn setup(mut diags: ResMut<ScreenDiagnostics>) {
     diags.modify("fps").aggregate(Aggregate::Average);
+    for (name, path) in [
+        ("tiles queued", tilemap::TILES_QUEUED),
+        ("tiles loading", tilemap::TILES_LOADING),
+        ("tiles loaded", tilemap::TILES_LOADED),
+        ("tiles failed", tilemap::TILES_FAILED),
+        ("view distance m", tilemap::VIEW_DISTANCE),
+        ("downloaded MiB", http_assets::NETWORK_BYTES),
+        ("cache hits", http_assets::CACHE_HITS),
+        ("cache misses", http_assets::CACHE_MISSES),
+        ("cache hit %", http_assets::CACHE_HIT_RATIO),
+        ("cache MiB", http_assets::CACHE_SIZE),
+        ("cache entries", http_assets::CACHE_ENTRIES),
+    ] {
+        diags
+            .add(name.into(), path)
+            .aggregate(Aggregate::Value)
+            .format(|value| format!("{value:.0}"));
+    }
+    // Over the last downloads, not only those of the last frame
+    diags
+        .add("tile latency ms".into(), http_assets::TILE_LATENCY)
+        .aggregate(Aggregate::Average)
+        .format(|value| format!("{value:.0}"));
+    diags
+        .add("network".into(), http_assets::NETWORK_POLICY)
+        .aggregate(Aggregate::Value)
+        .format(|value| NetworkPolicy::ALL[value as usize].to_string());
 }
 
+/// The distance in meters up to which tiles are loaded. It is adapted to the FPS.
 #[derive(Resource, Copy, Clone)]
-pub struct ViewDistance(f32);
+pub struct ViewDistance(pub f32);
@@ -0,0 +1,139 @@
+//! Markers: pins standing on geo coordinates, added by the remote control or other tools.
+//! The label of a marker is shown on the screen above its pin.
+//!
+//! To add a marker, send an [`AddMarker`] event.
+
+use bevy::{prelude::*, transform::TransformSystem};
+use serde::{Deserialize, Serialize};
+
+use crate::geocoord::GeoCoord;
+use crate::player::{Control, Directions, PlanetaryPosition};
+
+/// Height of a marker pin in meters
+const PIN_HEIGHT: f32 = 20.0;
+const LABEL_SIZE: f32 = 18.0;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Marker {
+    pub geo_coord: GeoCoord,
+    /// Shown above the pin, none if empty
+    #[serde(default)]
+    pub label: String,
+    /// Elevation of the foot of the pin above the ground
+    #[serde(default)]
+    pub elevation: f32,
+}
+
+/// Send this event to place a marker
+#[derive(Event, Clone)]
+pub struct AddMarker(pub Marker);
+
+/// A placed marker
+#[derive(Component)]
+pub struct MarkerPin(pub Marker);
+
+/// The label of the pin entity, on the screen
+#[derive(Component)]
+struct MarkerLabel(Entity);
+
+#[derive(Resource)]
+struct PinAssets {
+    mesh: Handle<Mesh>,
+    material: Handle<StandardMaterial>,
+}
+
+fn setup(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    commands.insert_resource(PinAssets {
+        mesh: meshes.add(Capsule3d::new(PIN_HEIGHT / 10., PIN_HEIGHT * 0.8)),
+        material: materials.add(StandardMaterial {
+            base_color: Color::rgb(0.9, 0.1, 0.1),
+            unlit: true,
+            ..default()
+        }),
+    });
+}
+
+fn add_markers(mut events: EventReader<AddMarker>, mut commands: Commands, pin: Res<PinAssets>) {
+    for AddMarker(marker) in events.read() {
+        let pos = PlanetaryPosition::from(marker.geo_coord);
+        let Directions { up, north, west: _ } = pos.directions();
+        let mut galactic_transform = pos.to_galactic_transform();
+        // The capsule is centered, stand it on its foot
+        galactic_transform.transform.translation += up * (marker.elevation + PIN_HEIGHT / 2.);
+        galactic_transform.transform.look_to(north, up);
+        info!("marker {:?} at {:?}", marker.label, marker.geo_coord);
+        let pin = commands.spawn((
+            PbrBundle {
+                mesh: pin.mesh.clone(),
+                material: pin.material.clone(),
+                transform: galactic_transform.transform,
+                ..default()
+            },
+            galactic_transform.cell,
+            MarkerPin(marker.clone()),
+        ));
+        let pin = pin.id();
+        if !marker.label.is_empty() {
+            commands.spawn((
+                TextBundle::from_section(
+                    marker.label.clone(),
+                    TextStyle {
+                        font_size: LABEL_SIZE,
+                        color: Color::WHITE,
+                        ..default()
+                    },
+                )
+                .with_style(Style {
+                    position_type: PositionType::Absolute,
+                    ..default()
+                }),
+                // Hidden until placed
+                Visibility::Hidden,
+                MarkerLabel(pin),
+            ));
+        }
+    }
+}
+
+/// Keep the labels above the top of their pins, hide the ones out of view.
+/// The UI layout ran already, the labels move in the next frame.
+fn place_labels(
+    camera: Query<(&Camera, &GlobalTransform), With<Control>>,
+    pins: Query<&GlobalTransform, With<MarkerPin>>,
+    mut labels: Query<(&MarkerLabel, &mut Style, &mut Visibility)>,
+) {
+    let Ok((camera, camera_transform)) = camera.get_single() else {
+        return;
+    };
+    for (MarkerLabel(pin), mut style, mut visibility) in &mut labels {
+        let top = pins
+            .get(*pin)
+            .map(|pin| pin.transform_point(Vec3::Y * PIN_HEIGHT / 2.));
+        match top.map(|top| camera.world_to_viewport(camera_transform, top)) {
+            Ok(Some(position)) => {
+                style.left = Val::Px(position.x);
+                style.top = Val::Px(position.y - LABEL_SIZE * 1.5);
+                *visibility = Visibility::Inherited;
+            }
+            _ => *visibility = Visibility::Hidden,
+        }
+    }
+}
+
+pub struct Plugin;
+
+impl bevy::prelude::Plugin for Plugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<AddMarker>()
+            .add_systems(Startup, setup)
+            .add_systems(Update, add_markers)
+            .add_systems(
+                PostUpdate,
+                place_labels.after(TransformSystem::TransformPropagate),
+            );
+    }
+}
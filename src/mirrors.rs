@@ -0,0 +1,282 @@
+//! Several hosts serving the same files. Requests are spread round-robin over the hosts,
+//! and a host that fails is avoided for a while, so the others take over.
+//!
+//! Hosts are given as a comma separated list of base URLs (host and path, `https://` unless
+//! `http://` is given, as for the mock tile server of the `mock_tiles` example).
+//! A `{a,b,c}` group expands to one host per alternative, for subdomains:
+//! `{a,b,c}.tile.openstreetmap.org/` are the three hosts `a.tile.openstreetmap.org/`, ...
+//!
+//! To follow the usage policies of public tile servers, each host gets only a few requests
+//! at the same time, and no requests while it asked to wait (HTTP 429 with `Retry-After`).
+
+use async_lock::{Semaphore, SemaphoreGuard};
+use std::{
+    sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use crate::bookmarks::now;
+use crate::http::sleep;
+
+/// Seconds a host is avoided after its first failure. It doubles with every further failure.
+const BACKOFF: u64 = 2;
+const MAX_BACKOFF: u64 = 300;
+
+struct Host {
+    base_url: String,
+    /// Failures in a row
+    failures: AtomicU32,
+    /// Seconds since 1970 until the host is avoided
+    down_until: AtomicU64,
+    /// Seconds since 1970 until the host asked to send no requests
+    retry_at: AtomicU64,
+    requests: Semaphore,
+}
+
+pub struct Mirrors {
+    hosts: Vec<Host>,
+    next: AtomicUsize,
+}
+
+/// Split the list at the commas outside of `{}` groups and expand the groups.
+/// Unbalanced braces are taken literally, empty hosts are skipped.
+pub fn expand_mirrors(list: &str) -> Vec<String> {
+    let mut parts = vec![];
+    let mut depth = 0_usize;
+    let mut start = 0;
+    for (i, c) in list.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth = depth.saturating_sub(1),
+            ',' if depth == 0 => {
+                parts.push(&list[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&list[start..]);
+
+    let mut urls = vec![];
+    for part in parts
+        .into_iter()
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+    {
+        match (part.find('{'), part.find('}')) {
+            (Some(open), Some(close)) if open < close => {
+                for alternative in part[open + 1..close].split(',') {
+                    let url = format!(
+                        "{}{}{}",
+                        &part[..open],
+                        alternative.trim(),
+                        &part[close + 1..]
+                    );
+                    if !url.is_empty() {
+                        urls.push(url);
+                    }
+                }
+            }
+            _ => urls.push(part.into()),
+        }
+    }
+    urls
+}
+
+impl Mirrors {
+    /// The hosts of a list (see the module docs), with at most `max_requests` at the same time.
+    /// Panics without any host, the options reject such lists.
+    pub fn new(list: &str, max_requests: usize) -> Self {
+        let hosts = expand_mirrors(list)
+            .into_iter()
+            .map(|base_url| Host {
+                base_url,
+                failures: AtomicU32::new(0),
+                down_until: AtomicU64::new(0),
+                retry_at: AtomicU64::new(0),
+                requests: Semaphore::new(max_requests.max(1)),
+            })
+            .collect::<Vec<_>>();
+        assert!(!hosts.is_empty(), "no host in `{list}`");
+        Self {
+            hosts,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn base_url(&self, host: usize) -> &str {
+        &self.hosts[host].base_url
+    }
+
+    /// All hosts in the order to try them: the next one of the rotation first, failed ones last
+    pub fn order(&self) -> Vec<usize> {
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let now = now();
+        let mut order: Vec<usize> = (0..self.hosts.len())
+            .map(|i| (start + i) % self.hosts.len())
+            .collect();
+        // Stable, so the healthy hosts keep their rotation
+        order.sort_by_key(|&host| {
+            let host = &self.hosts[host];
+            let retry_at = host.retry_at.load(Ordering::Relaxed);
+            host.down_until
+                .load(Ordering::Relaxed)
+                .max(retry_at)
+                .max(now)
+        });
+        order
+    }
+
+    /// Wait until a request to the host is allowed. It is allowed while the guard is kept.
+    pub async fn request(&self, host: usize) -> SemaphoreGuard<'_> {
+        let host = &self.hosts[host];
+        let guard = host.requests.acquire().await;
+        let wait = host.retry_at.load(Ordering::Relaxed).saturating_sub(now());
+        if wait > 0 {
+            sleep(Duration::from_secs(wait)).await;
+        }
+        guard
+    }
+
+    /// The host asked to wait `seconds` before the next request
+    pub fn retry_after(&self, host: usize, seconds: u64) {
+        self.hosts[host]
+            .retry_at
+            .fetch_max(now() + seconds, Ordering::Relaxed);
+    }
+
+    pub fn success(&self, host: usize) {
+        let host = &self.hosts[host];
+        host.failures.store(0, Ordering::Relaxed);
+        host.down_until.store(0, Ordering::Relaxed);
+    }
+
+    pub fn failure(&self, host: usize) {
+        let host = &self.hosts[host];
+        let failures = host.failures.fetch_add(1, Ordering::Relaxed).min(16);
+        let backoff = (BACKOFF << failures).min(MAX_BACKOFF);
+        host.down_until.store(now() + backoff, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::tasks::block_on;
+    use std::time::Instant;
+
+    /// Seconds the host is avoided, give or take the second passing meanwhile
+    fn assert_down(mirrors: &Mirrors, host: usize, seconds: u64) {
+        let down_until = mirrors.hosts[host].down_until.load(Ordering::Relaxed);
+        let down = down_until.saturating_sub(now());
+        assert!(
+            (seconds.saturating_sub(1)..=seconds).contains(&down),
+            "{down} s instead of {seconds} s"
+        );
+    }
+
+    #[test]
+    fn expand() {
+        assert_eq!(expand_mirrors("a.org/"), ["a.org/"]);
+        assert_eq!(
+            expand_mirrors("{a,b,c}.tile.org/"),
+            ["a.tile.org/", "b.tile.org/", "c.tile.org/"]
+        );
+        assert_eq!(
+            expand_mirrors(" x.org/ , http://{a, b}.y.org/tiles/,z.org/"),
+            [
+                "x.org/",
+                "http://a.y.org/tiles/",
+                "http://b.y.org/tiles/",
+                "z.org/"
+            ]
+        );
+        assert_eq!(expand_mirrors("{,www.}x.org/"), ["x.org/", "www.x.org/"]);
+        // Empty parts
+        assert!(expand_mirrors("").is_empty());
+        assert!(expand_mirrors(" , ,").is_empty());
+        assert!(expand_mirrors("{}").is_empty());
+        assert_eq!(expand_mirrors("a/,,b/,"), ["a/", "b/"]);
+        assert_eq!(expand_mirrors("{a,,b}/"), ["a/", "/", "b/"]);
+        // Unbalanced braces
+        assert_eq!(expand_mirrors("{a,b.org/"), ["{a,b.org/"]);
+        assert_eq!(expand_mirrors("a}.org/,b.org/"), ["a}.org/", "b.org/"]);
+        assert_eq!(expand_mirrors("}a{,b}.org/"), ["}a{,b}.org/"]);
+    }
+
+    #[test]
+    fn order() {
+        let mirrors = Mirrors::new("{a,b,c}/", 2);
+        assert_eq!(mirrors.base_url(2), "c/");
+        // Round-robin
+        assert_eq!(mirrors.order(), [0, 1, 2]);
+        assert_eq!(mirrors.order(), [1, 2, 0]);
+        assert_eq!(mirrors.order(), [2, 0, 1]);
+        assert_eq!(mirrors.order(), [0, 1, 2]);
+
+        // Failed hosts last, the longest failed at the end
+        mirrors.failure(0);
+        mirrors.failure(0);
+        mirrors.failure(1);
+        assert_eq!(mirrors.order(), [2, 1, 0]);
+        assert_eq!(mirrors.order(), [2, 1, 0]);
+        mirrors.success(0);
+        assert_eq!(mirrors.order(), [0, 2, 1]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn no_hosts() {
+        Mirrors::new(" , ", 2);
+    }
+
+    #[test]
+    fn backoff() {
+        let mirrors = Mirrors::new("a/,b/", 2);
+        mirrors.failure(0);
+        assert_down(&mirrors, 0, BACKOFF);
+        mirrors.failure(0);
+        assert_down(&mirrors, 0, 2 * BACKOFF);
+        mirrors.failure(0);
+        assert_down(&mirrors, 0, 4 * BACKOFF);
+        for _ in 0..40 {
+            mirrors.failure(0);
+        }
+        assert_down(&mirrors, 0, MAX_BACKOFF);
+        assert_down(&mirrors, 1, 0);
+
+        mirrors.success(0);
+        assert_down(&mirrors, 0, 0);
+        mirrors.failure(0);
+        assert_down(&mirrors, 0, BACKOFF);
+    }
+
+    #[test]
+    fn retry_after() {
+        let mirrors = Mirrors::new("a/,b/", 1);
+        mirrors.retry_after(0, 60);
+        // A shorter wait doesn't shorten the one asked for before
+        mirrors.retry_after(0, 1);
+        let retry_at = mirrors.hosts[0].retry_at.load(Ordering::Relaxed);
+        assert!(retry_at >= now() + 59);
+        // The other host goes first
+        assert_eq!(mirrors.order(), [1, 0]);
+        assert_eq!(mirrors.order(), [1, 0]);
+    }
+
+    #[test]
+    fn request() {
+        let mirrors = Mirrors::new("a/", 1);
+        let guard = block_on(mirrors.request(0));
+        // Only one request at the same time
+        assert!(mirrors.hosts[0].requests.try_acquire().is_none());
+        drop(guard);
+        assert!(mirrors.hosts[0].requests.try_acquire().is_some());
+
+        // At least one second, as the times are in whole seconds
+        mirrors.retry_after(0, 2);
+        let start = Instant::now();
+        drop(block_on(mirrors.request(0)));
+        assert!(start.elapsed() >= Duration::from_secs(1));
+    }
+}
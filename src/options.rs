@@ -0,0 +1,615 @@
+//! Start options of OSMeta, from the command line, the browser URL query and a config file.
+//!
+//! Arguments are `key=value` pairs, like `lat=48.1408 lon=11.5577`. In the browser they are
+//! given as URL query: `?lat=48.1408&lon=11.5577`.
+//! An optional TOML config file uses the same keys (`lat = 48.1408`). Arguments override it.
+//! The config file is `config=<file>` or `osmeta.toml` in the project config directory.
+
+use bevy::log::warn;
+use serde::Deserialize;
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::PathBuf;
+use std::sync::Arc;
+
+#[cfg(target_arch = "wasm32")]
+use crate::cache::BrowserCache;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::cache::DiskCache;
+use crate::cache::{default_cache, CacheStore, DEFAULT_CACHE_SIZE};
+
+use crate::geocoord::GeoCoord;
+use crate::geoview::GeoView;
+use crate::http::default_user_agent;
+use crate::http_assets::{NetworkPolicy, DEFAULT_CACHE_TTL, DEFAULT_MAX_REQUESTS};
+use crate::mirrors::expand_mirrors;
+use crate::player::CamControlMode;
+use crate::share::{height_from_zoom, parse_osm_map};
+use crate::validate::DownloadLimits;
+
+pub const HELP: &str = "\
+OSMeta - OpenStreetMap Metaverse
+
+Usage: osmeta [key=value]...   (browser: index.html?key=value&...)
+
+Start view:
+  lat=<degrees>         latitude (default 48.1408, Munich)
+  lon=<degrees>         longitude (default 11.5577)
+  ele=<meters>          elevation above the ground (default 1.4)
+  view=<degrees>        up-view: -90 = down, 0 = horizontal, 90 = up (default -30)
+  dir=<degrees>         compass direction, 0 = north (default -105)
+  dist=<meters>         distance of the orbit camera to the view point (default 500)
+  fov=<degrees>         vertical field of view (default 30)
+  map=<zoom/lat/lon>    an openstreetmap.org view, as in its links (#map=17/48.1408/11.5577).
+                        The whole link is accepted too, like
+                        'https://www.openstreetmap.org/?mlat=48.14&mlon=11.56#map=17/48.14/11.56'
+                        (quoted in a shell)
+  search=<place>        search a place and fly there (`+` for spaces)
+
+Controls:
+  con=<f4|fly|ufo>      camera control (default fly, f4 for other values)
+  xr=<true|false>       use a VR headset (default false)
+  gam=<number>          gamification: 0 = off, 1 = Galactica (default 2)
+
+Tiles:
+  tiles=<url>           tile server, host and path (default gltiles.osm2world.org/glb/).
+                        https:// unless http:// is given, like tiles=http://localhost:8080/.
+                        Mirrors are separated by commas and used in turn, `{a,b,c}.host/` are subdomains.
+  raster=<url>          server of the flat map tiles where there are no 3D tiles, like tiles=
+                        (default {a,b,c}.tile.openstreetmap.org/)
+  cache=<dir|none>      tile cache directory, `none` to disable (default: the OS cache directory,
+                        in the browser its storage)
+  cache_ttl=<seconds>   use cached tiles this long before asking the server for changes,
+                        if the server doesn't tell (default 604800, a week)
+  cache_size=<MiB>      size limit of the tile cache, the least recently used tiles are removed
+                        (default 1024)
+  network=<policy>      online: use the cache, download the rest (default)
+                        offline: only use cached tiles, even outdated ones
+                        refresh: download all tiles again and update the cache
+                        no-cache: download all tiles, don't use the cache
+  max_requests=<n>      parallel requests per tile server host (default 2)
+  user_agent=<text>     sent to the tile servers, with contact information as required by
+                        public servers (default osmeta/<version> (+https://derkarlos.github.io/OSMeta/))
+  max_download=<MiB>    downloads are rejected above this size (default 32)
+  max_unpacked=<MiB>    and above this size after decompression (default 128)
+  max_texture=<px>      tiles with bigger textures are rejected (default 8192)
+  max_vertices=<n>      tiles with more vertices are rejected (default 4000000)
+  view_distance=<m>     view distance to start with (default 2000)
+
+Files:
+  config=<file>         TOML config file with the same keys (default: osmeta.toml in the config directory)
+  gazetteer=<file>      search in a local file (`name;lat;lon` lines) instead of Nominatim
+  import=<file>         import bookmarks (.json or .toml)
+  export=<file>         write all bookmarks to this file (.json or .toml), again when one is stored
+  tour=<file>           play and record a camera tour (.json)
+  loop=<true|false>     repeat the tour (default false)
+  record=<file>         record the session for a benchmark
+  replay=<file>         replay a recorded session
+  report=<file>         CSV report of the replay (default: the replay file with extension csv)
+
+Remote control:
+  remote=<host:port>    JSON-RPC over WebSocket for other tools, like 127.0.0.1:9001 (default off)
+  remote_public=<true|false>
+                        allow other computers to connect, not only this one (default false)
+  remote_origins=<urls> web pages that may connect, besides the ones of this computer,
+                        separated by commas, like https://dashboard.example.org
+
+  --help                show this text
+";
+
+#[derive(Debug)]
+pub enum OptionsError {
+    /// The argument is not a `key=value` pair
+    NoPair(String),
+    UnknownKey(String),
+    InvalidValue {
+        key: String,
+        value: String,
+        expected: &'static str,
+    },
+    ConfigFile(String),
+    /// `--help` was given
+    Help,
+}
+
+impl std::fmt::Display for OptionsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OptionsError::NoPair(arg) => {
+                write!(f, "argument `{arg}` must be a `key=value` pair")
+            }
+            OptionsError::UnknownKey(key) => write!(f, "unknown key `{key}`"),
+            OptionsError::InvalidValue {
+                key,
+                value,
+                expected,
+            } => write!(
+                f,
+                "invalid value `{value}` for `{key}`, expected {expected}"
+            ),
+            OptionsError::ConfigFile(msg) => write!(f, "config file: {msg}"),
+            OptionsError::Help => f.write_str(HELP),
+        }
+    }
+}
+
+/// All start options. The serde names are the argument keys.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Options {
+    #[serde(rename = "con", deserialize_with = "deserialize_control")]
+    pub cam_control_mode: CamControlMode,
+    pub lat: f32,
+    pub lon: f32,
+    #[serde(rename = "ele")]
+    pub elevation: f32,
+    #[serde(rename = "view")]
+    pub up_view: f32,
+    #[serde(rename = "dir")]
+    pub direction: f32,
+    #[serde(rename = "dist")]
+    pub distance: f32,
+    #[serde(rename = "fov")]
+    pub camera_fov: f32,
+    /// Zoom level of an openstreetmap.org link, sets the camera height
+    #[serde(skip)]
+    pub map_zoom: Option<f32>,
+    pub xr: bool,
+    #[serde(rename = "gam")]
+    pub gamification: i8, // May become an enum
+    pub search: Option<String>,
+    #[serde(rename = "tiles", deserialize_with = "deserialize_servers")]
+    pub tile_server: String,
+    #[serde(rename = "raster", deserialize_with = "deserialize_servers")]
+    pub raster_server: String,
+    pub cache: Option<String>,
+    pub cache_ttl: u64,  // Seconds until cached tiles are revalidated
+    pub cache_size: u64, // MiB
+    pub network: NetworkPolicy,
+    pub max_requests: usize,
+    pub user_agent: String,
+    pub max_download: usize, // MiB
+    pub max_unpacked: usize, // MiB
+    pub max_texture: u32,
+    pub max_vertices: u64,
+    pub view_distance: f32,
+    pub gazetteer: Option<String>,
+    pub import: Option<String>, // Bookmark file to add to the views
+    pub export: Option<String>, // Bookmark file to write the views to
+    pub tour: Option<String>,
+    #[serde(rename = "loop")]
+    pub tour_loop: bool,
+    pub record: Option<String>, // Session recording and replay for benchmarks
+    pub replay: Option<String>,
+    pub report: Option<String>,
+    pub remote: Option<String>, // Address of the remote control
+    pub remote_public: bool,
+    pub remote_origins: String, // Comma separated
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        let limits = DownloadLimits::default();
+        Self {
+            cam_control_mode: CamControlMode::Fly, // default: F4,  test: Fly
+            // Germany, Munic, Main railway station
+            lat: 48.1408,
+            lon: 11.5577,
+            elevation: 1.4, // default 1.4 for f4control
+            // GeoView to city center, Marienplatz
+            direction: -105.0, // Compass view-direction to Oeast-Southeast. 0 = Nord, -90 = East Todo: Why minus?
+            up_view: -30.0,    // Up-view slightly down. -90 = down, 0 = horizontal 90 = Up
+            distance: 500.,    // radius of the sphere, the arc rotate camera rotates on
+            camera_fov: 30.,   // field of view, the angle widht of the world, the camera is showing
+            map_zoom: None,
+            xr: false,
+            gamification: 2, // 0: off  1: Galactica
+            search: None,
+            tile_server: "gltiles.osm2world.org/glb/".into(),
+            raster_server: "{a,b,c}.tile.openstreetmap.org/".into(),
+            cache: None,
+            cache_ttl: DEFAULT_CACHE_TTL,
+            cache_size: DEFAULT_CACHE_SIZE / (1024 * 1024),
+            network: NetworkPolicy::Online,
+            max_requests: DEFAULT_MAX_REQUESTS,
+            user_agent: default_user_agent(),
+            max_download: limits.max_compressed / (1024 * 1024),
+            max_unpacked: limits.max_decompressed / (1024 * 1024),
+            max_texture: limits.max_texture_size,
+            max_vertices: limits.max_vertices,
+            view_distance: 2000.0,
+            gazetteer: None,
+            import: None,
+            export: None,
+            tour: None,
+            tour_loop: false,
+            record: None,
+            replay: None,
+            report: None,
+            remote: None,
+            remote_public: false,
+            remote_origins: String::new(),
+        }
+    }
+}
+
+/// Other values fall back to F4, as in the first versions
+fn parse_control(value: &str) -> CamControlMode {
+    match value {
+        "fly" | "ufo" => CamControlMode::Fly,
+        "f4" => CamControlMode::F4,
+        other => {
+            warn!("unknown camera control `con={other}`, using f4");
+            CamControlMode::F4
+        }
+    }
+}
+
+fn deserialize_control<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<CamControlMode, D::Error> {
+    Ok(parse_control(&String::deserialize(deserializer)?))
+}
+
+const SERVERS: &str = "one or more hosts, separated by commas";
+
+/// A list of [`crate::mirrors`], with at least one host
+fn parse_servers(key: &str, value: &str) -> Result<String, OptionsError> {
+    if expand_mirrors(value).is_empty() {
+        return Err(OptionsError::InvalidValue {
+            key: key.into(),
+            value: value.into(),
+            expected: SERVERS,
+        });
+    }
+    Ok(value.into())
+}
+
+fn deserialize_servers<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<String, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    if expand_mirrors(&value).is_empty() {
+        return Err(serde::de::Error::invalid_value(
+            serde::de::Unexpected::Str(&value),
+            &SERVERS,
+        ));
+    }
+    Ok(value)
+}
+
+fn parse<T: std::str::FromStr>(
+    key: &str,
+    value: &str,
+    expected: &'static str,
+) -> Result<T, OptionsError> {
+    value.parse().map_err(|_| OptionsError::InvalidValue {
+        key: key.into(),
+        value: value.into(),
+        expected,
+    })
+}
+
+impl Options {
+    /// Read the config file (if any) and apply the arguments
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn from_args(args: &[String]) -> Result<Self, OptionsError> {
+        if args
+            .iter()
+            .any(|arg| matches!(arg.as_str(), "--help" | "-h" | "help"))
+        {
+            return Err(OptionsError::Help);
+        }
+        let config = args
+            .iter()
+            .find_map(|arg| arg.strip_prefix("config="))
+            .map(PathBuf::from);
+        let mut options = Self::from_config_file(config)?;
+        for arg in args {
+            options.set_arg(arg)?;
+        }
+        Ok(options)
+    }
+
+    /// Parse a `key=value` argument and set the option
+    pub fn set_arg(&mut self, arg: &str) -> Result<(), OptionsError> {
+        if arg.is_empty() {
+            return Ok(()); // skip unneeded & in the browser URL
+        };
+        // A pasted openstreetmap.org link, its query (`?mlat=`) may come before the `#map=`
+        if let Some((_, map)) = arg.split_once("#map=") {
+            return self.set_map(map);
+        }
+        let (k, v) = arg
+            .split_once('=')
+            .ok_or_else(|| OptionsError::NoPair(arg.into()))?;
+        const NUMBER: &str = "a number";
+        const BOOL: &str = "true or false";
+        match k {
+            "con" => self.cam_control_mode = parse_control(v),
+            "lat" => self.lat = parse(k, v, NUMBER)?,
+            "lon" => self.lon = parse(k, v, NUMBER)?,
+            "ele" => self.elevation = parse(k, v, NUMBER)?,
+            "view" => self.up_view = parse(k, v, NUMBER)?,
+            "dir" => self.direction = parse(k, v, NUMBER)?,
+            "dist" => self.distance = parse(k, v, NUMBER)?,
+            "fov" => self.camera_fov = parse(k, v, NUMBER)?,
+            "map" => self.set_map(v)?,
+
+            "xr" => self.xr = parse(k, v, BOOL)?,
+            "gam" => self.gamification = parse(k, v, "a small number")?,
+            "search" => self.search = Some(v.replace('+', " ")),
+            "tiles" => self.tile_server = parse_servers(k, v)?,
+            "raster" => self.raster_server = parse_servers(k, v)?,
+            "cache" => self.cache = Some(v.into()),
+            "cache_ttl" => self.cache_ttl = parse(k, v, "a number of seconds")?,
+            "cache_size" => self.cache_size = parse(k, v, "a number of MiB")?,
+            "max_requests" => self.max_requests = parse(k, v, "a number")?,
+            "user_agent" => self.user_agent = v.into(),
+            "max_download" => self.max_download = parse(k, v, "a number of MiB")?,
+            "max_unpacked" => self.max_unpacked = parse(k, v, "a number of MiB")?,
+            "max_texture" => self.max_texture = parse(k, v, "a number of pixels")?,
+            "max_vertices" => self.max_vertices = parse(k, v, "a number")?,
+            "network" => self.network = parse(k, v, "online, offline, refresh or no-cache")?,
+            "view_distance" => self.view_distance = parse(k, v, NUMBER)?,
+            "config" => (), // Already read by `from_args`, no files in the browser
+            "gazetteer" => self.gazetteer = Some(v.into()),
+            "import" => self.import = Some(v.into()),
+            "export" => self.export = Some(v.into()),
+            "tour" => self.tour = Some(v.into()),
+            "loop" => self.tour_loop = parse(k, v, BOOL)?,
+            "record" => self.record = Some(v.into()),
+            "replay" => self.replay = Some(v.into()),
+            "report" => self.report = Some(v.into()),
+            "remote" => self.remote = Some(v.into()),
+            "remote_public" => self.remote_public = parse(k, v, BOOL)?,
+            "remote_origins" => self.remote_origins = v.into(),
+            other => return Err(OptionsError::UnknownKey(other.into())),
+        }
+        Ok(())
+    }
+
+    /// The `zoom/lat/lon` of an openstreetmap.org link
+    fn set_map(&mut self, value: &str) -> Result<(), OptionsError> {
+        let (zoom, GeoCoord { lat, lon }) =
+            parse_osm_map(value).ok_or_else(|| OptionsError::InvalidValue {
+                key: "map".into(),
+                value: value.into(),
+                expected: "zoom/lat/lon",
+            })?;
+        self.map_zoom = Some(zoom);
+        self.lat = lat;
+        self.lon = lon;
+        Ok(())
+    }
+
+    /// The options of a TOML file, or the defaults if there is no file.
+    /// A missing default config file is fine, a missing given `config=` file is an error.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn from_config_file(path: Option<PathBuf>) -> Result<Self, OptionsError> {
+        let required = path.is_some();
+        let Some(path) = path.or_else(|| {
+            directories::ProjectDirs::from("org", "osmeta", "OSMeta")
+                .map(|dirs| dirs.config_dir().join("osmeta.toml"))
+        }) else {
+            return Ok(Self::default());
+        };
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(_) if !required => return Ok(Self::default()),
+            Err(err) => return Err(OptionsError::ConfigFile(format!("{path:?}: {err}"))),
+        };
+        toml::from_str(&text).map_err(|err| OptionsError::ConfigFile(format!("{path:?}: {err}")))
+    }
+
+    pub fn geo_coord(&self) -> GeoCoord {
+        GeoCoord {
+            lat: self.lat,
+            lon: self.lon,
+        }
+    }
+
+    pub fn start_view(&self) -> GeoView {
+        let mut view = GeoView {
+            geo_coord: self.geo_coord(),
+            elevation: self.elevation,
+            direction: self.direction,
+            up_view: self.up_view,
+            distance: self.distance,
+            camera_fov: self.camera_fov,
+        };
+        if let Some(zoom) = self.map_zoom {
+            // Show about the area of the map
+            let height = height_from_zoom(zoom, self.lat);
+            match self.cam_control_mode {
+                CamControlMode::F4 => view.distance = height,
+                CamControlMode::Fly => view.elevation = height,
+            }
+        }
+        view
+    }
+
+    /// The download limits in bytes
+    pub fn download_limits(&self) -> DownloadLimits {
+        DownloadLimits {
+            // Huge values mean no limit, not an overflow on 32 bit targets like wasm
+            max_compressed: self.max_download.saturating_mul(1024 * 1024),
+            max_decompressed: self.max_unpacked.saturating_mul(1024 * 1024),
+            max_texture_size: self.max_texture,
+            max_vertices: self.max_vertices,
+        }
+    }
+
+    /// The tile cache: the given directory (the name of the browser cache on the web),
+    /// none or the default of the platform
+    pub fn cache(&self) -> Option<Arc<dyn CacheStore>> {
+        match self.cache.as_deref() {
+            Some("none") => None,
+            #[cfg(target_arch = "wasm32")]
+            Some(name) => Some(Arc::new(BrowserCache { name: name.into() })),
+            #[cfg(not(target_arch = "wasm32"))]
+            Some(dir) => Some(Arc::new(DiskCache { root: dir.into() })),
+            None => default_cache(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(args: &[&str]) -> Result<Options, OptionsError> {
+        let mut options = Options::default();
+        for arg in args {
+            options.set_arg(arg)?;
+        }
+        Ok(options)
+    }
+
+    #[test]
+    fn set_arg() {
+        let options =
+            options(&["lat=50.5", "", "lon=-2", "search=Marien+Platz", "loop=true"]).unwrap();
+        assert_eq!(
+            options.geo_coord(),
+            GeoCoord {
+                lat: 50.5,
+                lon: -2.
+            }
+        );
+        assert_eq!(options.search.as_deref(), Some("Marien Platz"));
+        assert!(options.tour_loop);
+        assert!(matches!(
+            self::options(&["lat"]),
+            Err(OptionsError::NoPair(arg)) if arg == "lat"
+        ));
+        assert!(matches!(
+            self::options(&["latitude=1"]),
+            Err(OptionsError::UnknownKey(key)) if key == "latitude"
+        ));
+        assert!(matches!(
+            self::options(&["lat=north"]),
+            Err(OptionsError::InvalidValue { key, .. }) if key == "lat"
+        ));
+        assert!(matches!(
+            self::options(&["network=sometimes"]),
+            Err(OptionsError::InvalidValue { key, .. }) if key == "network"
+        ));
+    }
+
+    #[test]
+    fn servers() {
+        let options = options(&["tiles=a/,b/", "raster={x,y}.example.org/"]).unwrap();
+        assert_eq!(options.tile_server, "a/,b/");
+        assert_eq!(options.raster_server, "{x,y}.example.org/");
+        for arg in ["tiles=", "raster= , ", "tiles={}"] {
+            assert!(
+                matches!(
+                    self::options(&[arg]),
+                    Err(OptionsError::InvalidValue { .. })
+                ),
+                "{arg}"
+            );
+        }
+        assert!(toml::from_str::<Options>("tiles = \"\"").is_err());
+        assert!(toml::from_str::<Options>("raster = \"a/\"").is_ok());
+    }
+
+    #[test]
+    fn download_limits() {
+        let limits = options(&["max_download=2", "max_vertices=10"])
+            .unwrap()
+            .download_limits();
+        assert_eq!(limits.max_compressed, 2 * 1024 * 1024);
+        assert_eq!(
+            limits.max_decompressed,
+            DownloadLimits::default().max_decompressed
+        );
+        assert_eq!(limits.max_vertices, 10);
+        let max = options(&[&format!("max_unpacked={}", usize::MAX)])
+            .unwrap()
+            .download_limits();
+        assert_eq!(max.max_decompressed, usize::MAX);
+    }
+
+    #[test]
+    fn control() {
+        let control = |value: &str| {
+            options(&[&format!("con={value}")])
+                .unwrap()
+                .cam_control_mode
+        };
+        assert_eq!(control("fly"), CamControlMode::Fly);
+        assert_eq!(control("ufo"), CamControlMode::Fly);
+        assert_eq!(control("f4"), CamControlMode::F4);
+        // The fallback of the first versions
+        assert_eq!(control("orbit"), CamControlMode::F4);
+    }
+
+    #[test]
+    fn config_file() {
+        let options: Options = toml::from_str(
+            "lat = 50.5\ncon = \"f4\"\ntiles = \"http://localhost:8080/\"\ncache_size = 64\n",
+        )
+        .unwrap();
+        assert_eq!(options.lat, 50.5);
+        assert_eq!(options.lon, Options::default().lon);
+        assert_eq!(options.cam_control_mode, CamControlMode::F4);
+        assert_eq!(options.tile_server, "http://localhost:8080/");
+        assert_eq!(options.cache_size, 64);
+
+        let unknown = toml::from_str::<Options>("latitude = 50.5").unwrap_err();
+        assert!(unknown.to_string().contains("latitude"), "{unknown}");
+        assert!(toml::from_str::<Options>("lat = \"north\"").is_err());
+        // `map_zoom` is no key
+        assert!(toml::from_str::<Options>("map_zoom = 17.0").is_err());
+    }
+
+    #[test]
+    fn map() {
+        let options = options(&["map=17/48.1408/11.5577"]).unwrap();
+        assert_eq!(options.map_zoom, Some(17.));
+        assert_eq!(
+            options.geo_coord(),
+            GeoCoord {
+                lat: 48.1408,
+                lon: 11.5577
+            }
+        );
+        let view = options.start_view();
+        assert_eq!(view.elevation, height_from_zoom(17., 48.1408));
+        assert_eq!(view.distance, Options::default().distance);
+
+        let f4 = self::options(&["con=f4", "map=17/48.1408/11.5577"]).unwrap();
+        let view = f4.start_view();
+        assert_eq!(view.elevation, Options::default().elevation);
+        assert_eq!(view.distance, height_from_zoom(17., 48.1408));
+
+        for link in [
+            "https://www.openstreetmap.org/#map=12/51.5/-0.12",
+            "https://www.openstreetmap.org/#map=12/51.5/-0.12&layers=C",
+            "https://www.openstreetmap.org/?mlat=51.6&mlon=-0.1#map=12/51.5/-0.12",
+            "www.openstreetmap.org/?mlat=51.6&mlon=-0.1#map=12/51.5/-0.12&layers=H",
+            "map=12/51.5/-0.12&layers=C",
+        ] {
+            let options = self::options(&[link]).unwrap();
+            assert_eq!(options.map_zoom, Some(12.), "{link}");
+            assert_eq!(
+                options.geo_coord(),
+                GeoCoord {
+                    lat: 51.5,
+                    lon: -0.12
+                },
+                "{link}"
+            );
+        }
+        assert!(matches!(
+            self::options(&["https://www.openstreetmap.org/?mlat=51.6&mlon=-0.1"]),
+            Err(OptionsError::UnknownKey(_))
+        ));
+
+        assert!(matches!(
+            self::options(&["map=17/48.1408"]),
+            Err(OptionsError::InvalidValue { key, .. }) if key == "map"
+        ));
+    }
+}
@@ -41,6 +41,7 @@ pub struct PlayerQuery<'w, 's> {
         GalacticTransform,
         (With<Control>, Without<OpenXRTrackingRoot>, Without<Compass>),
     >,
+    pub(crate) projection: Query<'w, 's, &'static Projection, With<Control>>,
     //removed: pub(crate) space: Res<'w, FloatingOriginSettings>,
 }
 
@@ -57,6 +58,14 @@ impl From<PlanetaryPosition> for DVec3 {
     }
 }
 
+impl From<GeoCoord> for PlanetaryPosition {
+    fn from(value: GeoCoord) -> Self {
+        Self {
+            pos: value.to_cartesian(),
+        }
+    }
+}
+
 impl std::ops::Deref for PlanetaryPosition {
     type Target = DVec3;
 
@@ -151,6 +160,14 @@ impl<'w, 's> PlayerQuery<'w, 's> {
         PlayerGalacticTransform { galactic_transform }
     }
 
+    /// The vertical field of view of the camera in degrees
+    pub fn fov(&self) -> f32 {
+        match self.projection.get_single() {
+            Ok(Projection::Perspective(perspective)) => perspective.fov.to_degrees(),
+            _ => GeoView::default().camera_fov,
+        }
+    }
+
     pub fn set_pos(&mut self, new_pos: GalacticTransformOwned) {
         let mut pos = if let Ok(xr_pos) = self.xr_pos.get_single_mut() {
             xr_pos
@@ -166,7 +183,7 @@ impl<'w, 's> PlayerQuery<'w, 's> {
 #[derive(Component)]
 pub struct Control;
 
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum CamControlMode {
     F4,
     Fly,
@@ -278,7 +295,14 @@ pub fn setup_player_controls(
         .id();
 
     let mut camera = commands.spawn((
-        Camera3dBundle { ..default() },
+        Camera3dBundle {
+            projection: PerspectiveProjection {
+                fov: starting_values.view.camera_fov.to_radians(),
+                ..default()
+            }
+            .into(),
+            ..default()
+        },
         InheritedVisibility::default(),
         Control,
         grid,
@@ -302,6 +326,42 @@ pub fn setup_player_controls(
     // regions where the floating point numbers become imprecise.
 }
 
+/// Keys to change the field of view, in all control modes
+fn fov_keys(
+    keys: Res<ButtonInput<KeyCode>>,
+    time: Res<Time>,
+    mut control_values: ResMut<ControlValues>,
+) {
+    let fov_fakt = 1. + time.delta_seconds() / 2.0;
+    let view = &mut control_values.view;
+    if keys.pressed(KeyCode::Minus) {
+        view.camera_fov *= fov_fakt; // wider, zoom out
+    } else if keys.pressed(KeyCode::Equal) {
+        view.camera_fov /= fov_fakt; // narrower, zoom in
+    } else {
+        return;
+    }
+    view.limit();
+}
+
+/// The camera projection follows the field of view of the actual [`GeoView`]
+fn apply_camera_fov(
+    control_values: Res<ControlValues>,
+    mut projections: Query<&mut Projection, With<Control>>,
+) {
+    let fov = control_values.view.camera_fov.to_radians();
+    for mut projection in projections.iter_mut() {
+        if let Projection::Perspective(perspective) = projection.as_ref() {
+            if perspective.fov == fov {
+                continue; // Don't trigger change detection
+            }
+        }
+        if let Projection::Perspective(perspective) = projection.as_mut() {
+            perspective.fov = fov;
+        }
+    }
+}
+
 pub struct Plugin;
 
 impl bevy::prelude::Plugin for Plugin {
@@ -309,7 +369,8 @@ impl bevy::prelude::Plugin for Plugin {
         app.init_resource::<ControlValues>()
             .init_resource::<InputState>()
             .add_systems(Startup, setup_player_controls)
-            .add_systems(Update, update_camera_speed);
+            .add_systems(Update, update_camera_speed)
+            .add_systems(Update, (fov_keys, apply_camera_fov).chain());
     }
 }
 
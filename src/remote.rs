@@ -0,0 +1,515 @@
+//! Remote control of the viewer by other tools (a dashboard, a test script): JSON-RPC 2.0
+//! over a local WebSocket. Start it with the argument `remote=127.0.0.1:9001`.
+//!
+//! Only this computer may connect, unless `remote_public=true` is given. Browser pages may only
+//! connect if they are served from this computer too, or from one of the `remote_origins`.
+//! Requests without an `id` are notifications, they get no response.
+//!
+//! Methods:
+//! * `getView` returns the actual [`GeoView`]
+//! * `setView` `{"view": GeoView, "fly": true}` jumps or flies to the view
+//! * `listBookmarks` returns all stored [`Bookmark`]s
+//! * `applyBookmark` `{"id": "Digit1"}` flies to a bookmark
+//! * `storeBookmark` `{"id": "Digit1", "name": "Munich", "description": "..."}` stores the actual
+//!   view as bookmark. Name and description are optional, a replaced bookmark keeps its own.
+//! * `addMarker` `{"geo_coord": {"lat": 48.1, "lon": 11.5}, "label": "Here"}` places a [`Marker`]
+//! * `cacheStats` returns the [`CacheStats`] of the tile cache
+//! * `clearCache` `{"source": "tile"}` removes the cached files of a source (`default`, `tile`
+//!   or `raster`), all without a source
+//! * `subscribe` / `unsubscribe` to `view` notifications, sent when the camera moved
+//!
+//! Try it with: `echo '{"jsonrpc":"2.0","id":1,"method":"getView"}' | websocat ws://127.0.0.1:9001`
+
+use bevy::prelude::*;
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{json, Value};
+use std::{
+    io::ErrorKind,
+    net::{TcpListener, TcpStream},
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tungstenite::{
+    handshake::server::{ErrorResponse, Request as HttpRequest, Response as HttpResponse},
+    http::StatusCode,
+    Error as WsError, Message,
+};
+
+use crate::bookmarks::Bookmark;
+use crate::cache::CacheStats;
+use crate::flyto::FlyToRequest;
+use crate::geoview::{GeoView, Views};
+use crate::http_assets::ClearCache;
+use crate::marker::{AddMarker, Marker};
+use crate::player::{ControlValues, PlayerQuery};
+
+/// Seconds between two `view` notifications, at most
+const NOTIFY_INTERVAL: f32 = 0.2;
+
+// JSON-RPC 2.0 error codes
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+type RpcError = (i64, String);
+
+#[derive(Deserialize)]
+struct Request {
+    /// None for notifications. A `null` id is Some.
+    #[serde(default, deserialize_with = "some")]
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+fn some<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Option<Value>, D::Error> {
+    Value::deserialize(deserializer).map(Some)
+}
+
+#[derive(Deserialize)]
+struct SetView {
+    view: GeoView,
+    #[serde(default)]
+    fly: bool,
+}
+
+#[derive(Deserialize)]
+struct ApplyBookmark {
+    id: String,
+}
+
+#[derive(Deserialize)]
+struct StoreBookmark {
+    id: String,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    description: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+struct ClearCacheParams {
+    #[serde(default)]
+    source: Option<String>,
+}
+
+/// A connected tool. Messages sent to it are written to its WebSocket.
+#[derive(Clone)]
+struct Client {
+    id: usize,
+    sender: Sender<String>,
+}
+
+impl Client {
+    fn send(&self, message: Value) -> bool {
+        self.sender.send(message.to_string()).is_ok()
+    }
+}
+
+/// A message received from a client
+struct Incoming {
+    client: Client,
+    text: String,
+}
+
+#[derive(Resource)]
+struct RemoteServer {
+    incoming: Mutex<Receiver<Incoming>>,
+    subscribers: Vec<Client>,
+    /// The last notified view, to skip unchanged ones
+    last_view: Option<GeoView>,
+    since_notify: f32,
+}
+
+/// Browser pages served from this computer, or from one of the `allowed` origins, like
+/// `https://dashboard.example.org`
+fn origin_allowed(origin: &str, allowed: &[String]) -> bool {
+    if allowed
+        .iter()
+        .any(|allowed| allowed.trim_end_matches('/') == origin)
+    {
+        return true;
+    }
+    let Some((_scheme, host)) = origin.split_once("://") else {
+        return false; // Like `null` of local files
+    };
+    let host = match host.strip_prefix('[') {
+        Some(ipv6) => ipv6.split(']').next().unwrap_or_default(),
+        None => host.split(':').next().unwrap_or_default(),
+    };
+    matches!(host, "localhost" | "127.0.0.1" | "::1")
+}
+
+/// Accepts connections, each is served by its own thread
+fn listen(listener: TcpListener, incoming: Sender<Incoming>, origins: Arc<Vec<String>>) {
+    for (id, stream) in listener.incoming().enumerate() {
+        match stream {
+            Ok(stream) => {
+                let incoming = incoming.clone();
+                let origins = origins.clone();
+                std::thread::spawn(move || match serve(id, stream, incoming, &origins) {
+                    Ok(()) => debug!("remote client {id} disconnected"),
+                    Err(err) => warn!("remote client {id}: {err}"),
+                });
+            }
+            Err(err) => warn!("remote control connection failed: {err}"),
+        }
+    }
+}
+
+// The error response of tungstenite is large
+#[allow(clippy::result_large_err)]
+fn serve(
+    id: usize,
+    stream: TcpStream,
+    incoming: Sender<Incoming>,
+    origins: &[String],
+) -> Result<(), String> {
+    // Tools send no origin, browsers always do
+    let check_origin = |request: &HttpRequest, response: HttpResponse| match request
+        .headers()
+        .get("origin")
+        .map(|origin| origin.to_str())
+    {
+        Some(Ok(origin)) if !origin_allowed(origin, origins) => {
+            let mut error = ErrorResponse::new(Some(format!("origin {origin} not allowed")));
+            *error.status_mut() = StatusCode::FORBIDDEN;
+            Err(error)
+        }
+        Some(Err(_)) => {
+            let mut error = ErrorResponse::new(Some("invalid origin".into()));
+            *error.status_mut() = StatusCode::BAD_REQUEST;
+            Err(error)
+        }
+        _ => Ok(response),
+    };
+    let mut socket =
+        tungstenite::accept_hdr(stream, check_origin).map_err(|err| err.to_string())?;
+    // Don't block on reading, to send responses and notifications in between
+    socket
+        .get_ref()
+        .set_read_timeout(Some(Duration::from_millis(20)))
+        .map_err(|err| err.to_string())?;
+    debug!("remote client {id} connected");
+    let (sender, outgoing) = channel();
+    let client = Client { id, sender };
+    loop {
+        match socket.read() {
+            Ok(Message::Text(text)) => {
+                let message = Incoming {
+                    client: client.clone(),
+                    text,
+                };
+                if incoming.send(message).is_err() {
+                    return Ok(()); // The app ended
+                }
+            }
+            Ok(_) => {} // Ping, pong and close are answered by tungstenite
+            Err(WsError::Io(err))
+                if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+            Err(WsError::ConnectionClosed) => return Ok(()),
+            Err(err) => return Err(err.to_string()),
+        }
+        while let Ok(text) = outgoing.try_recv() {
+            socket
+                .send(Message::Text(text))
+                .map_err(|err| err.to_string())?;
+        }
+    }
+}
+
+fn params<T: DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    serde_json::from_value(params).map_err(|err| (INVALID_PARAMS, err.to_string()))
+}
+
+fn handle_requests(
+    mut server: ResMut<RemoteServer>,
+    mut player: PlayerQuery,
+    mut control_values: ResMut<ControlValues>,
+    mut views: ResMut<Views>,
+    mut fly_to: EventWriter<FlyToRequest>,
+    mut markers: EventWriter<AddMarker>,
+    mut clear_cache: EventWriter<ClearCache>,
+    cache_stats: Res<CacheStats>,
+) {
+    let received: Vec<Incoming> = server.incoming.lock().unwrap().try_iter().collect();
+    for Incoming { client, text } in received {
+        let request = match parse_request(&text) {
+            Ok(request) => request,
+            Err(error) => {
+                client.send(error);
+                continue;
+            }
+        };
+        let result = match request.method.as_str() {
+            "getView" => Ok(json!(GeoView::from_control(&player, &control_values))),
+            "setView" => params::<SetView>(request.params).map(|SetView { mut view, fly }| {
+                view.limit();
+                if fly {
+                    fly_to.send(FlyToRequest::new(view));
+                } else {
+                    view.set_camera_view(&mut player, &mut control_values);
+                }
+                json!(true)
+            }),
+            "listBookmarks" => {
+                let mut bookmarks: Vec<&Bookmark> = views.map.values().collect();
+                bookmarks.sort_by(|a, b| a.id.cmp(&b.id));
+                Ok(json!(bookmarks))
+            }
+            "applyBookmark" => {
+                params::<ApplyBookmark>(request.params).and_then(|ApplyBookmark { id }| {
+                    let bookmark = views
+                        .map
+                        .get(&id)
+                        .ok_or_else(|| (INVALID_PARAMS, format!("no bookmark `{id}`")))?;
+                    fly_to.send(FlyToRequest::new(bookmark.view));
+                    Ok(json!(true))
+                })
+            }
+            "storeBookmark" => params::<StoreBookmark>(request.params).map(
+                |StoreBookmark {
+                     id,
+                     name,
+                     description,
+                 }| {
+                    let view = GeoView::from_control(&player, &control_values);
+                    view.store_bookmark(id, name, description, &mut views.map);
+                    views.save();
+                    json!(true)
+                },
+            ),
+            "addMarker" => params::<Marker>(request.params).map(|marker| {
+                markers.send(AddMarker(marker));
+                json!(true)
+            }),
+            "cacheStats" => Ok(json!(*cache_stats)),
+            "clearCache" => {
+                // The params are optional
+                let params = if request.params.is_null() {
+                    Ok(ClearCacheParams::default())
+                } else {
+                    params::<ClearCacheParams>(request.params)
+                };
+                params.map(|ClearCacheParams { source }| {
+                    clear_cache.send(ClearCache { source });
+                    json!(true)
+                })
+            }
+            "subscribe" => {
+                if !server.subscribers.iter().any(|c| c.id == client.id) {
+                    server.subscribers.push(client.clone());
+                }
+                // Send the actual view at once
+                server.last_view = None;
+                server.since_notify = NOTIFY_INTERVAL;
+                Ok(json!(true))
+            }
+            "unsubscribe" => {
+                server.subscribers.retain(|c| c.id != client.id);
+                Ok(json!(true))
+            }
+            other => Err((METHOD_NOT_FOUND, format!("unknown method `{other}`"))),
+        };
+        if let Some(response) = response(request.id, result) {
+            client.send(response);
+        }
+    }
+}
+
+/// The request of a message, or the error response to it
+fn parse_request(text: &str) -> Result<Request, Value> {
+    let value: Value = serde_json::from_str(text)
+        .map_err(|err| error(Value::Null, (PARSE_ERROR, err.to_string())))?;
+    // Valid JSON, but maybe not a request. Its id is answered, if it is one.
+    let id = match value.get("id") {
+        Some(id) if id.is_string() || id.is_number() => id.clone(),
+        _ => Value::Null,
+    };
+    serde_json::from_value(value).map_err(|err| error(id, (INVALID_REQUEST, err.to_string())))
+}
+
+/// The response to a request, none to a notification
+fn response(id: Option<Value>, result: Result<Value, RpcError>) -> Option<Value> {
+    let id = id?;
+    Some(match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(err) => error(id, err),
+    })
+}
+
+fn error(id: Value, (code, message): RpcError) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": code, "message": message },
+    })
+}
+
+fn notify_subscribers(
+    time: Res<Time>,
+    mut server: ResMut<RemoteServer>,
+    player: PlayerQuery,
+    control_values: Res<ControlValues>,
+) {
+    if server.subscribers.is_empty() {
+        return;
+    }
+    server.since_notify += time.delta_seconds();
+    if server.since_notify < NOTIFY_INTERVAL {
+        return;
+    }
+    server.since_notify = 0.;
+    let view = GeoView::from_control(&player, &control_values);
+    if server.last_view == Some(view) {
+        return;
+    }
+    server.last_view = Some(view);
+    let notification = json!({ "jsonrpc": "2.0", "method": "view", "params": view });
+    // Forget disconnected clients
+    server
+        .subscribers
+        .retain(|client| client.send(notification.clone()));
+}
+
+/// Serves the remote control on `address`, like `127.0.0.1:9001`
+pub struct Plugin {
+    pub address: String,
+    /// Allow other addresses than the loopback ones of this computer
+    pub public: bool,
+    /// Allowed origins of browser pages, besides the ones of this computer
+    pub origins: Vec<String>,
+}
+
+impl bevy::prelude::Plugin for Plugin {
+    fn build(&self, app: &mut App) {
+        let listener = match TcpListener::bind(&self.address) {
+            Ok(listener) => listener,
+            Err(err) => {
+                error!(
+                    "remote control: could not listen on {}: {err}",
+                    self.address
+                );
+                return;
+            }
+        };
+        let local = listener
+            .local_addr()
+            .map(|address| address.ip().is_loopback());
+        if !self.public && !matches!(local, Ok(true)) {
+            error!(
+                "remote control: {} is reachable from other computers, use a loopback address \
+                 like 127.0.0.1:9001 or remote_public=true",
+                self.address
+            );
+            return;
+        }
+        info!("remote control on ws://{}", self.address);
+        let (sender, receiver) = channel();
+        let origins = Arc::new(self.origins.clone());
+        std::thread::spawn(move || listen(listener, sender, origins));
+        app.insert_resource(RemoteServer {
+            incoming: Mutex::new(receiver),
+            subscribers: vec![],
+            last_view: None,
+            since_notify: 0.,
+        })
+        .add_systems(Update, (handle_requests, notify_subscribers).chain());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn origins() {
+        let allowed = vec!["https://dashboard.example.org/".to_string()];
+        for origin in [
+            "http://localhost:8080",
+            "http://localhost",
+            "http://127.0.0.1:3000",
+            "http://[::1]:3000",
+            "https://dashboard.example.org",
+        ] {
+            assert!(origin_allowed(origin, &allowed), "{origin}");
+        }
+        for origin in [
+            "null",
+            "https://example.org",
+            "http://localhost.example.org",
+            "http://127.0.0.1.example.org:80",
+            "https://dashboard.example.org.evil.org",
+        ] {
+            assert!(!origin_allowed(origin, &allowed), "{origin}");
+        }
+    }
+
+    #[test]
+    fn invalid_requests() {
+        let code = |text| {
+            parse_request(text)
+                .err()
+                .map(|error| error["error"]["code"].clone())
+        };
+        assert_eq!(code(r#"{"jsonrpc":"2.0","id":1,"method":"getView"}"#), None);
+        assert_eq!(
+            code(r#"{"jsonrpc":"2.0","method""#),
+            Some(json!(PARSE_ERROR))
+        );
+        assert_eq!(code("getView"), Some(json!(PARSE_ERROR)));
+        for text in [
+            r#"{"jsonrpc":"2.0","id":1}"#,
+            r#"{"jsonrpc":"2.0","id":1,"method":7}"#,
+            r#"[{"jsonrpc":"2.0","id":1,"method":"getView"}]"#,
+            "7",
+            "null",
+        ] {
+            assert_eq!(code(text), Some(json!(INVALID_REQUEST)), "{text}");
+        }
+
+        // The id is answered if it is valid
+        let error = parse_request(r#"{"jsonrpc":"2.0","id":"a","params":{}}"#).err();
+        assert_eq!(
+            error,
+            Some(json!({
+                "jsonrpc": "2.0",
+                "id": "a",
+                "error": { "code": INVALID_REQUEST, "message": "missing field `method`" },
+            }))
+        );
+        let error = parse_request(r#"{"jsonrpc":"2.0","id":{},"params":{}}"#)
+            .err()
+            .unwrap();
+        assert_eq!(error["id"], Value::Null);
+        let error = parse_request("{").err().unwrap();
+        assert_eq!(error["id"], Value::Null);
+    }
+
+    #[test]
+    fn notifications() {
+        let request: Request =
+            serde_json::from_str(r#"{"jsonrpc":"2.0","method":"subscribe"}"#).unwrap();
+        assert_eq!(request.id, None);
+        assert_eq!(response(request.id, Ok(json!(true))), None);
+        assert_eq!(
+            response(None, Err((METHOD_NOT_FOUND, "unknown".into()))),
+            None
+        );
+
+        let request: Request =
+            serde_json::from_str(r#"{"jsonrpc":"2.0","id":null,"method":"getView"}"#).unwrap();
+        assert_eq!(request.id, Some(Value::Null));
+        let request: Request =
+            serde_json::from_str(r#"{"jsonrpc":"2.0","id":7,"method":"getView"}"#).unwrap();
+        assert_eq!(
+            response(request.id, Ok(json!(true))),
+            Some(json!({ "jsonrpc": "2.0", "id": 7, "result": true }))
+        );
+    }
+}
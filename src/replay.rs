@@ -0,0 +1,459 @@
+//! Session recording and replay, to compare the performance of versions by identical flights.
+//!
+//! `record=<file>` writes the player position and the input of each frame to a file.
+//! `replay=<file>` moves the player frame by frame as recorded and replays the recorded input
+//! instead of the user input. It writes a CSV report (`report=<file>`, default: the replay file
+//! with extension `csv`) with the frame time, the number of loaded tiles and the tile load latency.
+//! The app ends after the last frame.
+//!
+//! The record file has one line per frame:
+//! `cell.x cell.y cell.z translation.x .y .z rotation.x .y .z .w keys buttons wheel.x,wheel.y`.
+//! Keys and mouse buttons are comma separated, like `KeyW,ShiftLeft`, or `-` for none.
+//! Files of older versions may end after the keys or the position.
+
+use bevy::{
+    app::AppExit,
+    input::{
+        mouse::{MouseScrollUnit, MouseWheel},
+        InputSystem,
+    },
+    prelude::*,
+    transform::TransformSystem,
+    utils::HashMap,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use std::{
+    fs::File,
+    hash::Hash,
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::player::PlayerQuery;
+use crate::tilemap::{Loading, TileMap};
+use crate::{GalacticGrid, GalacticTransformOwned};
+
+/// Writes the player position and input of each frame
+#[derive(Resource)]
+struct Recorder {
+    file: BufWriter<File>,
+}
+
+/// The recorded state of a frame
+#[derive(Debug, Clone)]
+struct Frame {
+    pos: GalacticTransformOwned,
+    keys: Vec<KeyCode>,
+    buttons: Vec<MouseButton>,
+    /// Sum of the mouse wheel events
+    wheel: Vec2,
+}
+
+/// Plays a recorded session and reports the performance
+#[derive(Resource)]
+struct Replay {
+    frames: Vec<Frame>,
+    next: usize,
+    report: BufWriter<File>,
+    /// Start time of the tiles loading right now
+    loading_since: HashMap<Entity, f64>,
+    /// Sum of all frame times, to log the average at the end
+    total_frame_time: f64,
+}
+
+/// The serde name of a key or button, None for the ones without a name
+fn input_name(input: &impl Serialize) -> Option<String> {
+    match serde_json::to_value(input) {
+        Ok(Value::String(name)) => Some(name),
+        _ => None,
+    }
+}
+
+fn format_inputs<T: Serialize>(inputs: &[T]) -> String {
+    let names: Vec<String> = inputs.iter().filter_map(input_name).collect();
+    if names.is_empty() {
+        "-".into()
+    } else {
+        names.join(",")
+    }
+}
+
+fn parse_inputs<T: DeserializeOwned>(list: Option<&str>) -> Result<Vec<T>, String> {
+    match list {
+        None | Some("-") | Some("") => Ok(vec![]),
+        Some(list) => list
+            .split(',')
+            .map(|name| {
+                serde_json::from_value(Value::String(name.into()))
+                    .map_err(|_| format!("unknown input `{name}`"))
+            })
+            .collect(),
+    }
+}
+
+fn format_frame(frame: &Frame) -> String {
+    let GalacticTransformOwned { transform, cell } = &frame.pos;
+    let t = transform.translation;
+    let r = transform.rotation;
+    format!(
+        "{} {} {} {} {} {} {} {} {} {} {} {} {},{}",
+        cell.x,
+        cell.y,
+        cell.z,
+        t.x,
+        t.y,
+        t.z,
+        r.x,
+        r.y,
+        r.z,
+        r.w,
+        format_inputs(&frame.keys),
+        format_inputs(&frame.buttons),
+        frame.wheel.x,
+        frame.wheel.y,
+    )
+}
+
+fn parse_frame(line: &str) -> Result<Frame, String> {
+    let values: Vec<&str> = line.split(' ').collect();
+    if values.len() < 10 {
+        return Err(format!("expected at least 10 values, got `{line}`"));
+    }
+    let int = |i: usize| {
+        values[i]
+            .parse::<i64>()
+            .map_err(|err| format!("`{}`: {err}", values[i]))
+    };
+    let float = |i: usize| {
+        values[i]
+            .parse::<f32>()
+            .map_err(|err| format!("`{}`: {err}", values[i]))
+    };
+    let cell = GalacticGrid::new(int(0)?, int(1)?, int(2)?);
+    let translation = Vec3::new(float(3)?, float(4)?, float(5)?);
+    let rotation = Quat::from_xyzw(float(6)?, float(7)?, float(8)?, float(9)?);
+    let wheel = match values.get(12) {
+        None => Vec2::ZERO,
+        Some(wheel) => {
+            let (x, y) = wheel
+                .split_once(',')
+                .ok_or_else(|| format!("wheel `{wheel}` is no `x,y` pair"))?;
+            let parse = |v: &str| {
+                v.parse::<f32>()
+                    .map_err(|err| format!("wheel `{wheel}`: {err}"))
+            };
+            Vec2::new(parse(x)?, parse(y)?)
+        }
+    };
+    Ok(Frame {
+        pos: GalacticTransformOwned {
+            transform: Transform::from_translation(translation).with_rotation(rotation),
+            cell,
+        },
+        keys: parse_inputs(values.get(10).copied())?,
+        buttons: parse_inputs(values.get(11).copied())?,
+        wheel,
+    })
+}
+
+fn record(
+    mut recorder: ResMut<Recorder>,
+    player: PlayerQuery,
+    keys: Res<ButtonInput<KeyCode>>,
+    buttons: Res<ButtonInput<MouseButton>>,
+    mut wheel: EventReader<MouseWheel>,
+) {
+    let frame = Frame {
+        pos: player.pos().galactic_transform,
+        keys: keys.get_pressed().copied().collect(),
+        buttons: buttons.get_pressed().copied().collect(),
+        wheel: wheel.read().map(|event| Vec2::new(event.x, event.y)).sum(),
+    };
+    if let Err(err) = writeln!(recorder.file, "{}", format_frame(&frame)) {
+        error!("recording failed: {err}");
+    }
+}
+
+/// Write the rest of the recording when the app ends
+fn flush_recording(mut recorder: ResMut<Recorder>, mut exit: EventReader<AppExit>) {
+    if exit.read().count() == 0 {
+        return;
+    }
+    if let Err(err) = recorder.file.flush() {
+        error!("recording failed: {err}");
+    }
+}
+
+/// Set the buttons of `input` as recorded, instead of the user input
+fn replay_buttons<T: Copy + Eq + Hash + Send + Sync + 'static>(
+    input: &mut ButtonInput<T>,
+    previous: &[T],
+    pressed: &[T],
+) {
+    input.reset_all();
+    for &button in previous {
+        if !pressed.contains(&button) {
+            input.press(button);
+            input.release(button);
+            input.clear_just_pressed(button);
+        }
+    }
+    for &button in pressed {
+        input.press(button);
+        if previous.contains(&button) {
+            input.clear_just_pressed(button);
+        }
+    }
+}
+
+fn replay_input(
+    replay: Res<Replay>,
+    mut keys: ResMut<ButtonInput<KeyCode>>,
+    mut buttons: ResMut<ButtonInput<MouseButton>>,
+    mut wheel: ResMut<Events<MouseWheel>>,
+) {
+    let Some(frame) = replay.frames.get(replay.next) else {
+        return;
+    };
+    let previous = replay
+        .next
+        .checked_sub(1)
+        .map(|index| &replay.frames[index]);
+    replay_buttons(
+        &mut keys,
+        previous.map_or(&[], |previous| &previous.keys),
+        &frame.keys,
+    );
+    replay_buttons(
+        &mut buttons,
+        previous.map_or(&[], |previous| &previous.buttons),
+        &frame.buttons,
+    );
+    wheel.clear();
+    if frame.wheel != Vec2::ZERO {
+        wheel.send(MouseWheel {
+            unit: MouseScrollUnit::Line,
+            x: frame.wheel.x,
+            y: frame.wheel.y,
+            window: Entity::PLACEHOLDER,
+        });
+    }
+}
+
+fn replay(
+    mut replay: ResMut<Replay>,
+    mut player: PlayerQuery,
+    time: Res<Time>,
+    tilemap: Res<TileMap>,
+    started: Query<Entity, Added<Loading>>,
+    mut finished: RemovedComponents<Loading>,
+    loading: Query<&Loading>,
+    mut exit: EventWriter<AppExit>,
+) {
+    let Some(pos) = replay.frames.get(replay.next).map(|frame| frame.pos) else {
+        let average = replay.total_frame_time / replay.next.max(1) as f64;
+        info!(
+            "replay finished: {} frames, {average:.2} ms per frame",
+            replay.next
+        );
+        if let Err(err) = replay.report.flush() {
+            error!("writing the replay report failed: {err}");
+        }
+        exit.send(AppExit);
+        return;
+    };
+
+    let now = time.elapsed_seconds_f64();
+    for entity in started.iter() {
+        replay.loading_since.insert(entity, now);
+    }
+    let latencies: Vec<f64> = finished
+        .read()
+        .filter_map(|entity| replay.loading_since.remove(&entity))
+        .map(|start| (now - start) * 1000.)
+        .collect();
+    let latency = if latencies.is_empty() {
+        String::new()
+    } else {
+        format!(
+            "{:.1}",
+            latencies.iter().sum::<f64>() / latencies.len() as f64
+        )
+    };
+
+    let frame_time = time.delta_seconds_f64() * 1000.;
+    replay.total_frame_time += frame_time;
+    let loading = loading.iter().count();
+    let line = format!(
+        "{},{:.2},{},{},{}",
+        replay.next,
+        frame_time,
+        tilemap.tile_count() - loading,
+        loading,
+        latency
+    );
+    if let Err(err) = writeln!(replay.report, "{line}") {
+        error!("writing the replay report failed: {err}");
+    }
+
+    player.set_pos(pos);
+    replay.next += 1;
+}
+
+fn open_record(path: &Path) -> std::io::Result<Recorder> {
+    Ok(Recorder {
+        file: BufWriter::new(File::create(path)?),
+    })
+}
+
+fn open_replay(path: &Path, report: &Path) -> Result<Replay, String> {
+    let file = File::open(path).map_err(|err| err.to_string())?;
+    let frames = BufReader::new(file)
+        .lines()
+        .map(|line| parse_frame(&line.map_err(|err| err.to_string())?))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut report = BufWriter::new(File::create(report).map_err(|err| err.to_string())?);
+    writeln!(
+        report,
+        "frame,frame_time_ms,tiles_loaded,tiles_loading,load_latency_ms"
+    )
+    .map_err(|err| err.to_string())?;
+    Ok(Replay {
+        frames,
+        next: 0,
+        report,
+        loading_since: HashMap::default(),
+        total_frame_time: 0.,
+    })
+}
+
+/// Records a session to `record` and/or replays the session from `replay`.
+pub struct Plugin {
+    pub record: Option<PathBuf>,
+    pub replay: Option<PathBuf>,
+    pub report: Option<PathBuf>,
+}
+
+impl bevy::prelude::Plugin for Plugin {
+    fn build(&self, app: &mut App) {
+        if let Some(path) = &self.record {
+            match open_record(path) {
+                Ok(recorder) => {
+                    app.insert_resource(recorder)
+                        .add_systems(Last, (record, flush_recording).chain());
+                }
+                Err(err) => error!("could not record to {path:?}: {err}"),
+            }
+        }
+        if let Some(path) = &self.replay {
+            let report = self
+                .report
+                .clone()
+                .unwrap_or_else(|| path.with_extension("csv"));
+            match open_replay(path, &report) {
+                Ok(replay_state) => {
+                    info!(
+                        "replaying {} frames from {path:?}",
+                        replay_state.frames.len()
+                    );
+                    // The recorded input replaces the user input, before the controls read it.
+                    // After all controls, the recorded position wins.
+                    app.insert_resource(replay_state)
+                        .add_systems(PreUpdate, replay_input.after(InputSystem))
+                        .add_systems(
+                            PostUpdate,
+                            replay.before(TransformSystem::TransformPropagate),
+                        );
+                }
+                Err(err) => error!("could not replay {path:?}: {err}"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame() -> Frame {
+        Frame {
+            pos: GalacticTransformOwned {
+                transform: Transform::from_xyz(1.5, -2.25, 3.)
+                    .with_rotation(Quat::from_rotation_y(0.5)),
+                cell: GalacticGrid::new(-4, 5, 6),
+            },
+            keys: vec![KeyCode::KeyW, KeyCode::ShiftLeft],
+            buttons: vec![MouseButton::Right],
+            wheel: Vec2::new(0., -3.5),
+        }
+    }
+
+    fn assert_same(a: &Frame, b: &Frame) {
+        assert_eq!(a.pos.cell, b.pos.cell);
+        assert_eq!(a.pos.transform, b.pos.transform);
+        assert_eq!(a.keys, b.keys);
+        assert_eq!(a.buttons, b.buttons);
+        assert_eq!(a.wheel, b.wheel);
+    }
+
+    #[test]
+    fn round_trip() {
+        let frame = frame();
+        let line = format_frame(&frame);
+        assert!(line.ends_with(" KeyW,ShiftLeft Right 0,-3.5"), "{line}");
+        assert_same(&parse_frame(&line).unwrap(), &frame);
+
+        let idle = Frame {
+            keys: vec![],
+            buttons: vec![],
+            wheel: Vec2::ZERO,
+            ..frame
+        };
+        let line = format_frame(&idle);
+        assert!(line.ends_with(" - - 0,0"), "{line}");
+        assert_same(&parse_frame(&line).unwrap(), &idle);
+    }
+
+    #[test]
+    fn older_files() {
+        let frame = frame();
+        let line = format_frame(&frame);
+        let values: Vec<&str> = line.split(' ').collect();
+        // Only the position
+        let parsed = parse_frame(&values[..10].join(" ")).unwrap();
+        assert_eq!(parsed.pos.transform, frame.pos.transform);
+        assert!(parsed.keys.is_empty() && parsed.buttons.is_empty());
+        // And the keys
+        let parsed = parse_frame(&values[..11].join(" ")).unwrap();
+        assert_eq!(parsed.keys, frame.keys);
+        assert_eq!(parsed.wheel, Vec2::ZERO);
+    }
+
+    #[test]
+    fn invalid_lines() {
+        let line = format_frame(&frame());
+        assert!(parse_frame("1 2 3").is_err());
+        assert!(parse_frame(&line.replacen("-4", "x", 1)).is_err());
+        assert!(parse_frame(&line.replace("KeyW", "NoKey")).is_err());
+        assert!(parse_frame(&line.replace("0,-3.5", "0")).is_err());
+    }
+
+    #[test]
+    fn replayed_buttons() {
+        let mut keys = ButtonInput::default();
+        // The user input is replaced
+        keys.press(KeyCode::KeyX);
+        replay_buttons(&mut keys, &[], &[KeyCode::KeyW]);
+        assert!(keys.just_pressed(KeyCode::KeyW));
+        assert!(!keys.pressed(KeyCode::KeyX));
+
+        replay_buttons(&mut keys, &[KeyCode::KeyW], &[KeyCode::KeyW, KeyCode::KeyA]);
+        assert!(keys.pressed(KeyCode::KeyW) && !keys.just_pressed(KeyCode::KeyW));
+        assert!(keys.just_pressed(KeyCode::KeyA));
+
+        replay_buttons(&mut keys, &[KeyCode::KeyW, KeyCode::KeyA], &[KeyCode::KeyA]);
+        assert!(keys.just_released(KeyCode::KeyW) && !keys.pressed(KeyCode::KeyW));
+        assert!(keys.pressed(KeyCode::KeyA) && !keys.just_pressed(KeyCode::KeyA));
+    }
+}
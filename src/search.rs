@@ -0,0 +1,387 @@
+//! Place search (geocoding): Find a place by name and fly the camera there.
+//!
+//! The lookup itself is done by a [`Geocoder`]. Two backends are offered:
+//! * [`NominatimGeocoder`] asks a Nominatim-compatible server (HTTP JSON API)
+//! * [`GazetteerGeocoder`] searches a local text file, usefull for testing and offline use
+//!
+//! Send a [`SearchRequest`] event (or start with the argument `search=Munich`).
+//! The first result is shown by setting a [`GeoView`] to the found coordinates.
+//! All results are also sent as [`SearchResult`] event.
+
+use bevy::{
+    asset::{io::AssetReader, AsyncReadExt},
+    prelude::*,
+    tasks::{futures_lite::future, IoTaskPool, Task},
+    utils::BoxedFuture,
+};
+use serde::Deserialize;
+use std::{path::Path, path::PathBuf, sync::Arc};
+
+use crate::flyto::FlyToRequest;
+use crate::geocoord::GeoCoord;
+use crate::geoview::GeoView;
+use crate::player::ControlValues;
+
+/// A place, found by a [`Geocoder`]
+#[derive(Debug, Clone)]
+pub struct Place {
+    pub name: String,
+    pub geo_coord: GeoCoord,
+}
+
+#[derive(Debug)]
+pub enum SearchError {
+    Io(std::io::Error),
+    Format(String),
+}
+
+impl std::fmt::Display for SearchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SearchError::Io(err) => write!(f, "search failed: {err}"),
+            SearchError::Format(msg) => write!(f, "invalid search result: {msg}"),
+        }
+    }
+}
+
+impl From<std::io::Error> for SearchError {
+    fn from(err: std::io::Error) -> Self {
+        SearchError::Io(err)
+    }
+}
+
+/// A backend to look up places by name.
+pub trait Geocoder: Send + Sync + 'static {
+    fn search<'a>(&'a self, query: &'a str) -> BoxedFuture<'a, Result<Vec<Place>, SearchError>>;
+}
+
+/// Searches by a Nominatim-compatible HTTP JSON API, like `nominatim.openstreetmap.org`
+pub struct NominatimGeocoder {
+    /// Host and path, without `https://`, the search parameters are appended
+    pub base_url: String,
+    /// Maximal number of results to ask for
+    pub limit: u8,
+}
+
+impl Default for NominatimGeocoder {
+    fn default() -> Self {
+        Self {
+            base_url: "nominatim.openstreetmap.org/search".into(),
+            limit: 5,
+        }
+    }
+}
+
+/// One entry of the Nominatim answer. Nominatim sends the coordinates as strings.
+#[derive(Deserialize)]
+struct NominatimPlace {
+    display_name: String,
+    lat: String,
+    lon: String,
+}
+
+impl Geocoder for NominatimGeocoder {
+    fn search<'a>(&'a self, query: &'a str) -> BoxedFuture<'a, Result<Vec<Place>, SearchError>> {
+        Box::pin(async move {
+            let url = format!(
+                "{}?format=jsonv2&limit={}&q={}",
+                self.base_url,
+                self.limit,
+                url_encode(query)
+            );
+            info!("searching {url}");
+            let mut bytes = vec![];
+            bevy_web_asset::WebAssetReader::Https
+                .read(Path::new(&url))
+                .await
+                .map_err(|err| SearchError::Format(err.to_string()))?
+                .read_to_end(&mut bytes)
+                .await?;
+            parse_nominatim(&bytes)
+        })
+    }
+}
+
+fn parse_nominatim(bytes: &[u8]) -> Result<Vec<Place>, SearchError> {
+    let places: Vec<NominatimPlace> =
+        serde_json::from_slice(bytes).map_err(|err| SearchError::Format(err.to_string()))?;
+    places
+        .into_iter()
+        .map(|place| {
+            let lat = place
+                .lat
+                .parse()
+                .map_err(|_| SearchError::Format(place.lat))?;
+            let lon = place
+                .lon
+                .parse()
+                .map_err(|_| SearchError::Format(place.lon))?;
+            Ok(Place {
+                name: place.display_name,
+                geo_coord: GeoCoord { lat, lon },
+            })
+        })
+        .collect()
+}
+
+/// Percent-encode a search text for the URL query
+fn url_encode(text: &str) -> String {
+    let mut encoded = String::new();
+    for byte in text.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            b' ' => encoded.push('+'),
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
+}
+
+/// Searches a local gazetteer file. Each line is `name;lat;lon`, empty lines
+/// and lines starting with `#` are skipped. The match is case insensitive on a part of the name.
+pub struct GazetteerGeocoder {
+    pub path: PathBuf,
+}
+
+impl Geocoder for GazetteerGeocoder {
+    fn search<'a>(&'a self, query: &'a str) -> BoxedFuture<'a, Result<Vec<Place>, SearchError>> {
+        Box::pin(async move {
+            let text = async_fs::read_to_string(&self.path).await?;
+            let query = query.to_lowercase();
+            let mut places = vec![];
+            for line in text.lines() {
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                let place = parse_gazetteer_line(line)?;
+                if place.name.to_lowercase().contains(&query) {
+                    places.push(place);
+                }
+            }
+            Ok(places)
+        })
+    }
+}
+
+fn parse_gazetteer_line(line: &str) -> Result<Place, SearchError> {
+    let [name, lat, lon] = *line.split(';').map(str::trim).collect::<Vec<_>>() else {
+        return Err(SearchError::Format(line.into()));
+    };
+    if name.is_empty() {
+        return Err(SearchError::Format(line.into()));
+    }
+    let lat = lat.parse().map_err(|_| SearchError::Format(line.into()))?;
+    let lon = lon.parse().map_err(|_| SearchError::Format(line.into()))?;
+    Ok(Place {
+        name: name.into(),
+        geo_coord: GeoCoord { lat, lon },
+    })
+}
+
+/// The geocoder used for all searches
+#[derive(Resource, Clone)]
+pub struct Search {
+    pub geocoder: Arc<dyn Geocoder>,
+}
+
+impl Default for Search {
+    fn default() -> Self {
+        Self {
+            geocoder: Arc::new(NominatimGeocoder::default()),
+        }
+    }
+}
+
+/// Send this event to search for a place. The camera flies to the first result.
+#[derive(Event, Clone)]
+pub struct SearchRequest(pub String);
+
+/// All places found for a [`SearchRequest`]
+#[derive(Event, Clone)]
+pub struct SearchResult {
+    pub query: String,
+    pub places: Vec<Place>,
+}
+
+/// A running search
+#[derive(Component)]
+struct SearchTask {
+    query: String,
+    task: Task<Result<Vec<Place>, SearchError>>,
+}
+
+fn start_search(
+    mut commands: Commands,
+    search: Res<Search>,
+    mut requests: EventReader<SearchRequest>,
+) {
+    for SearchRequest(query) in requests.read() {
+        let geocoder = search.geocoder.clone();
+        let text = query.clone();
+        let task = IoTaskPool::get().spawn(async move { geocoder.search(&text).await });
+        commands.spawn(SearchTask {
+            query: query.clone(),
+            task,
+        });
+    }
+}
+
+fn finish_search(
+    mut commands: Commands,
+    mut tasks: Query<(Entity, &mut SearchTask)>,
+    mut results: EventWriter<SearchResult>,
+    mut fly_to: EventWriter<FlyToRequest>,
+    control_values: Res<ControlValues>,
+) {
+    for (entity, mut search) in tasks.iter_mut() {
+        let Some(result) = future::block_on(future::poll_once(&mut search.task)) else {
+            continue;
+        };
+        commands.entity(entity).despawn();
+        match result {
+            Ok(places) => {
+                if let Some(place) = places.first() {
+                    info!("found {:?}: {}", search.query, place.name);
+                    let view = GeoView {
+                        geo_coord: place.geo_coord,
+                        ..control_values.view
+                    };
+                    fly_to.send(FlyToRequest::new(view));
+                } else {
+                    warn!("nothing found for {:?}", search.query);
+                }
+                results.send(SearchResult {
+                    query: search.query.clone(),
+                    places,
+                });
+            }
+            Err(err) => error!("{:?}: {err}", search.query),
+        }
+    }
+}
+
+fn search_at_start(
+    starting_values: Res<crate::StartingValues>,
+    mut requests: EventWriter<SearchRequest>,
+) {
+    if let Some(query) = &starting_values.search {
+        requests.send(SearchRequest(query.clone()));
+    }
+}
+
+pub struct Plugin;
+
+impl bevy::prelude::Plugin for Plugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Search>()
+            .add_event::<SearchRequest>()
+            .add_event::<SearchResult>()
+            .add_systems(PostStartup, search_at_start)
+            .add_systems(Update, (start_search, finish_search));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nominatim() {
+        let places = parse_nominatim(
+            br#"[
+                {"display_name": "Marienplatz, M\u00fcnchen", "lat": "48.137", "lon": "11.575",
+                 "place_id": 1},
+                {"display_name": "Null Island", "lat": "0", "lon": "-0.5"}
+            ]"#,
+        )
+        .unwrap();
+        assert_eq!(places.len(), 2);
+        assert_eq!(places[0].name, "Marienplatz, München");
+        assert_eq!(
+            places[0].geo_coord,
+            GeoCoord {
+                lat: 48.137,
+                lon: 11.575
+            }
+        );
+        assert_eq!(places[1].geo_coord.lon, -0.5);
+        assert!(parse_nominatim(b"[]").unwrap().is_empty());
+
+        for malformed in [
+            &b""[..],
+            b"{}",
+            b"[{\"display_name\": \"x\", \"lat\": \"1\"}]",
+            b"[{\"display_name\": \"x\", \"lat\": 1, \"lon\": 2}]",
+            b"[{\"display_name\": \"x\", \"lat\": \"north\", \"lon\": \"2\"}]",
+            b"<html>error</html>",
+        ] {
+            assert!(
+                matches!(parse_nominatim(malformed), Err(SearchError::Format(_))),
+                "{}",
+                String::from_utf8_lossy(malformed)
+            );
+        }
+    }
+
+    #[test]
+    fn gazetteer_line() {
+        let place = parse_gazetteer_line(" Frauenkirche ; 48.1386;11.5736 ").unwrap();
+        assert_eq!(place.name, "Frauenkirche");
+        assert_eq!(
+            place.geo_coord,
+            GeoCoord {
+                lat: 48.1386,
+                lon: 11.5736
+            }
+        );
+        for malformed in [
+            "Frauenkirche",
+            "Frauenkirche;48.1386",
+            "Frauenkirche;48.1386;11.5736;extra",
+            "Frauenkirche;;11.5736",
+            "Frauenkirche;north;11.5736",
+            ";48.1386;11.5736",
+            "Frauenkirche,48.1386,11.5736",
+        ] {
+            assert!(
+                matches!(parse_gazetteer_line(malformed), Err(SearchError::Format(line)) if line == malformed),
+                "{malformed}"
+            );
+        }
+    }
+
+    #[test]
+    fn gazetteer() {
+        let path = std::env::temp_dir().join(format!("osmeta_gazetteer_{}", std::process::id()));
+        std::fs::write(
+            &path,
+            "# name;lat;lon\n\nFrauenkirche;48.1386;11.5736\nMarienplatz;48.137;11.575\n",
+        )
+        .unwrap();
+        let gazetteer = GazetteerGeocoder { path: path.clone() };
+        let search = |query| bevy::tasks::block_on(gazetteer.search(query));
+        let places = search("KIRCHE").unwrap();
+        assert_eq!(places.len(), 1);
+        assert_eq!(places[0].name, "Frauenkirche");
+        assert_eq!(search("").unwrap().len(), 2);
+        assert!(search("Dom").unwrap().is_empty());
+
+        std::fs::write(&path, "Frauenkirche;48.1386\n").unwrap();
+        assert!(matches!(search("Frauen"), Err(SearchError::Format(_))));
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(search("Frauen"), Err(SearchError::Io(_))));
+    }
+
+    #[test]
+    fn encoding() {
+        assert_eq!(url_encode("Marien Platz"), "Marien+Platz");
+        assert_eq!(url_encode("a-b_c.d~e"), "a-b_c.d~e");
+        assert_eq!(url_encode("München"), "M%C3%BCnchen");
+        assert_eq!(url_encode("東京"), "%E6%9D%B1%E4%BA%AC");
+        assert_eq!(url_encode("a&b=c+d/e?"), "a%26b%3Dc%2Bd%2Fe%3F");
+        assert_eq!(url_encode(""), "");
+    }
+}
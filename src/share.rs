@@ -0,0 +1,214 @@
+//! Links to the actual view: the OSMeta URL with the view as query, an openstreetmap.org link
+//! and a `geo:` URI. The `#map=zoom/lat/lon` links of openstreetmap.org are accepted as input.
+//!
+//! In the browser the page URL follows the camera. On native, the key `U` logs the links
+//! and copies the OSMeta URL to the clipboard.
+
+use bevy::prelude::*;
+use std::f32::consts::PI;
+
+use crate::geocoord::{GeoCoord, EARTH_RADIUS};
+use crate::geoview::GeoView;
+use crate::player::{CamControlMode, ControlValues, PlayerQuery};
+
+/// Where the web build is published
+pub const WEB_URL: &str = "https://derkarlos.github.io/OSMeta/";
+
+/// The argument keys of a view, see [`crate::Options`]
+pub const VIEW_KEYS: [&str; 8] = ["lat", "lon", "ele", "view", "dir", "dist", "fov", "map"];
+
+/// The view as URL query, with the same keys as the arguments
+pub fn view_query(view: &GeoView) -> String {
+    format!(
+        "lat={:.6}&lon={:.6}&ele={:.1}&view={:.1}&dir={:.1}&dist={:.0}&fov={:.0}",
+        view.geo_coord.lat,
+        view.geo_coord.lon,
+        view.elevation,
+        view.up_view,
+        view.direction,
+        view.distance,
+        view.camera_fov
+    )
+}
+
+pub fn share_url(view: &GeoView) -> String {
+    format!("{WEB_URL}?{}", view_query(view))
+}
+
+/// Height of the camera above the ground
+pub fn camera_height(view: &GeoView, cam_control_mode: CamControlMode) -> f32 {
+    match cam_control_mode {
+        CamControlMode::F4 => view.elevation + view.distance,
+        CamControlMode::Fly => view.elevation,
+    }
+}
+
+/// A map zoom level shows about the same area as a camera at this height
+pub fn height_from_zoom(zoom: f32, lat: f32) -> f32 {
+    2. * PI * EARTH_RADIUS * lat.to_radians().cos() / 2_f32.powf(zoom)
+}
+
+pub fn zoom_from_height(height: f32, lat: f32) -> u8 {
+    let circumference = 2. * PI * EARTH_RADIUS * lat.to_radians().cos();
+    (circumference / height.max(1.))
+        .log2()
+        .round()
+        .clamp(0., 19.) as u8
+}
+
+pub fn osm_url(view: &GeoView, height: f32) -> String {
+    let GeoCoord { lat, lon } = view.geo_coord;
+    let zoom = zoom_from_height(height, lat);
+    format!("https://www.openstreetmap.org/#map={zoom}/{lat:.5}/{lon:.5}")
+}
+
+pub fn geo_uri(view: &GeoView) -> String {
+    let GeoCoord { lat, lon } = view.geo_coord;
+    format!("geo:{lat:.6},{lon:.6}")
+}
+
+/// Parses the `zoom/lat/lon` of an openstreetmap.org `#map=` link. Following parameters,
+/// like `&layers=C`, are ignored.
+pub fn parse_osm_map(value: &str) -> Option<(f32, GeoCoord)> {
+    let value = value.split('&').next().unwrap_or_default();
+    let mut parts = value.split('/').map(|part| part.parse::<f32>().ok());
+    let (Some(Some(zoom)), Some(Some(lat)), Some(Some(lon))) =
+        (parts.next(), parts.next(), parts.next())
+    else {
+        return None;
+    };
+    Some((zoom, GeoCoord { lat, lon }))
+}
+
+#[cfg(target_arch = "wasm32")]
+mod browser {
+    use super::*;
+    use wasm_bindgen::JsValue;
+
+    /// Seconds between two URL updates. Browsers limit the frequency of `replaceState`.
+    const SYNC_INTERVAL: f32 = 1.0;
+
+    #[derive(Default)]
+    pub(super) struct UrlSync {
+        since: f32,
+        view: Option<GeoView>,
+    }
+
+    fn is_view_arg(arg: &str) -> bool {
+        arg.split_once('=')
+            .is_some_and(|(key, _)| VIEW_KEYS.contains(&key))
+    }
+
+    /// Replace the view arguments of the page URL, keep the others
+    fn replace_url(view: &GeoView) -> Result<(), JsValue> {
+        let window = web_sys::window().ok_or_else(|| JsValue::from_str("no window"))?;
+        let location = window.location();
+        let search = location.search()?;
+        let query = view_query(view);
+        let mut args: Vec<&str> = search
+            .trim_start_matches('?')
+            .split('&')
+            .filter(|arg| !arg.is_empty() && !is_view_arg(arg))
+            .collect();
+        args.push(&query);
+        let url = format!("{}?{}", location.pathname()?, args.join("&"));
+        window
+            .history()?
+            .replace_state_with_url(&JsValue::NULL, "", Some(&url))
+    }
+
+    pub(super) fn sync_browser_url(
+        time: Res<Time>,
+        mut sync: Local<UrlSync>,
+        player: PlayerQuery,
+        control_values: Res<ControlValues>,
+    ) {
+        sync.since += time.delta_seconds();
+        if sync.since < SYNC_INTERVAL {
+            return;
+        }
+        sync.since = 0.;
+        let view = GeoView::from_control(&player, &control_values);
+        if sync.view == Some(view) {
+            return;
+        }
+        sync.view = Some(view);
+        if let Err(err) = replace_url(&view) {
+            warn!("could not update the page URL: {err:?}");
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn copy_share_link(
+    keys: Res<ButtonInput<KeyCode>>,
+    player: PlayerQuery,
+    control_values: Res<ControlValues>,
+) {
+    if !keys.just_pressed(KeyCode::KeyU) {
+        return;
+    }
+    let view = GeoView::from_control(&player, &control_values);
+    let url = share_url(&view);
+    let height = camera_height(&view, control_values.cam_control_mode);
+    info!(
+        "share links:\n  {url}\n  {}\n  {}",
+        osm_url(&view, height),
+        geo_uri(&view)
+    );
+    #[cfg(any(target_os = "linux", target_os = "windows", target_os = "macos"))]
+    match arboard::Clipboard::new().and_then(|mut clipboard| clipboard.set_text(url)) {
+        Ok(()) => info!("share link copied to the clipboard"),
+        Err(err) => warn!("could not copy the share link: {err}"),
+    }
+}
+
+pub struct Plugin;
+
+impl bevy::prelude::Plugin for Plugin {
+    fn build(&self, app: &mut App) {
+        #[cfg(target_arch = "wasm32")]
+        app.add_systems(Update, browser::sync_browser_url);
+        #[cfg(not(target_arch = "wasm32"))]
+        app.add_systems(Update, copy_share_link);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn osm_map() {
+        let munich = Some((
+            17.,
+            GeoCoord {
+                lat: 48.14,
+                lon: 11.56,
+            },
+        ));
+        assert_eq!(parse_osm_map("17/48.14/11.56"), munich);
+        assert_eq!(parse_osm_map("17/48.14/11.56&layers=C"), munich);
+        assert_eq!(parse_osm_map("17/48.14/11.56&layers=C&mlat=1"), munich);
+        assert_eq!(parse_osm_map("17/48.14"), None);
+        assert_eq!(parse_osm_map("17/48.14&layers=C/11.56"), None);
+        assert_eq!(parse_osm_map("17/north/11.56"), None);
+        assert_eq!(parse_osm_map(""), None);
+    }
+
+    #[test]
+    fn osm_url_round_trip() {
+        let view = GeoView {
+            geo_coord: GeoCoord {
+                lat: 48.14,
+                lon: 11.56,
+            },
+            ..default()
+        };
+        let url = osm_url(&view, 500.);
+        let (_, map) = url.split_once("#map=").unwrap();
+        let (zoom, geo_coord) = parse_osm_map(map).unwrap();
+        assert_eq!(zoom, zoom_from_height(500., 48.14) as f32);
+        assert_eq!(geo_coord, view.geo_coord);
+    }
+}
@@ -1,127 +1,89 @@
+//! The Bevy adapter of the tile scheduling of the core crate: spawns, loads and hides the tiles.
+
+use crate::ViewDistance;
 use bevy::{
     asset::LoadState,
-    diagnostic::{DiagnosticsStore, FrameTimeDiagnosticsPlugin},
+    diagnostic::{
+        Diagnostic, DiagnosticPath, Diagnostics, DiagnosticsStore, FrameTimeDiagnosticsPlugin,
+        RegisterDiagnostic,
+    },
     gltf::Gltf,
     prelude::*,
     render::{mesh::Indices, render_resource::PrimitiveTopology},
-    utils::HashSet,
 };
-//use gl am::Vec3;
-//use bevy::prelude::Vec3;
-
-use crate::geocoord::{GeoCoord, EARTH_RADIUS};
-use crate::ViewDistance;
 
 use crate::big_space::Space;
-use crate::player::Control;
+use crate::player::{Directions, PlanetaryPosition};
+
+use crate::{GalacticGrid, GalacticTransformOwned};
 
-use crate::{GalacticGrid, GalacticTransform, GalacticTransformOwned};
+use osmeta_core::schedule::adapt_view_distance;
+pub use osmeta_core::schedule::{TileSchedule, ViewOrigin, TILE_ZOOM};
+pub use osmeta_core::tile::{TileCoord, TileIndex};
 
-mod coord;
-mod index;
-pub use coord::*;
-pub use index::*;
+/// Tiles within the view distance that are not loaded yet
+pub const TILES_QUEUED: DiagnosticPath = DiagnosticPath::const_new("tiles/queued");
+pub const TILES_LOADING: DiagnosticPath = DiagnosticPath::const_new("tiles/loading");
+/// Since the start, like the failed ones
+pub const TILES_LOADED: DiagnosticPath = DiagnosticPath::const_new("tiles/loaded");
+pub const TILES_FAILED: DiagnosticPath = DiagnosticPath::const_new("tiles/failed");
+/// In meters, see [`ViewDistance`]
+pub const VIEW_DISTANCE: DiagnosticPath = DiagnosticPath::const_new("tiles/view_distance");
 
 #[derive(Resource, Default)]
 pub struct TileMap {
     /// All currently loaded tiles.
-    tiles: HashSet<TileIndex>,
+    schedule: TileSchedule,
+}
+
+/// The tile an entity shows
+#[derive(Component, Deref, Debug, Copy, Clone)]
+pub struct Tile(pub TileIndex);
+
+/// Sent when a tile finished loading
+#[derive(Event, Debug, Clone, Copy)]
+pub enum TileEvent {
+    Loaded(TileIndex),
+    /// The 3D tile is not available, a flat raster tile is shown instead
+    Failed(TileIndex),
 }
 
 #[derive(Component)]
 /// A marker component for tiles that are currently being loaded.
 pub struct Loading;
 
-pub const TILE_ZOOM: u8 = 15;
-
-fn phytagoras(a: f32, b: f32) -> f32 {
-    (a * a + b * b).sqrt()
-}
+impl TileMap {
+    /// Number of tiles loaded or in the process of loading
+    pub fn tile_count(&self) -> usize {
+        self.schedule.len()
+    }
 
-fn tile_faraway(
-    tile: &TileIndex,
-    origin: TileIndex,
-    elevation: f32,
-    radius: f32,
-    tile_size: f32,
-) -> bool {
-    // let distance = (tile.distance_squared(origin) as f32).sqrt() * tile_size;
-    // phytagoras(distance, elevation) > radius
-    let distance =
-        tile.distance_squared(origin) as f32 * elevation / tile_size * elevation / tile_size;
-    distance > radius / tile_size * radius / tile_size
-}
+    pub fn schedule(&self) -> &TileSchedule {
+        &self.schedule
+    }
 
-impl TileMap {
     pub fn hide_faraway_tiles(
-        In((origin, radius)): In<(TileIndex, f32)>,
-        mut tiles: Query<(&TileIndex, &mut Visibility)>,
-        fly_cam: Query<GalacticTransform, With<Control>>, // todo: make camera elevation a global resource?
+        In(origin): In<ViewOrigin>,
+        mut tiles: Query<(&Tile, &mut Visibility)>,
     ) {
-        let elevation = fly_cam.single().position_double().length() as f32 - EARTH_RADIUS;
         for (tile, mut vis) in tiles.iter_mut() {
-            // FIXME: use tile zoom level to increase view-distance for lower zoom tiles.
-            let tile_size = tile.as_coord().to_geo_coord().tile_size(TILE_ZOOM);
-            //let distance = (tile.distance_squared(origin) as f32).sqrt() * tile_size;
-            //info!("o_e_r {:?} {:?} {:?}", distance, elevation, radius);
-            // if phytagoras(distance, elevation) > radius {
-            if tile_faraway(tile, origin, elevation, radius, tile_size) {
-                *vis = Visibility::Hidden;
-            } else {
+            if origin.is_visible(**tile) {
                 *vis = Visibility::Inherited;
+            } else {
+                *vis = Visibility::Hidden;
             }
         }
     }
 
     pub fn load_next(
-        In((origin, radius)): In<(TileIndex, f32)>,
+        In(origin): In<ViewOrigin>,
         tilemap: Res<TileMap>,
         loading: Query<&Loading>,
-        fly_cam: Query<GalacticTransform, With<Control>>,
     ) -> Option<TileIndex> {
         if !loading.is_empty() {
             return None;
         }
-        let tile_size = origin.as_coord().to_geo_coord().tile_size(TILE_ZOOM);
-        let mut best_score = f32::INFINITY;
-        let mut best_pos = None;
-        let elevation = fly_cam.single().position_double().length() as f32 - EARTH_RADIUS;
-        let dist_max = (radius / tile_size).ceil() as i32;
-        for x_i in -dist_max..=dist_max {
-            for y_i in -dist_max..=dist_max {
-                let offset = IVec2::new(x_i, y_i);
-                let distance = (offset.length_squared() as f32).sqrt() * tile_size;
-                // info!("o e r {:?} {:?} {:?}",distance,elevation,radius);
-                if phytagoras(distance, elevation) > radius {
-                    continue;
-                }
-
-                //let tile_index = (*origin).as_ivec2() + offset;
-                //let tile = TileIndex{idx:tile_index.as_uvec2(), zoom:TILE_ZOOM};
-                //if tile_faraway(tile, origin, elevation, radius, tile_size) {
-                //    continue;
-                //}
-
-                let pos = origin.offset(offset);
-                let score = tilemap.get_view_tile_score(pos, offset);
-                if score < best_score {
-                    best_pos = Some(pos);
-                    best_score = score;
-                }
-            }
-        }
-        best_pos
-    }
-
-    /// Takes an offset to the player position and returns a score for how important
-    /// to load it is. Lower values are better.
-    // FIXME(#18): use a smarter algorithm
-    pub fn get_view_tile_score(&self, pos: TileIndex, offset: IVec2) -> f32 {
-        if self.tiles.contains(&pos) {
-            return f32::INFINITY;
-        }
-
-        offset.as_vec2().length_squared()
+        tilemap.schedule.next_to_load(&origin)
     }
 
     /// Queue a tile coordinate for loading. This will load tiles
@@ -141,7 +103,7 @@ impl TileMap {
         let name: String = format!("tile://{}_{}_{}.glb", pos.zoom(), pos.x, pos.y);
         // Start loading next tile
         let gltf: Handle<Gltf> = server.load(name);
-        if !tilemap.tiles.insert(pos) {
+        if !tilemap.schedule.insert(pos) {
             return;
         }
 
@@ -149,7 +111,13 @@ impl TileMap {
         let (grid, _coord, mesh) = flat_tile(pos);
         let mesh = meshes.add(mesh);
 
-        commands.spawn((PbrBundle { mesh, ..default() }, pos, grid, Loading, gltf));
+        commands.spawn((
+            PbrBundle { mesh, ..default() },
+            Tile(pos),
+            grid,
+            Loading,
+            gltf,
+        ));
     }
 
     pub fn update(
@@ -157,9 +125,10 @@ impl TileMap {
         server: Res<AssetServer>,
         scenes: ResMut<Assets<Gltf>>,
         mut materials: ResMut<Assets<StandardMaterial>>,
-        next: Query<(Entity, &TileIndex, &Handle<Gltf>), With<Loading>>,
+        next: Query<(Entity, &Tile, &Handle<Gltf>), With<Loading>>,
+        mut events: EventWriter<TileEvent>,
     ) {
-        let Ok((entity, pos, scene)) = next.get_single() else {
+        let Ok((entity, &Tile(pos), scene)) = next.get_single() else {
             return;
         };
         let state = server.get_load_state(scene).unwrap();
@@ -177,7 +146,7 @@ impl TileMap {
             LoadState::NotLoaded | LoadState::Loading => unreachable!(),
             LoadState::Loaded => {
                 entity.remove::<PbrBundle>();
-                let GalacticTransformOwned { transform, cell } = pos.to_cartesian();
+                let GalacticTransformOwned { transform, cell } = tile_transform(pos);
                 let scene = scenes.get(scene).unwrap().scenes[0].clone();
                 entity.insert(cell);
                 entity.insert(SceneBundle {
@@ -185,14 +154,10 @@ impl TileMap {
                     transform,
                     ..default()
                 });
+                events.send(TileEvent::Loaded(pos));
             }
             LoadState::Failed => {
-                let url = format!(
-                    "https://a.tile.openstreetmap.org/{}/{}/{}.png",
-                    pos.zoom(),
-                    pos.x,
-                    pos.y
-                );
+                let url = format!("raster://{}/{}/{}.png", pos.zoom(), pos.x, pos.y);
                 debug!(
                     ?url,
                     "failed to load tile {pos} from network, switching to flat tile"
@@ -203,13 +168,24 @@ impl TileMap {
                     perceptual_roughness: 1.0,
                     ..default()
                 }));
+                events.send(TileEvent::Failed(pos));
             }
         }
     }
 }
 
+/// The place of a loaded tile: its center, looking north
+fn tile_transform(pos: TileIndex) -> GalacticTransformOwned {
+    let coord = pos.as_coord().center();
+    let pos = PlanetaryPosition::from(coord.to_geo_coord());
+    let Directions { up, north, west: _ } = pos.directions();
+    let mut galactic_transform = pos.to_galactic_transform_space().galactic_transform;
+    galactic_transform.transform.look_to(north, up);
+    galactic_transform
+}
+
 // Compute a square mesh at the position for the given tile.
-fn flat_tile(pos: TileIndex) -> (GalacticGrid, coord::TileCoord, Mesh) {
+fn flat_tile(pos: TileIndex) -> (GalacticGrid, TileCoord, Mesh) {
     let coord = pos.as_coord();
 
     // Four corners of the tile in cartesian coordinates relative to the
@@ -228,9 +204,9 @@ fn flat_tile(pos: TileIndex) -> (GalacticGrid, coord::TileCoord, Mesh) {
     ];
 
     // `a` is our anchor point, all others are relative
-    let b = *b - *a;
-    let c = *c - *a;
-    let d = *d - *a;
+    let b = b - a;
+    let c = c - a;
+    let d = d - a;
 
     let (grid, a) = Space::translation_to_grid(a);
     let b = a + b.as_vec3();
@@ -257,25 +233,32 @@ pub struct Plugin;
 
 impl bevy::prelude::Plugin for Plugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(
-            Update,
-            (
+        app.add_event::<TileEvent>()
+            .register_diagnostic(Diagnostic::new(TILES_QUEUED))
+            .register_diagnostic(Diagnostic::new(TILES_LOADING))
+            .register_diagnostic(Diagnostic::new(TILES_LOADED))
+            .register_diagnostic(Diagnostic::new(TILES_FAILED))
+            .register_diagnostic(Diagnostic::new(VIEW_DISTANCE).with_suffix(" m"))
+            .add_systems(
+                Update,
                 (
-                    // After recomputing the view-distance from the FPS
-                    recompute_view_distance,
                     (
-                        // Hide tiles that are now beyond the view-distance
-                        get_main_camera_index.pipe(TileMap::hide_faraway_tiles),
-                        // And load tiles that are now within the view-distance
-                        get_main_camera_index
-                            .pipe(TileMap::load_next)
-                            .pipe(TileMap::load),
-                    ),
-                )
-                    .chain(),
-                TileMap::update,
-            ),
-        );
+                        // After recomputing the view-distance from the FPS
+                        recompute_view_distance,
+                        (
+                            // Hide tiles that are now beyond the view-distance
+                            get_main_camera_index.pipe(TileMap::hide_faraway_tiles),
+                            // And load tiles that are now within the view-distance
+                            get_main_camera_index
+                                .pipe(TileMap::load_next)
+                                .pipe(TileMap::load),
+                        ),
+                    )
+                        .chain(),
+                    TileMap::update,
+                    get_main_camera_index.pipe(update_tile_stats),
+                ),
+            );
     }
 }
 
@@ -285,29 +268,38 @@ fn recompute_view_distance(
 ) {
     if let Some(fps) = diagnostics.get(&FrameTimeDiagnosticsPlugin::FPS) {
         if let Some(fps) = fps.smoothed() {
-            if fps < 40.0 {
-                view_distance.0 *= 0.99;
-            } else if fps > 59.5 {
-                view_distance.0 *= 1.01;
-            }
-            view_distance.0 = view_distance.0.clamp(2000.0, 10000.0);
+            view_distance.0 = adapt_view_distance(view_distance.0, fps);
+        }
+    }
+}
+
+/// `counts` are the loaded and the failed tiles so far
+fn update_tile_stats(
+    In(origin): In<ViewOrigin>,
+    tilemap: Res<TileMap>,
+    loading: Query<(), With<Loading>>,
+    view_distance: Res<ViewDistance>,
+    mut events: EventReader<TileEvent>,
+    mut counts: Local<(u64, u64)>,
+    mut diagnostics: Diagnostics,
+) {
+    for event in events.read() {
+        match event {
+            TileEvent::Loaded(_) => counts.0 += 1,
+            TileEvent::Failed(_) => counts.1 += 1,
         }
     }
+    let (loaded, failed) = *counts;
+    diagnostics.add_measurement(&TILES_QUEUED, || tilemap.schedule.queued(&origin) as f64);
+    diagnostics.add_measurement(&TILES_LOADING, || loading.iter().count() as f64);
+    diagnostics.add_measurement(&TILES_LOADED, || loaded as f64);
+    diagnostics.add_measurement(&TILES_FAILED, || failed as f64);
+    diagnostics.add_measurement(&VIEW_DISTANCE, || view_distance.0 as f64);
 }
 
 fn get_main_camera_index(
     player: crate::player::PlayerQuery,
     view_distance: Res<ViewDistance>,
-) -> (TileIndex, f32) {
-    let player = player.pos();
-
-    let pos = player.pos();
-    let origin = GeoCoord::from_cartesian(pos);
-    let tile_size = origin.tile_size(TILE_ZOOM);
-    let radius = view_distance.0 + tile_size + 0.5;
-    //info!(?origin); //ü0
-    let origin = origin.to_tile_coordinates(TILE_ZOOM);
-    //info!(?origin); //ü0
-
-    (origin.as_tile_index(), radius)
+) -> ViewOrigin {
+    ViewOrigin::new(player.pos().pos(), view_distance.0)
 }
@@ -0,0 +1,327 @@
+//! Camera tours: A sequence of [`GeoView`] keyframes, flown one after the other.
+//!
+//! Keys:
+//! * `K` records the actual camera view as next keyframe (`Shift-K` starts a new tour)
+//! * `P` plays or stops the tour
+//! * `L` toggles looping
+//!
+//! With the argument `tour=<file.json>` the tour is loaded from (and recorded to) that file
+//! and starts playing. Together with `loop=true` this is the kiosk or presentation mode.
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+use crate::flyto::{Easing, Flight, FlightPath};
+use crate::geoview::GeoView;
+use crate::player::{ControlValues, PlayerQuery};
+
+/// The actual version of the tour file format
+pub const TOUR_VERSION: u32 = 1;
+
+/// One stop of a tour
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Keyframe {
+    pub view: GeoView,
+    /// Seconds to fly from the previous keyframe to this one
+    pub duration: f32,
+    #[serde(default)]
+    pub easing: Easing,
+    /// Seconds to stay at this view before flying on
+    #[serde(default)]
+    pub pause: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct Tour {
+    pub version: u32,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub looping: bool,
+    pub keyframes: Vec<Keyframe>,
+}
+
+impl Tour {
+    pub fn new() -> Self {
+        Self {
+            version: TOUR_VERSION,
+            ..default()
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load(path: &std::path::Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|err| err.to_string())?;
+        let tour: Tour = serde_json::from_str(&text).map_err(|err| err.to_string())?;
+        if tour.version > TOUR_VERSION {
+            return Err(format!("tour version {} is not supported", tour.version));
+        }
+        Ok(tour)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save(&self, path: &std::path::Path) -> Result<(), String> {
+        let text = serde_json::to_string_pretty(self).map_err(|err| err.to_string())?;
+        std::fs::write(path, text).map_err(|err| err.to_string())
+    }
+}
+
+/// How the camera gets to the next keyframe
+enum Step {
+    /// The tour starts there
+    Show(GeoView),
+    Fly(FlightPath),
+}
+
+/// The tour to record or play
+#[derive(Resource, Default)]
+pub struct TourPlayer {
+    pub tour: Tour,
+    /// File to load from and record to
+    pub path: Option<PathBuf>,
+    /// Index of the keyframe flown to, if playing
+    pub playing: Option<usize>,
+    /// Seconds to wait before the next keyframe
+    pub pause: f32,
+}
+
+impl TourPlayer {
+    pub fn play(&mut self) {
+        if self.tour.keyframes.is_empty() {
+            warn!("the tour has no keyframes");
+            return;
+        }
+        self.playing = Some(0);
+        self.pause = 0.;
+    }
+
+    pub fn stop(&mut self) {
+        self.playing = None;
+    }
+
+    /// Add a keyframe at the end of the tour. The flight time is the distance dependent
+    /// default time of the fly-to animation.
+    pub fn record(&mut self, view: GeoView) {
+        let duration = match self.tour.keyframes.last() {
+            Some(last) => FlightPath::new(last.view, view, None, Easing::default()).duration,
+            None => 0.,
+        };
+        self.tour.keyframes.push(Keyframe {
+            view,
+            duration,
+            easing: Easing::default(),
+            pause: 0.,
+        });
+        info!("tour keyframe {} recorded", self.tour.keyframes.len());
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(path) = &self.path {
+            if let Err(err) = self.tour.save(path) {
+                error!("could not save the tour to {path:?}: {err}");
+            }
+        }
+    }
+
+    /// Go on to the next keyframe, `None` at the end of the tour
+    fn next_step(&mut self) -> Option<Step> {
+        let index = self.playing?;
+        let keyframes = &self.tour.keyframes;
+        let (index, wrapped) = if index < keyframes.len() {
+            (index, false)
+        } else if self.tour.looping && !keyframes.is_empty() {
+            (0, true)
+        } else {
+            self.stop();
+            return None;
+        };
+        let keyframe = keyframes[index].clone();
+        let step = if index == 0 && !wrapped {
+            Step::Show(keyframe.view)
+        } else {
+            // Looping flies from the last keyframe back to the first. The first one has no
+            // recorded flight time, so it is the default one, unless the tour file sets one.
+            let start = keyframes[index.checked_sub(1).unwrap_or(keyframes.len() - 1)].view;
+            let duration = (index > 0 || keyframe.duration > 0.).then_some(keyframe.duration);
+            Step::Fly(FlightPath::new(
+                start,
+                keyframe.view,
+                duration,
+                keyframe.easing,
+            ))
+        };
+        self.pause = keyframe.pause;
+        self.playing = Some(index + 1);
+        Some(step)
+    }
+}
+
+fn keys_tour(
+    keys: Res<ButtonInput<KeyCode>>,
+    player: PlayerQuery,
+    control_values: Res<ControlValues>,
+    mut tour_player: ResMut<TourPlayer>,
+) {
+    if keys.just_pressed(KeyCode::KeyK) {
+        if keys.pressed(KeyCode::ShiftLeft) || keys.pressed(KeyCode::ShiftRight) {
+            tour_player.stop();
+            tour_player.tour.keyframes.clear();
+        }
+        tour_player.record(GeoView::from_control(&player, &control_values));
+    }
+    if keys.just_pressed(KeyCode::KeyP) {
+        if tour_player.playing.is_some() {
+            tour_player.stop();
+        } else {
+            tour_player.play();
+        }
+    }
+    if keys.just_pressed(KeyCode::KeyL) {
+        tour_player.tour.looping = !tour_player.tour.looping;
+        info!("tour looping: {}", tour_player.tour.looping);
+    }
+}
+
+/// Start the flight to the next keyframe, when the last one is reached
+fn play_tour(
+    time: Res<Time>,
+    mut tour_player: ResMut<TourPlayer>,
+    mut flight: ResMut<Flight>,
+    mut player: PlayerQuery,
+    mut control_values: ResMut<ControlValues>,
+) {
+    let Some(index) = tour_player.playing else {
+        return;
+    };
+    if index == 0 {
+        // A new start, forget flights cancelled before
+        flight.cancelled = false;
+    }
+    if flight.cancelled {
+        // The user took over
+        tour_player.stop();
+        return;
+    }
+    if flight.active.is_some() {
+        return;
+    }
+    if tour_player.pause > 0. {
+        tour_player.pause -= time.delta_seconds();
+        return;
+    }
+
+    match tour_player.next_step() {
+        Some(Step::Show(view)) => view.set_camera_view(&mut player, &mut control_values),
+        Some(Step::Fly(path)) => flight.start(path),
+        None => {}
+    }
+}
+
+fn setup(starting_values: Res<crate::StartingValues>, mut tour_player: ResMut<TourPlayer>) {
+    tour_player.tour = Tour::new();
+    tour_player.tour.looping = starting_values.tour_loop;
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(path) = &starting_values.tour {
+        let path = PathBuf::from(path);
+        if path.exists() {
+            match Tour::load(&path) {
+                Ok(tour) => {
+                    tour_player.tour = Tour {
+                        looping: tour.looping || starting_values.tour_loop,
+                        ..tour
+                    };
+                    tour_player.play();
+                }
+                Err(err) => error!("could not load the tour {path:?}: {err}"),
+            }
+        }
+        tour_player.path = Some(path);
+    }
+}
+
+pub struct Plugin;
+
+impl bevy::prelude::Plugin for Plugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<TourPlayer>()
+            .add_systems(PostStartup, setup)
+            .add_systems(Update, (keys_tour, play_tour).chain());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geocoord::GeoCoord;
+
+    fn view(lat: f32, lon: f32) -> GeoView {
+        GeoView {
+            geo_coord: GeoCoord { lat, lon },
+            ..default()
+        }
+    }
+
+    fn flight(step: Option<Step>) -> FlightPath {
+        match step {
+            Some(Step::Fly(path)) => path,
+            _ => panic!("no flight"),
+        }
+    }
+
+    #[test]
+    fn record() {
+        let mut tour_player = TourPlayer::default();
+        tour_player.record(view(50., 8.));
+        tour_player.record(view(50., 9.));
+        tour_player.record(view(50., 9.));
+        let durations: Vec<f32> = tour_player
+            .tour
+            .keyframes
+            .iter()
+            .map(|keyframe| keyframe.duration)
+            .collect();
+        assert_eq!(durations[0], 0.);
+        // The default flight time, by the distance
+        assert!(durations[1] > 1. && durations[1] < 8., "{durations:?}");
+        assert!((durations[2] - 1.).abs() < 0.01, "{durations:?}");
+    }
+
+    #[test]
+    fn play_tour() {
+        let mut tour_player = TourPlayer::default();
+        assert!(tour_player.next_step().is_none());
+        tour_player.play();
+        assert!(tour_player.playing.is_none());
+
+        tour_player.record(view(50., 8.));
+        tour_player.record(view(-30., 150.));
+        tour_player.tour.keyframes[1].duration = 3.;
+        tour_player.tour.keyframes[1].pause = 2.;
+        tour_player.play();
+        assert!(matches!(
+            tour_player.next_step(),
+            Some(Step::Show(view)) if view.geo_coord.lon == 8.
+        ));
+        let path = flight(tour_player.next_step());
+        assert_eq!(
+            (path.start.geo_coord.lon, path.target.geo_coord.lon),
+            (8., 150.)
+        );
+        assert_eq!((path.duration, tour_player.pause), (3., 2.));
+        assert!(tour_player.next_step().is_none());
+        assert!(tour_player.playing.is_none());
+
+        // Looping flies back to the start, in the default time of the distance
+        tour_player.tour.looping = true;
+        tour_player.play();
+        tour_player.next_step();
+        tour_player.next_step();
+        let path = flight(tour_player.next_step());
+        assert_eq!(
+            (path.start.geo_coord.lon, path.target.geo_coord.lon),
+            (150., 8.)
+        );
+        assert_eq!(path.duration, 8.);
+        assert_eq!(tour_player.playing, Some(1));
+        assert_eq!(flight(tour_player.next_step()).duration, 3.);
+    }
+}
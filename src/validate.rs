@@ -0,0 +1,341 @@
+//! Checks of downloaded files before they are cached and handed to the loaders, so a broken
+//! or malicious server can't exhaust the memory: size limits for the download and its
+//! decompression, the structure of glb files, texture dimensions and vertex counts.
+
+use serde_json::Value;
+
+/// Limits of a downloaded file
+#[derive(Debug, Clone, Copy)]
+pub struct DownloadLimits {
+    /// Bytes of the download
+    pub max_compressed: usize,
+    /// Bytes after the decompression
+    pub max_decompressed: usize,
+    /// Width and height of a texture in pixels
+    pub max_texture_size: u32,
+    /// Vertices of all meshes of a glb file
+    pub max_vertices: u64,
+}
+
+impl Default for DownloadLimits {
+    fn default() -> Self {
+        Self {
+            max_compressed: 32 * 1024 * 1024,
+            max_decompressed: 128 * 1024 * 1024,
+            max_texture_size: 8192,
+            max_vertices: 4_000_000,
+        }
+    }
+}
+
+const GLB_MAGIC: u32 = 0x4654_6C67; // "glTF"
+const CHUNK_JSON: u32 = 0x4E4F_534A;
+const CHUNK_BIN: u32 = 0x004E_4942;
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+fn u32_at(bytes: &[u8], offset: usize) -> Option<u32> {
+    let bytes = bytes.get(offset..offset.checked_add(4)?)?;
+    Some(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+/// Check a decompressed file, by the extension of its path
+pub fn validate(path: &str, bytes: &[u8], limits: &DownloadLimits) -> Result<(), String> {
+    let ext = path.rsplit('.').next().unwrap_or("").to_lowercase();
+    match ext.as_str() {
+        "glb" => validate_glb(bytes, limits),
+        "png" | "jpg" | "jpeg" => validate_image(bytes, limits),
+        _ => Ok(()),
+    }
+}
+
+fn validate_image(bytes: &[u8], limits: &DownloadLimits) -> Result<(), String> {
+    let Some((width, height)) = image_size(bytes) else {
+        // Other formats, like WebP or KTX2, are left to their loaders
+        return Ok(());
+    };
+    if width.max(height) > limits.max_texture_size {
+        return Err(format!(
+            "image of {width}x{height} pixels, at most {} allowed",
+            limits.max_texture_size
+        ));
+    }
+    Ok(())
+}
+
+/// Width and height of a PNG or JPEG image, from its header
+fn image_size(bytes: &[u8]) -> Option<(u32, u32)> {
+    if bytes.starts_with(&PNG_SIGNATURE) {
+        // The IHDR chunk comes first
+        let width = u32::from_be_bytes(bytes.get(16..20)?.try_into().ok()?);
+        let height = u32::from_be_bytes(bytes.get(20..24)?.try_into().ok()?);
+        return Some((width, height));
+    }
+    if !bytes.starts_with(&[0xff, 0xd8]) {
+        return None;
+    }
+    // Walk the JPEG segments up to the frame header
+    let mut offset = 2;
+    loop {
+        let [0xff, marker] = *bytes.get(offset..offset + 2)? else {
+            return None;
+        };
+        let length = u16::from_be_bytes(bytes.get(offset + 2..offset + 4)?.try_into().ok()?);
+        // SOF0 to SOF15, without DHT, JPG and DAC
+        if (0xc0..=0xcf).contains(&marker) && !matches!(marker, 0xc4 | 0xc8 | 0xcc) {
+            let height = u16::from_be_bytes(bytes.get(offset + 5..offset + 7)?.try_into().ok()?);
+            let width = u16::from_be_bytes(bytes.get(offset + 7..offset + 9)?.try_into().ok()?);
+            return Some((width.into(), height.into()));
+        }
+        offset += 2 + length as usize;
+    }
+}
+
+fn validate_glb(bytes: &[u8], limits: &DownloadLimits) -> Result<(), String> {
+    if u32_at(bytes, 0) != Some(GLB_MAGIC) {
+        return Err("not a glb file".into());
+    }
+    let version = u32_at(bytes, 4).unwrap_or(0);
+    if version != 2 {
+        return Err(format!("glb version {version}, expected 2"));
+    }
+    if u32_at(bytes, 8) != Some(bytes.len() as u32) {
+        return Err("glb length does not match the file".into());
+    }
+    let json_length = u32_at(bytes, 12).ok_or("no glb JSON chunk")? as usize;
+    if u32_at(bytes, 16) != Some(CHUNK_JSON) {
+        return Err("the first glb chunk is not JSON".into());
+    }
+    let json = bytes
+        .get(20..20usize.saturating_add(json_length))
+        .ok_or("glb JSON chunk exceeds the file")?;
+    let json: Value = serde_json::from_slice(json).map_err(|err| format!("glb JSON: {err}"))?;
+
+    let bin_offset = 20usize.saturating_add(json_length);
+    let bin = match (
+        u32_at(bytes, bin_offset),
+        u32_at(bytes, bin_offset.saturating_add(4)),
+    ) {
+        (Some(length), Some(CHUNK_BIN)) => Some(
+            bytes
+                .get(bin_offset + 8..(bin_offset + 8).saturating_add(length as usize))
+                .ok_or("glb BIN chunk exceeds the file")?,
+        ),
+        _ => None,
+    };
+
+    let vertices = vertex_count(&json);
+    if vertices > limits.max_vertices {
+        return Err(format!(
+            "{vertices} vertices, at most {} allowed",
+            limits.max_vertices
+        ));
+    }
+
+    // Embedded textures, external ones are checked when they are downloaded
+    let images = json["images"].as_array().map(Vec::as_slice).unwrap_or(&[]);
+    for image in images {
+        let Some(view) = image["bufferView"].as_u64() else {
+            continue;
+        };
+        let view = &json["bufferViews"][view as usize];
+        let offset = view["byteOffset"].as_u64().unwrap_or(0) as usize;
+        let length = view["byteLength"].as_u64().unwrap_or(0) as usize;
+        let data = bin
+            .and_then(|bin| bin.get(offset..offset.saturating_add(length)))
+            .ok_or("glb image exceeds the BIN chunk")?;
+        validate_image(data, limits)?;
+    }
+    Ok(())
+}
+
+/// The vertices of all mesh primitives: the counts of their position accessors.
+/// Saturates instead of overflowing with bogus counts.
+fn vertex_count(json: &Value) -> u64 {
+    let meshes = json["meshes"].as_array().map(Vec::as_slice).unwrap_or(&[]);
+    meshes
+        .iter()
+        .filter_map(|mesh| mesh["primitives"].as_array())
+        .flatten()
+        .filter_map(|primitive| primitive["attributes"]["POSITION"].as_u64())
+        .map(|accessor| {
+            json["accessors"][accessor as usize]["count"]
+                .as_u64()
+                .unwrap_or(0)
+        })
+        .fold(0, u64::saturating_add)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut png = PNG_SIGNATURE.to_vec();
+        png.extend(13_u32.to_be_bytes());
+        png.extend(b"IHDR");
+        png.extend(width.to_be_bytes());
+        png.extend(height.to_be_bytes());
+        png.extend([8, 6, 0, 0, 0]);
+        png
+    }
+
+    fn jpeg(width: u16, height: u16) -> Vec<u8> {
+        let mut jpeg = vec![0xff, 0xd8];
+        // APP0, skipped
+        jpeg.extend([0xff, 0xe0, 0, 16]);
+        jpeg.extend(b"JFIF\0\x01\x01\0\0\x01\0\x01\0\0");
+        // SOF0
+        jpeg.extend([0xff, 0xc0, 0, 17, 8]);
+        jpeg.extend(height.to_be_bytes());
+        jpeg.extend(width.to_be_bytes());
+        jpeg.extend([3; 10]);
+        jpeg
+    }
+
+    fn glb(json: &Value, bin: &[u8]) -> Vec<u8> {
+        let mut json = serde_json::to_vec(json).unwrap();
+        json.resize(json.len().next_multiple_of(4), b' ');
+        let mut bin = bin.to_vec();
+        bin.resize(bin.len().next_multiple_of(4), 0);
+        let length = 12 + 8 + json.len() + 8 + bin.len();
+        let mut glb = vec![];
+        for word in [GLB_MAGIC, 2, length as u32, json.len() as u32, CHUNK_JSON] {
+            glb.extend(word.to_le_bytes());
+        }
+        glb.extend(json);
+        glb.extend((bin.len() as u32).to_le_bytes());
+        glb.extend(CHUNK_BIN.to_le_bytes());
+        glb.extend(bin);
+        glb
+    }
+
+    /// A glb with a mesh of `vertices` and an embedded image
+    fn tile(vertices: &[u64], image: &[u8]) -> Vec<u8> {
+        let primitives: Vec<Value> = (0..vertices.len())
+            .map(|accessor| json!({ "attributes": { "POSITION": accessor } }))
+            .collect();
+        let accessors: Vec<Value> = vertices
+            .iter()
+            .map(|count| json!({ "count": count }))
+            .collect();
+        let json = json!({
+            "asset": { "version": "2.0" },
+            "meshes": [{ "primitives": primitives }],
+            "accessors": accessors,
+            "images": [{ "bufferView": 0, "mimeType": "image/png" }],
+            "bufferViews": [{ "buffer": 0, "byteOffset": 0, "byteLength": image.len() }],
+        });
+        glb(&json, image)
+    }
+
+    fn check(path: &str, bytes: &[u8]) -> Result<(), String> {
+        validate(path, bytes, &DownloadLimits::default())
+    }
+
+    #[test]
+    fn valid() {
+        assert_eq!(
+            check("15_1_2.glb", &tile(&[100, 200], &png(256, 256))),
+            Ok(())
+        );
+        assert_eq!(check("15_1_2.GLB", &glb(&json!({}), &[])), Ok(()));
+        assert_eq!(check("1/2/3.png", &png(256, 256)), Ok(()));
+        assert_eq!(check("photo.jpg", &jpeg(1024, 768)), Ok(()));
+        assert_eq!(check("style.json", b"not checked"), Ok(()));
+    }
+
+    #[test]
+    fn truncated_header() {
+        let tile = tile(&[100], &png(256, 256));
+        assert!(check("tile.glb", &tile[..3]).is_err());
+        assert!(check("tile.glb", &tile[..10]).is_err());
+        assert!(check("tile.glb", &tile[..16]).is_err());
+        assert!(check("tile.glb", &[]).is_err());
+        assert!(check("tile.glb", &tile[..tile.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn magic_and_version() {
+        let mut tile = tile(&[100], &png(256, 256));
+        tile[0] = b'x';
+        assert_eq!(check("tile.glb", &tile), Err("not a glb file".into()));
+        tile[0] = b'g';
+        tile[4] = 1;
+        assert_eq!(
+            check("tile.glb", &tile),
+            Err("glb version 1, expected 2".into())
+        );
+    }
+
+    #[test]
+    fn length_mismatch() {
+        let mut tile = tile(&[100], &png(256, 256));
+        let length = tile.len() as u32 + 4;
+        tile[8..12].copy_from_slice(&length.to_le_bytes());
+        assert_eq!(
+            check("tile.glb", &tile),
+            Err("glb length does not match the file".into())
+        );
+
+        // The chunks
+        let mut tile = self::tile(&[100], &png(256, 256));
+        tile[12..16].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(
+            check("tile.glb", &tile),
+            Err("glb JSON chunk exceeds the file".into())
+        );
+        let mut tile = self::tile(&[100], &png(256, 256));
+        let bin = 20 + u32_at(&tile, 12).unwrap() as usize;
+        tile[bin..bin + 4].copy_from_slice(&1000_u32.to_le_bytes());
+        assert_eq!(
+            check("tile.glb", &tile),
+            Err("glb BIN chunk exceeds the file".into())
+        );
+    }
+
+    #[test]
+    fn oversized_textures() {
+        assert!(check("tile.glb", &tile(&[100], &png(8193, 16))).is_err());
+        assert!(check("tile.glb", &tile(&[100], &png(16, 10_000))).is_err());
+        assert!(check("tile.glb", &tile(&[100], &jpeg(9000, 100))).is_err());
+        assert_eq!(
+            check("1/2/3.png", &png(16384, 16384)),
+            Err("image of 16384x16384 pixels, at most 8192 allowed".into())
+        );
+        assert!(check("photo.jpeg", &jpeg(100, 9000)).is_err());
+        // The limit is configurable
+        let limits = DownloadLimits {
+            max_texture_size: 16384,
+            ..DownloadLimits::default()
+        };
+        assert_eq!(validate("1/2/3.png", &png(16384, 16384), &limits), Ok(()));
+    }
+
+    #[test]
+    fn unknown_image_formats() {
+        let webp = b"RIFF\x24\0\0\0WEBPVP8 ";
+        assert_eq!(check("1/2/3.png", webp), Ok(()));
+        assert_eq!(check("tile.glb", &tile(&[100], webp)), Ok(()));
+        let ktx2 = b"\xabKTX 20\xbb\r\n\x1a\n";
+        assert_eq!(check("tile.glb", &tile(&[100], ktx2)), Ok(()));
+    }
+
+    #[test]
+    fn vertices() {
+        assert_eq!(
+            check("tile.glb", &tile(&[2_000_000, 2_000_000], &[])),
+            Ok(())
+        );
+        assert_eq!(
+            check("tile.glb", &tile(&[2_000_000, 2_000_001], &[])),
+            Err("4000001 vertices, at most 4000000 allowed".into())
+        );
+        // Would overflow
+        let bogus = tile(&[u64::MAX, u64::MAX], &[]);
+        assert_eq!(
+            check("tile.glb", &bogus),
+            Err(format!("{} vertices, at most 4000000 allowed", u64::MAX))
+        );
+    }
+}
@@ -0,0 +1,172 @@
+//! JavaScript API of the web build, to embed the viewer in a page, e.g. next to a maplibre map.
+//!
+//! ```js
+//! import init, { getView, setView, flyTo, addMarker, onCamera, onTile } from './demo.js'
+//! await init()
+//! onCamera(view => map.jumpTo({ center: [view.geo_coord.lon, view.geo_coord.lat] }))
+//! flyTo({ geo_coord: { lat: 48.1372, lon: 11.5755 } }, 3)
+//! ```
+//!
+//! Views are objects like [`GeoView`]. Fields missing in `setView` or `flyTo`, also the `lat` or `lon`
+//! of `geo_coord`, keep their actual value.
+//! The calls are queued and executed in the next frame.
+
+use bevy::prelude::*;
+use js_sys::{Function, JSON};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::cell::{Cell, RefCell};
+use std::thread::LocalKey;
+use wasm_bindgen::prelude::*;
+
+use crate::flyto::FlyToRequest;
+use crate::geocoord::GeoCoord;
+use crate::geoview::GeoView;
+use crate::marker::{AddMarker, Marker};
+use crate::player::{ControlValues, PlayerQuery};
+use crate::tilemap::TileEvent;
+
+enum Command {
+    SetView(GeoView),
+    FlyTo(FlyToRequest),
+    AddMarker(Marker),
+}
+
+thread_local! {
+    // Bevy runs in the main thread of the browser, as the JavaScript does
+    static COMMANDS: RefCell<Vec<Command>> = RefCell::default();
+    static VIEW: Cell<Option<GeoView>> = Cell::default();
+    static CAMERA_LISTENERS: RefCell<Vec<Function>> = RefCell::default();
+    static TILE_LISTENERS: RefCell<Vec<Function>> = RefCell::default();
+}
+
+fn to_js(value: &impl Serialize) -> JsValue {
+    serde_json::to_string(value)
+        .ok()
+        .and_then(|text| JSON::parse(&text).ok())
+        .unwrap_or(JsValue::NULL)
+}
+
+/// The actual view with the fields of the JavaScript object `update` replaced
+fn merge_view(update: &JsValue) -> Result<GeoView, JsError> {
+    let text: String = JSON::stringify(update)
+        .map_err(|_| JsError::new("the view is no JSON object"))?
+        .into();
+    let Value::Object(update) = serde_json::from_str::<Value>(&text)? else {
+        return Err(JsError::new("the view must be an object"));
+    };
+    let actual = VIEW.with(|view| view.get()).unwrap_or_default();
+    let mut view = actual.merged(Value::Object(update))?;
+    view.limit();
+    Ok(view)
+}
+
+fn queue(command: Command) {
+    COMMANDS.with(|commands| commands.borrow_mut().push(command));
+}
+
+/// The actual camera view, or `null` before the first frame
+#[wasm_bindgen(js_name = getView)]
+pub fn get_view() -> JsValue {
+    VIEW.with(|view| view.get())
+        .map(|view| to_js(&view))
+        .unwrap_or(JsValue::NULL)
+}
+
+/// Jump to a view
+#[wasm_bindgen(js_name = setView)]
+pub fn set_view(view: JsValue) -> Result<(), JsError> {
+    queue(Command::SetView(merge_view(&view)?));
+    Ok(())
+}
+
+/// Fly to a view. Without `duration` (seconds), the flight time depends on the distance.
+#[wasm_bindgen(js_name = flyTo)]
+pub fn fly_to(view: JsValue, duration: Option<f32>) -> Result<(), JsError> {
+    queue(Command::FlyTo(FlyToRequest {
+        duration,
+        ..FlyToRequest::new(merge_view(&view)?)
+    }));
+    Ok(())
+}
+
+#[wasm_bindgen(js_name = addMarker)]
+pub fn add_marker(lat: f32, lon: f32, label: Option<String>) {
+    queue(Command::AddMarker(Marker {
+        geo_coord: GeoCoord { lat, lon },
+        label: label.unwrap_or_default(),
+        elevation: 0.,
+    }));
+}
+
+/// Call `callback(view)` whenever the camera moved
+#[wasm_bindgen(js_name = onCamera)]
+pub fn on_camera(callback: Function) {
+    CAMERA_LISTENERS.with(|listeners| listeners.borrow_mut().push(callback));
+}
+
+/// Call `callback({ event: "loaded" | "failed", zoom, x, y })` whenever a tile finished loading
+#[wasm_bindgen(js_name = onTile)]
+pub fn on_tile(callback: Function) {
+    TILE_LISTENERS.with(|listeners| listeners.borrow_mut().push(callback));
+}
+
+fn call_listeners(listeners: &'static LocalKey<RefCell<Vec<Function>>>, arg: JsValue) {
+    // Cloned, so a listener may add listeners
+    let listeners = listeners.with(|listeners| listeners.borrow().clone());
+    for listener in listeners {
+        if let Err(err) = listener.call1(&JsValue::NULL, &arg) {
+            error!("JavaScript listener failed: {err:?}");
+        }
+    }
+}
+
+fn run_commands(
+    mut player: PlayerQuery,
+    mut control_values: ResMut<ControlValues>,
+    mut fly_to: EventWriter<FlyToRequest>,
+    mut markers: EventWriter<AddMarker>,
+) {
+    let commands = COMMANDS.with(|commands| commands.take());
+    for command in commands {
+        match command {
+            Command::SetView(view) => view.set_camera_view(&mut player, &mut control_values),
+            Command::FlyTo(request) => {
+                fly_to.send(request);
+            }
+            Command::AddMarker(marker) => {
+                markers.send(AddMarker(marker));
+            }
+        }
+    }
+}
+
+fn publish_camera(player: PlayerQuery, control_values: Res<ControlValues>) {
+    let view = GeoView::from_control(&player, &control_values);
+    if VIEW.with(|last| last.replace(Some(view))) == Some(view) {
+        return;
+    }
+    call_listeners(&CAMERA_LISTENERS, to_js(&view));
+}
+
+fn publish_tiles(mut events: EventReader<TileEvent>) {
+    for event in events.read() {
+        let (name, tile) = match *event {
+            TileEvent::Loaded(tile) => ("loaded", tile),
+            TileEvent::Failed(tile) => ("failed", tile),
+        };
+        let arg = json!({ "event": name, "zoom": tile.zoom(), "x": tile.x, "y": tile.y });
+        call_listeners(&TILE_LISTENERS, to_js(&arg));
+    }
+}
+
+pub struct Plugin;
+
+impl bevy::prelude::Plugin for Plugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (run_commands, publish_camera, publish_tiles).chain(),
+        );
+    }
+}